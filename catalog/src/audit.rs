@@ -0,0 +1,86 @@
+use actor::prelude::*;
+use gantry_protocol as protocol;
+use protocol::audit::*;
+use protocol::catalog::{CatalogQueryResult, Token, TokenChange};
+use crate::catalog;
+
+const AUDIT_LOG_KEY: &str = "gantry:audit";
+
+/// Records the outcome of a token operation. The record is written whether or not the
+/// operation succeeded, and a failure to write it is logged rather than masking the
+/// operation's own result. Whoever published the token is unknown: its issuer signed it,
+/// but anyone may put a signed token, so no caller is recorded
+pub(crate) fn record_token_op(
+    ctx: &CapabilitiesContext,
+    operation: AuditOperation,
+    token: &Token,
+    outcome: &Result<CatalogQueryResult, Box<dyn std::error::Error>>,
+) {
+    let claims: serde_json::Value =
+        serde_json::from_str(&token.decoded_token_json).unwrap_or(serde_json::Value::Null);
+    let record = AuditRecord {
+        sequence: 0,
+        timestamp: token.received_at,
+        operation,
+        subject: claims["sub"].as_str().unwrap_or("??").to_string(),
+        revision: claims["sub"].as_str().map(|_| catalog::revision(&claims)),
+        caller: None,
+        success: outcome.is_ok(),
+        error: outcome.as_ref().err().map(|e| e.to_string()),
+    };
+    if let Err(e) = append(ctx, &record) {
+        ctx.log(&format!("Failed to write audit record {:?}: {}", record, e));
+    }
+}
+
+/// Records the outcome of a change to a subject already in the catalog, attributing it
+/// to the key that signed the change's proof
+pub(crate) fn record_change_op(
+    ctx: &CapabilitiesContext,
+    operation: AuditOperation,
+    change: &TokenChange,
+    outcome: &Result<CatalogQueryResult, Box<dyn std::error::Error>>,
+) {
+    let record = AuditRecord {
+        sequence: 0,
+        timestamp: change.received_at,
+        operation,
+        subject: change.subject.to_string(),
+        revision: change.revision,
+        caller: change.signer.clone(),
+        success: outcome.is_ok(),
+        error: outcome.as_ref().err().map(|e| e.to_string()),
+    };
+    if let Err(e) = append(ctx, &record) {
+        ctx.log(&format!("Failed to write audit record {:?}: {}", record, e));
+    }
+}
+
+/// Appends a record to the end of the audit log. Records are never rewritten
+pub(crate) fn append(
+    ctx: &CapabilitiesContext,
+    record: &AuditRecord,
+) -> Result<(), Box<dyn std::error::Error>> {
+    ctx.kv().list_add(AUDIT_LOG_KEY, &serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+pub(crate) fn query(
+    ctx: &CapabilitiesContext,
+    query: &AuditQuery,
+) -> Result<AuditLog, Box<dyn std::error::Error>> {
+    ctx.log(&format!("Querying audit log: {:?}", query));
+    let mut records = Vec::new();
+    for (i, raw) in ctx.kv().list_range(AUDIT_LOG_KEY, 0, -1)?.iter().enumerate() {
+        let mut record: AuditRecord = serde_json::from_str(raw)?;
+        record.sequence = i as u64 + 1;
+        if query.matches(&record) {
+            records.push(record);
+        }
+    }
+    if let Some(limit) = query.limit {
+        let skip = records.len().saturating_sub(limit as usize);
+        records.drain(..skip);
+    }
+    Ok(AuditLog { records })
+}
@@ -1,16 +1,297 @@
 use actor::prelude::*;
 use gantry_protocol as protocol;
 use protocol::catalog::*;
+use protocol::events::{CatalogEvent, EventKind};
 use protocol::token::TokenType;
+use protocol::ContentType;
+use crate::trust;
 
 pub(crate) fn put_token(
     ctx: &CapabilitiesContext,
     token: &Token,
+    ct: ContentType,
 ) -> Result<CatalogQueryResult, Box<dyn std::error::Error>> {
     ctx.log(&format!("Request to put token: {:?}", token));
     let claims: serde_json::Value = serde_json::from_str(&token.decoded_token_json)?;
-    let subject = claims["sub"].as_str().unwrap();
-    write_token(ctx, subject, token, &claims)
+    let subject = claims["sub"].as_str().ok_or("Token has no subject")?;
+    let issuer = claims["iss"].as_str().ok_or("Token has no issuer")?;
+    if is_revoked(ctx, subject)? {
+        return Err(format!("Cannot store token - {} has been revoked", subject).into());
+    }
+    let operator = resolve_operator(ctx, subject, issuer)?;
+    let result = write_token(ctx, subject, token, &claims, &operator)?;
+    if let TokenType::Operator = token_type(subject) {
+        trust::operator_token_stored(ctx, subject, &claims)?;
+    }
+    publish_event(
+        ctx,
+        CatalogEvent {
+            kind: EventKind::TokenAdded,
+            subject: subject.to_string(),
+            issuer: Some(issuer.to_string()),
+            revision: Some(revision(&claims)),
+            operator: Some(operator),
+            total_bytes: None,
+        },
+        ct,
+    );
+    Ok(result)
+}
+
+/// Marks the subject as revoked and removes it from catalog listings. The stored tokens
+/// are retained so that trust chain verification can report the revocation, and so that
+/// the subject can be reinstated
+pub(crate) fn delete_token(
+    ctx: &CapabilitiesContext,
+    change: &TokenChange,
+    ct: ContentType,
+) -> Result<CatalogQueryResult, Box<dyn std::error::Error>> {
+    ctx.log(&format!("Request to delete token: {:?}", change));
+    let subject = change.subject.as_str();
+    let rev = latest_revision(ctx, subject)?
+        .ok_or_else(|| format!("{} is not registered in the catalog", subject))?;
+    let claims = authorize_change(ctx, change, rev)?;
+
+    ctx.kv().set(&revoked_key(subject), "true", None)?;
+    ctx.kv().set_remove(list_key(subject), subject)?;
+
+    let result = change_result(ctx, subject, &claims)?;
+    publish_event(
+        ctx,
+        CatalogEvent {
+            kind: EventKind::TokenRevoked,
+            subject: result.subject.to_string(),
+            issuer: Some(result.issuer.to_string()),
+            revision: Some(rev),
+            operator: result.operator.clone(),
+            total_bytes: None,
+        },
+        ct,
+    );
+    Ok(result)
+}
+
+/// Lifts the revocation of a subject, listing it in the catalog again with the revisions
+/// it had when it was revoked
+pub(crate) fn reinstate_token(
+    ctx: &CapabilitiesContext,
+    change: &TokenChange,
+    ct: ContentType,
+) -> Result<CatalogQueryResult, Box<dyn std::error::Error>> {
+    ctx.log(&format!("Request to reinstate token: {:?}", change));
+    let subject = change.subject.as_str();
+    if !is_revoked(ctx, subject)? {
+        return Err(format!("{} has not been revoked", subject).into());
+    }
+    let rev = latest_revision(ctx, subject)?
+        .ok_or_else(|| format!("{} is not registered in the catalog", subject))?;
+    let claims = authorize_change(ctx, change, rev)?;
+
+    ctx.kv().del_key(&revoked_key(subject))?;
+    ctx.kv().set_add(list_key(subject), subject)?;
+
+    let result = change_result(ctx, subject, &claims)?;
+    publish_event(
+        ctx,
+        CatalogEvent {
+            kind: EventKind::TokenReinstated,
+            subject: result.subject.to_string(),
+            issuer: Some(result.issuer.to_string()),
+            revision: Some(rev),
+            operator: result.operator.clone(),
+            total_bytes: None,
+        },
+        ct,
+    );
+    Ok(result)
+}
+
+/// Checks that a change was proven by a key with authority over the given revision of its
+/// subject: the revision's issuer, the trusted operator at the root of the subject's trust
+/// chain, or one of that operator's signers. Returns the revision's stored claims
+fn authorize_change(
+    ctx: &CapabilitiesContext,
+    change: &TokenChange,
+    revision: u64,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let subject = change.subject.as_str();
+    let signer = change.signer.as_ref().ok_or_else(|| {
+        format!(
+            "The change to {} is not proven by a valid, short-lived JWT naming the change",
+            subject
+        )
+    })?;
+    let (claims, _) = stored_token(ctx, subject, revision)?
+        .ok_or_else(|| format!("Revision {} of {} is not stored", revision, subject))?;
+    let operator = operator_of(ctx, subject)?;
+    let authorized = claims["iss"].as_str() == Some(signer.as_str())
+        || operator.as_ref() == Some(signer)
+        || (operator.is_some() && trust::operator_for_issuer(ctx, signer)? == operator);
+    if authorized {
+        Ok(claims)
+    } else {
+        Err(format!("{} has no authority to change {}", signer, subject).into())
+    }
+}
+
+fn change_result(
+    ctx: &CapabilitiesContext,
+    subject: &str,
+    claims: &serde_json::Value,
+) -> Result<CatalogQueryResult, Box<dyn std::error::Error>> {
+    Ok(CatalogQueryResult {
+        subject: subject.to_string(),
+        issuer: claims["iss"].as_str().unwrap_or("??").to_string(),
+        name: claims["wascap"]["name"]
+            .as_str()
+            .unwrap_or("Anonymous")
+            .to_string(),
+        actor: None,
+        operator: operator_of(ctx, subject)?,
+    })
+}
+
+/// Announces a change to the catalog, encoded in the content type of the request that
+/// made it. A failure to publish is logged rather than failing the operation, since the
+/// change itself has already been stored
+fn publish_event(ctx: &CapabilitiesContext, event: CatalogEvent, ct: ContentType) {
+    if let Err(e) = try_publish_event(ctx, &event, ct) {
+        ctx.log(&format!("Failed to publish {:?} event: {}", event.kind, e));
+    }
+}
+
+fn try_publish_event(
+    ctx: &CapabilitiesContext,
+    event: &CatalogEvent,
+    ct: ContentType,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let buf = protocol::encode(ct, event)?;
+    ctx.msg().publish(event.kind.subject(), None, &buf)?;
+    Ok(())
+}
+
+/// Removes the revision of a subject named by the change, without revoking the subject.
+/// Once no revisions remain, the subject is no longer listed. Used to roll back a
+/// registration whose module could not be uploaded
+pub(crate) fn unregister_token(
+    ctx: &CapabilitiesContext,
+    change: &TokenChange,
+) -> Result<CatalogQueryResult, Box<dyn std::error::Error>> {
+    ctx.log(&format!("Request to unregister token: {:?}", change));
+    let subject = change.subject.as_str();
+    let rev = change
+        .revision
+        .ok_or("Cannot unregister token - the change does not name a revision")?;
+    let claims = authorize_change(ctx, change, rev)?;
+    let result = change_result(ctx, subject, &claims)?;
+
+    ctx.kv().del_key(&token_key(subject, rev))?;
+    ctx.kv().del_key(&token_raw_key(subject, rev))?;
+    ctx.kv().del_key(&validation_key(subject, rev))?;
+    ctx.kv().set_remove(&revisions_key(subject), &format!("{}", rev))?;
+    if latest_revision(ctx, subject)?.is_none() {
+        ctx.kv().set_remove(list_key(subject), subject)?;
+        ctx.kv().del_key(&operator_key(subject))?;
+    }
+
+    Ok(result)
+}
+
+/// Indicates whether the subject has been revoked (deleted) from the catalog
+pub(crate) fn is_revoked(
+    ctx: &CapabilitiesContext,
+    subject: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    Ok(ctx.kv().exists(&revoked_key(subject))?)
+}
+
+/// Retrieves the highest revision stored for the subject, if any
+pub(crate) fn latest_revision(
+    ctx: &CapabilitiesContext,
+    subject: &str,
+) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+    Ok(ctx
+        .kv()
+        .set_members(&revisions_key(subject))?
+        .iter()
+        .filter_map(|r| r.parse::<u64>().ok())
+        .max())
+}
+
+/// Reports the status of a single subject, reading only that subject's keys so that the
+/// streams actor can authorize transfers without listing the catalog
+pub(crate) fn subject_status(
+    ctx: &CapabilitiesContext,
+    req: &SubjectStatusRequest,
+) -> Result<SubjectStatus, Box<dyn std::error::Error>> {
+    let latest_revision = latest_revision(ctx, &req.subject)?;
+    let registered = match req.revision {
+        Some(rev) => ctx.kv().exists(&token_key(&req.subject, rev))?,
+        None => latest_revision.is_some(),
+    };
+    Ok(SubjectStatus {
+        subject: req.subject.to_string(),
+        registered,
+        latest_revision,
+        revoked: is_revoked(ctx, &req.subject)?,
+        operator: operator_of(ctx, &req.subject)?,
+    })
+}
+
+/// Retrieves the decoded claims and the validation result recorded when the given
+/// revision of the subject's token was put into the catalog
+pub(crate) fn stored_token(
+    ctx: &CapabilitiesContext,
+    subject: &str,
+    revision: u64,
+) -> Result<Option<(serde_json::Value, Option<TokenValidation>)>, Box<dyn std::error::Error>> {
+    let claims = match ctx.kv().get(&token_key(subject, revision))? {
+        Some(raw) => serde_json::from_str(&raw)?,
+        None => return Ok(None),
+    };
+    let validation = match ctx.kv().get(&validation_key(subject, revision))? {
+        Some(raw) => Some(serde_json::from_str(&raw)?),
+        None => None,
+    };
+    Ok(Some((claims, validation)))
+}
+
+/// Retrieves the trusted operator at the root of the given subject's trust chain, if the
+/// subject has been stored in the catalog
+pub(crate) fn operator_of(
+    ctx: &CapabilitiesContext,
+    subject: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    Ok(ctx.kv().get(&operator_key(subject))?)
+}
+
+/// Determines the trusted operator to which a token being put belongs. Operators must be
+/// trusted and self-signed, accounts must be issued by a trusted operator or one of its
+/// signers, and actors must be issued by an account that is already in the catalog
+fn resolve_operator(
+    ctx: &CapabilitiesContext,
+    subject: &str,
+    issuer: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    match token_type(subject) {
+        TokenType::Operator => {
+            // Only the operator's own key may speak for it, or anyone could name a trusted
+            // operator as the subject of a token listing their own signers
+            if issuer != subject {
+                Err(format!("Operator {} must sign its own token, not {}", subject, issuer).into())
+            } else if trust::is_trusted_operator(ctx, subject)? {
+                Ok(subject.to_string())
+            } else {
+                Err(format!("Operator {} is not trusted by this registry", subject).into())
+            }
+        }
+        TokenType::Account => trust::operator_for_issuer(ctx, issuer)?.ok_or_else(|| {
+            format!("Account {} was not issued by a trusted operator", subject).into()
+        }),
+        TokenType::Actor => operator_of(ctx, issuer)?.ok_or_else(|| {
+            format!("Issuing account {} is not registered in the catalog", issuer).into()
+        }),
+    }
 }
 
 pub(crate) fn query_catalog(
@@ -18,40 +299,44 @@ pub(crate) fn query_catalog(
     query: &CatalogQuery,
 ) -> Result<CatalogQueryResults, Box<dyn std::error::Error>> {
     ctx.log(&format!("Querying catalog: {:?}", query));
-    let set_key = {
-        match query.query_type {
-            QueryType::Actor => "gantry:actors",
-            QueryType::Operator => "gantry:operators",
-            QueryType::Account => "gantry:accounts",
-        }
-        .to_string()
-    };
-    let results_raw = ctx.kv().set_members(&set_key)?;    
+    let results_raw = ctx.kv().set_members(query_set_key(&query.query_type))?;
 
-    let items = results_raw
+    let mut items = Vec::new();
+    for r in results_raw
         .iter()
-        .filter_map(|r| {            
-            let raw = ctx.kv().get(&format!("gantry:tokens:{}:0", r)).unwrap();
-            let details: serde_json::Value = serde_json::from_str(&raw.unwrap()).unwrap();
-            let issuer = details["iss"].as_str().unwrap_or("??").to_string();
-            if let Some(ref tgt_issuer) = query.issuer {
-                if issuer == *tgt_issuer {
-                    Some(gen_result(details, issuer))
-                } else {
-                    None
-                }
-            } else {
-                Some(gen_result(details, issuer))
-            }            
-        })
-        .collect();
+        .filter(|r| query.subject.as_ref().map_or(true, |s| s == *r))
+    {
+        let rev = match latest_revision(ctx, r)? {
+            Some(rev) => rev,
+            None => continue,
+        };
+        let raw = ctx
+            .kv()
+            .get(&token_key(r, rev))?
+            .ok_or_else(|| format!("Revision {} of {} is listed but not stored", rev, r))?;
+        let details: serde_json::Value = serde_json::from_str(&raw)?;
+        let issuer = details["iss"].as_str().unwrap_or("??").to_string();
+        let operator = operator_of(ctx, r)?;
+        if query.operator.is_some() && query.operator != operator {
+            continue;
+        }
+        if query.issuer.as_ref().map_or(true, |tgt_issuer| issuer == *tgt_issuer) {
+            items.push(gen_result(details, rev, issuer, operator));
+        }
+    }
 
     Ok(CatalogQueryResults { results: items })
 }
 
-fn gen_result(details: serde_json::Value, issuer: String) -> CatalogQueryResult {
+fn gen_result(
+    details: serde_json::Value,
+    revision: u64,
+    issuer: String,
+    operator: Option<String>,
+) -> CatalogQueryResult {
     CatalogQueryResult {
-        actor: None,
+        actor: actor_summary(&details, revision),
+        operator,
         issuer,
         name: details["wascap"]["name"]
             .as_str()
@@ -61,35 +346,121 @@ fn gen_result(details: serde_json::Value, issuer: String) -> CatalogQueryResult
     }
 }
 
+/// Retrieves every stored revision of the raw tokens of the requested kind, or of the one
+/// requested subject. Revoked subjects are not listed, and so are not exported
+pub(crate) fn export_tokens(
+    ctx: &CapabilitiesContext,
+    req: &CatalogExportRequest,
+) -> Result<CatalogExport, Box<dyn std::error::Error>> {
+    ctx.log(&format!("Exporting catalog: {:?}", req));
+    let mut subjects: Vec<String> = ctx
+        .kv()
+        .set_members(query_set_key(&req.query_type))?
+        .into_iter()
+        .filter(|s| req.subject.as_ref().map_or(true, |subject| subject == s))
+        .collect();
+    subjects.sort();
+
+    let mut tokens = Vec::new();
+    for subject in subjects {
+        let mut revisions: Vec<u64> = ctx
+            .kv()
+            .set_members(&revisions_key(&subject))?
+            .iter()
+            .filter_map(|r| r.parse().ok())
+            .collect();
+        revisions.sort();
+        for revision in revisions {
+            if let Some(raw_token) = ctx.kv().get(&token_raw_key(&subject, revision))? {
+                tokens.push(ExportedToken {
+                    subject: subject.to_string(),
+                    revision,
+                    raw_token,
+                });
+            }
+        }
+    }
+
+    Ok(CatalogExport { tokens })
+}
+
+fn query_set_key(query_type: &QueryType) -> &'static str {
+    match query_type {
+        QueryType::Actor => "gantry:actors",
+        QueryType::Operator => "gantry:operators",
+        QueryType::Account => "gantry:accounts",
+    }
+}
+
+/// Summarizes the claims of an actor token, returning `None` for any other kind of token
+fn actor_summary(details: &serde_json::Value, revision: u64) -> Option<ActorSummary> {
+    let subject = details["sub"].as_str()?;
+    match token_type(subject) {
+        TokenType::Actor => {}
+        _ => return None,
+    }
+    let strings = |v: &serde_json::Value| -> Vec<String> {
+        v.as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|s| s.as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let wascap = &details["wascap"];
+    Some(ActorSummary {
+        public_key: subject.to_string(),
+        capabilities: strings(&wascap["caps"]),
+        provider: wascap["prov"].as_bool().unwrap_or(false),
+        tags: strings(&wascap["tags"]),
+        version: wascap["ver"].as_str().unwrap_or("").to_string(),
+        revision,
+        account: details["iss"].as_str().unwrap_or("??").to_string(),
+        name: wascap["name"].as_str().unwrap_or("??").to_string(),
+        module_hash: wascap["hash"].as_str().unwrap_or("").to_string(),
+    })
+}
+
 /// Places decoded token in gantry:tokens:{subject}:{revision}
 /// puts revision into gantry:actors:{subject}:revisions
 /// Puts subject into list gantry:actors, gantry:operators, or gantry:accounts depending on subject type
 /// Puts the raw (encoded) token in gantry:tokens:{subject}:{revision}:raw
+/// Puts the validation result in gantry:tokens:{subject}:{revision}:validation
+/// Puts the subject's trusted operator in gantry:tokens:{subject}:operator
 fn write_token(
     ctx: &CapabilitiesContext,
     subject: &str,
     token: &Token,
     claims: &serde_json::Value,
+    operator: &str,
 ) -> Result<CatalogQueryResult, Box<dyn ::std::error::Error>> {
-    if !token.validation_result.as_ref().unwrap().signature_valid {
+    let validation = token
+        .validation_result
+        .as_ref()
+        .ok_or("Cannot store token - it has not been validated")?;
+    if !validation.signature_valid {
         return Err("Cannot store token - invalid signature".into());
     }
-    if token.validation_result.as_ref().unwrap().expired {
+    if validation.expired {
         return Err("Cannot store token - expired".into());
     }
 
+    let rev = revision(claims);
     ctx.kv()
-        .set(&token_key(subject, claims), &token.decoded_token_json, None)?;
+        .set(&token_key(subject, rev), &token.decoded_token_json, None)?;
     ctx.kv()
-        .set(&token_raw_key(subject, claims), &token.raw_token, None)?;
+        .set(&token_raw_key(subject, rev), &token.raw_token, None)?;
+    ctx.kv().set(
+        &validation_key(subject, rev),
+        &serde_json::to_string(validation)?,
+        None,
+    )?;
     ctx.kv()
         .set_add(&revisions_key(subject), &format!("{}", revision(claims)))?;
+    ctx.kv().set(&operator_key(subject), operator, None)?;
 
-    match token_type(subject) {
-        TokenType::Actor => ctx.kv().set_add("gantry:actors", subject)?,
-        TokenType::Operator => ctx.kv().set_add("gantry:operators", subject)?,
-        TokenType::Account => ctx.kv().set_add("gantry:accounts", subject)?,
-    };
+    ctx.kv().set_add(list_key(subject), subject)?;
 
     Ok(CatalogQueryResult {
         subject: claims["sub"].as_str().unwrap_or("??").to_string(),
@@ -99,10 +470,19 @@ fn write_token(
             .unwrap_or("Anonymous")
             .to_string(),
         actor: None,
+        operator: Some(operator.to_string()),
     })
 }
 
-fn token_type(subject: &str) -> TokenType {
+fn list_key(subject: &str) -> &'static str {
+    match token_type(subject) {
+        TokenType::Actor => "gantry:actors",
+        TokenType::Operator => "gantry:operators",
+        TokenType::Account => "gantry:accounts",
+    }
+}
+
+pub(crate) fn token_type(subject: &str) -> TokenType {
     if subject.starts_with('A') {
         TokenType::Account
     } else if subject.starts_with('M') {
@@ -116,14 +496,28 @@ fn revisions_key(subject: &str) -> String {
     format!("gantry:tokens:{}:revisions", subject)
 }
 
-fn token_key(subject: &str, claims: &serde_json::Value) -> String {
-    format!("gantry:tokens:{}:{}", subject, revision(claims))
+fn operator_key(subject: &str) -> String {
+    format!("gantry:tokens:{}:operator", subject)
+}
+
+fn revoked_key(subject: &str) -> String {
+    format!("gantry:tokens:{}:revoked", subject)
+}
+
+fn token_key(subject: &str, revision: u64) -> String {
+    format!("gantry:tokens:{}:{}", subject, revision)
+}
+
+fn token_raw_key(subject: &str, revision: u64) -> String {
+    format!("gantry:tokens:{}:{}:raw", subject, revision)
 }
 
-fn token_raw_key(subject: &str, claims: &serde_json::Value) -> String {
-    format!("gantry:tokens:{}:{}:raw", subject, revision(claims))
+fn validation_key(subject: &str, revision: u64) -> String {
+    format!("gantry:tokens:{}:{}:validation", subject, revision)
 }
 
-fn revision(claims: &serde_json::Value) -> u64 {
-    claims["metadata"]["revision"].as_u64().unwrap_or(0)
+/// The revision of a token's claims. Only actors carry a revision, under `wascap.rev`, so
+/// every account and operator token is revision 0
+pub(crate) fn revision(claims: &serde_json::Value) -> u64 {
+    claims["wascap"]["rev"].as_u64().unwrap_or(0)
 }
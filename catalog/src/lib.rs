@@ -12,19 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[macro_use]
-extern crate lazy_static;
-
 extern crate wascc_actor as actor;
 use gantry_protocol as protocol;
 
 use actor::prelude::*;
-use std::sync::RwLock;
+use protocol::audit::{AuditOperation, AuditQuery, AuditRecord};
+use protocol::meta::ServerInfo;
+use protocol::ContentType;
+mod audit;
 mod catalog;
-
-lazy_static! {
-    static ref OPERATOR_SIGNERS: RwLock<Vec<String>> = RwLock::new(Vec::new());
-}
+mod modules;
+mod trust;
+mod verify;
 
 actor_handlers!{ messaging::OP_DELIVER_MESSAGE => handle_message,
                 core::OP_CONFIGURE => handle_config,
@@ -39,15 +38,14 @@ fn handle_config(
     ctx: &CapabilitiesContext,
     config: core::CapabilityConfiguration,
 ) -> ReceiveResult {    
-    let mut lock = OPERATOR_SIGNERS.write().unwrap();
-    lock.push(config.values.get("operator").unwrap().to_string());
-    for signer in config.values.get("signers").unwrap().split(',') {
-        lock.push(signer.to_string());
+    let trusted = trust::configure(ctx, &config.values)?;
+    for roots in trusted.operators {
+        ctx.log(&format!(
+            "Catalog trusts operator {} with the following valid signers: {}",
+            roots.operator,
+            roots.signers.join(",")
+        ));
     }
-    ctx.log(&format!(
-        "Catalog configured with the following valid operator signers: {}",
-        lock.join(",")
-    ));
     Ok(vec![])
 }
 
@@ -56,21 +54,94 @@ fn handle_message(
     msg:  messaging::DeliverMessage,
 ) -> ReceiveResult {    
     let subject = msg.message.subject.clone();
+    let body = msg.message.body.as_ref();
+    let reply_to = &msg.message.reply_to;
 
+    // Replies are encoded in the content type of the request
     if subject == protocol::catalog::SUBJECT_CATALOG_PUT_TOKEN {
-        let token = deserialize::<protocol::catalog::Token>(msg.message.body.as_ref())?;
+        let (token, ct) = protocol::decode::<protocol::catalog::Token>(body)?;
+        let res = catalog::put_token(ctx, &token, ct);
+        audit::record_token_op(ctx, AuditOperation::PutToken, &token, &res);
+        publish_results(ctx, reply_to, protocol::encode(ct, &res?)?)
+    } else if subject == protocol::catalog::SUBJECT_CATALOG_DELETE_TOKEN {
+        let (change, ct) = protocol::decode::<protocol::catalog::TokenChange>(body)?;
+        let res = catalog::delete_token(ctx, &change, ct);
+        audit::record_change_op(ctx, AuditOperation::RevokeToken, &change, &res);
+        publish_results(ctx, reply_to, protocol::encode(ct, &res?)?)
+    } else if subject == protocol::catalog::SUBJECT_CATALOG_REINSTATE_TOKEN {
+        let (change, ct) = protocol::decode::<protocol::catalog::TokenChange>(body)?;
+        let res = catalog::reinstate_token(ctx, &change, ct);
+        audit::record_change_op(ctx, AuditOperation::ReinstateToken, &change, &res);
+        publish_results(ctx, reply_to, protocol::encode(ct, &res?)?)
+    } else if subject == protocol::catalog::SUBJECT_CATALOG_UNREGISTER_TOKEN {
+        let (change, ct) = protocol::decode::<protocol::catalog::TokenChange>(body)?;
+        let res = catalog::unregister_token(ctx, &change);
+        audit::record_change_op(ctx, AuditOperation::UnregisterToken, &change, &res);
+        publish_results(ctx, reply_to, protocol::encode(ct, &res?)?)
+    } else if subject == protocol::catalog::SUBJECT_CATALOG_QUERY {
+        let (query, ct) = protocol::decode::<protocol::catalog::CatalogQuery>(body)?;
         publish_results(
             ctx,
-            &msg.message.reply_to,
-            serialize(catalog::put_token(ctx, &token)?)?,
+            reply_to,
+            protocol::encode(ct, &catalog::query_catalog(ctx, &query)?)?,
         )
-    } else if subject == protocol::catalog::SUBJECT_CATALOG_QUERY {
-        let query =
-            deserialize::<protocol::catalog::CatalogQuery>(msg.message.body.as_ref())?;
+    } else if subject == protocol::catalog::SUBJECT_CATALOG_STATUS {
+        let (req, ct) = protocol::decode::<protocol::catalog::SubjectStatusRequest>(body)?;
+        publish_results(
+            ctx,
+            reply_to,
+            protocol::encode(ct, &catalog::subject_status(ctx, &req)?)?,
+        )
+    } else if subject == protocol::catalog::SUBJECT_CATALOG_EXPORT {
+        let (req, ct) = protocol::decode::<protocol::catalog::CatalogExportRequest>(body)?;
+        publish_results(
+            ctx,
+            reply_to,
+            protocol::encode(ct, &catalog::export_tokens(ctx, &req)?)?,
+        )
+    } else if subject == protocol::audit::SUBJECT_AUDIT_QUERY {
+        let (query, ct) = protocol::decode::<AuditQuery>(body)?;
+        publish_results(
+            ctx,
+            reply_to,
+            protocol::encode(ct, &audit::query(ctx, &query)?)?,
+        )
+    } else if subject == protocol::audit::SUBJECT_AUDIT_APPEND {
+        let (record, _) = protocol::decode::<AuditRecord>(body)?;
+        audit::append(ctx, &record)?;
+        Ok(vec![])
+    } else if subject == protocol::stream::SUBJECT_MODULE_RECORD {
+        let info = protocol::deserialize::<protocol::stream::ModuleInfo>(body)?;
+        modules::record(ctx, info)?;
+        Ok(vec![])
+    } else if subject == protocol::stream::SUBJECT_MODULE_LOOKUP {
+        let req = protocol::deserialize::<protocol::stream::ModuleInfoRequest>(body)?;
+        let info = modules::lookup(ctx, &req.actor)?.unwrap_or(protocol::stream::ModuleInfo {
+            actor: req.actor,
+            ..Default::default()
+        });
+        Ok(protocol::serialize(&info)?)
+    } else if subject == protocol::meta::SUBJECT_META_INFO {
+        publish_results(
+            ctx,
+            reply_to,
+            protocol::encode(
+                ContentType::of(body),
+                &ServerInfo::current(env!("CARGO_PKG_VERSION")),
+            )?,
+        )
+    } else if subject == protocol::catalog::SUBJECT_CATALOG_TRUST_ROOTS {
+        publish_results(
+            ctx,
+            reply_to,
+            protocol::encode(ContentType::of(body), &trust::trust_roots(ctx)?)?,
+        )
+    } else if subject == protocol::catalog::SUBJECT_CATALOG_TRUST_VERIFY {
+        let (req, ct) = protocol::decode::<protocol::catalog::ChainVerificationRequest>(body)?;
         publish_results(
             ctx,
-            &msg.message.reply_to,
-            serialize(catalog::query_catalog(ctx, &query)?)?,
+            reply_to,
+            protocol::encode(ct, &verify::verify_chain(ctx, &req)?)?,
         )
     } else {
         Err("Unknown catalog request subject".into())
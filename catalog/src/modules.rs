@@ -0,0 +1,38 @@
+use actor::prelude::*;
+use gantry_protocol as protocol;
+use protocol::stream::ModuleInfo;
+use crate::catalog;
+
+/// Records a completed upload reported by the streams actor, attributing it to the
+/// revision of the actor's token for which the upload was authorized or, if the streams
+/// actor does not know it, the latest revision. Only the latest upload of each module is kept
+pub(crate) fn record(
+    ctx: &CapabilitiesContext,
+    mut info: ModuleInfo,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if info.revision.is_none() {
+        info.revision = catalog::latest_revision(ctx, &info.actor)?;
+    }
+    info.uploader = match info.revision {
+        Some(revision) => catalog::stored_token(ctx, &info.actor, revision)?
+            .and_then(|(claims, _)| claims["iss"].as_str().map(|s| s.to_string())),
+        None => None,
+    };
+    ctx.kv().set(&module_key(&info.actor), &serde_json::to_string(&info)?, None)?;
+    Ok(())
+}
+
+/// Retrieves the record of the latest upload of an actor's module, if one was made
+pub(crate) fn lookup(
+    ctx: &CapabilitiesContext,
+    actor: &str,
+) -> Result<Option<ModuleInfo>, Box<dyn std::error::Error>> {
+    match ctx.kv().get(&module_key(actor))? {
+        Some(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+        None => Ok(None),
+    }
+}
+
+fn module_key(actor: &str) -> String {
+    format!("gantry:modules:{}", actor)
+}
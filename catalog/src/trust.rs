@@ -0,0 +1,218 @@
+use crate::catalog;
+use actor::prelude::*;
+use gantry_protocol as protocol;
+use protocol::catalog::{TrustRoots, TrustedOperators};
+use std::collections::HashMap;
+
+const TRUSTED_OPERATORS_KEY: &str = "gantry:trust:operators";
+
+/// Applies the operator configuration supplied by the host. The configuration holds a
+/// comma-delimited `operators` list, with each operator's signers and issue time under
+/// `{operator}.signers` and `{operator}.issued_at`. The single-operator `operator` and
+/// `signers` values are still honored. Operators no longer present in the configuration
+/// stop being trusted. If the key-value store already holds roots for an operator that
+/// came from a newer JWT put into the catalog, those are kept rather than being clobbered
+/// by the (older) startup configuration
+pub(crate) fn configure(
+    ctx: &CapabilitiesContext,
+    values: &HashMap<String, String>,
+) -> Result<TrustedOperators, Box<dyn std::error::Error>> {
+    let configured = parse_config(values)?;
+
+    for existing in ctx.kv().set_members(TRUSTED_OPERATORS_KEY)? {
+        if !configured.iter().any(|r| r.operator == existing) {
+            ctx.log(&format!("Operator {} is no longer trusted", existing));
+            ctx.kv().set_remove(TRUSTED_OPERATORS_KEY, &existing)?;
+        }
+    }
+
+    let mut operators = Vec::new();
+    for roots in configured {
+        let roots = match load_roots(ctx, &roots.operator)? {
+            Some(stored) if !is_newer(&roots, &stored) => {
+                ctx.log(&format!(
+                    "Retaining persisted trust roots for operator {}",
+                    stored.operator
+                ));
+                stored
+            }
+            _ => {
+                store_roots(ctx, &roots)?;
+                roots
+            }
+        };
+        ctx.kv().set_add(TRUSTED_OPERATORS_KEY, &roots.operator)?;
+        operators.push(roots);
+    }
+
+    Ok(TrustedOperators { operators })
+}
+
+/// Retrieves the roots of every trusted operator, failing if the catalog has not been configured
+pub(crate) fn trust_roots(
+    ctx: &CapabilitiesContext,
+) -> Result<TrustedOperators, Box<dyn std::error::Error>> {
+    let mut operators = Vec::new();
+    for operator in ctx.kv().set_members(TRUSTED_OPERATORS_KEY)? {
+        if let Some(roots) = load_roots(ctx, &operator)? {
+            operators.push(roots);
+        }
+    }
+    if operators.is_empty() {
+        return Err("Catalog has not been configured with an operator".into());
+    }
+    operators.sort_by(|a, b| a.operator.cmp(&b.operator));
+    Ok(TrustedOperators { operators })
+}
+
+/// Resolves the trusted operator that the given issuer key belongs to, either because
+/// it is the operator itself or one of its valid signers
+pub(crate) fn operator_for_issuer(
+    ctx: &CapabilitiesContext,
+    issuer: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    for operator in ctx.kv().set_members(TRUSTED_OPERATORS_KEY)? {
+        if let Some(roots) = load_roots(ctx, &operator)? {
+            if roots.operator == issuer || roots.signers.iter().any(|s| s == issuer) {
+                return Ok(Some(roots.operator));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Indicates whether the given operator is one of the catalog's trusted operators
+pub(crate) fn is_trusted_operator(
+    ctx: &CapabilitiesContext,
+    operator: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    Ok(ctx
+        .kv()
+        .set_members(TRUSTED_OPERATORS_KEY)?
+        .iter()
+        .any(|o| o == operator))
+}
+
+/// Called whenever an operator token is put into the catalog. If the token is a newer,
+/// self-signed revision of a trusted operator, its valid signers replace the persisted ones
+pub(crate) fn operator_token_stored(
+    ctx: &CapabilitiesContext,
+    subject: &str,
+    claims: &serde_json::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if claims["iss"].as_str() != Some(subject) {
+        return Err(format!("Operator {} did not sign its own token", subject).into());
+    }
+    let stored = match load_roots(ctx, subject)? {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+    let candidate = TrustRoots {
+        operator: subject.to_string(),
+        revision: catalog::revision(claims),
+        issued_at: claims["iat"].as_u64().unwrap_or(0),
+        signers: normalize_signers(
+            claims["wascap"]["signing_keys"]
+                .as_array()
+                .map(|keys| {
+                    keys.iter()
+                        .filter_map(|k| k.as_str().map(|s| s.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        ),
+    };
+    if !is_newer(&candidate, &stored) {
+        return Ok(());
+    }
+    if candidate.signers != stored.signers {
+        ctx.log(&format!(
+            "Operator {} revision {} changed valid signers to: {}",
+            subject,
+            candidate.revision,
+            candidate.signers.join(",")
+        ));
+    }
+    store_roots(ctx, &candidate)
+}
+
+fn parse_config(
+    values: &HashMap<String, String>,
+) -> Result<Vec<TrustRoots>, Box<dyn std::error::Error>> {
+    if let Some(operator) = values.get("operator").filter(|o| !o.is_empty()) {
+        return Ok(vec![TrustRoots {
+            operator: operator.to_string(),
+            revision: parse_u64(values.get("revision")),
+            issued_at: parse_u64(values.get("issued_at")),
+            signers: parse_signers(values.get("signers")),
+        }]);
+    }
+
+    let operators: Vec<TrustRoots> = values
+        .get("operators")
+        .map(|ops| {
+            ops.split(',')
+                .map(|o| o.trim())
+                .filter(|o| !o.is_empty())
+                .map(|o| TrustRoots {
+                    operator: o.to_string(),
+                    revision: parse_u64(values.get(&format!("{}.revision", o))),
+                    issued_at: parse_u64(values.get(&format!("{}.issued_at", o))),
+                    signers: parse_signers(values.get(&format!("{}.signers", o))),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if operators.is_empty() {
+        Err("Catalog configuration is missing the 'operators' value".into())
+    } else {
+        Ok(operators)
+    }
+}
+
+fn is_newer(candidate: &TrustRoots, stored: &TrustRoots) -> bool {
+    (candidate.revision, candidate.issued_at) > (stored.revision, stored.issued_at)
+}
+
+fn parse_u64(raw: Option<&String>) -> u64 {
+    raw.and_then(|r| r.parse().ok()).unwrap_or(0)
+}
+
+fn parse_signers(raw: Option<&String>) -> Vec<String> {
+    normalize_signers(
+        raw.map(|r| r.split(',').map(|s| s.trim().to_string()).collect())
+            .unwrap_or_default(),
+    )
+}
+
+fn normalize_signers(mut signers: Vec<String>) -> Vec<String> {
+    signers.retain(|s| !s.is_empty());
+    signers.sort();
+    signers.dedup();
+    signers
+}
+
+fn roots_key(operator: &str) -> String {
+    format!("gantry:trust:roots:{}", operator)
+}
+
+fn load_roots(
+    ctx: &CapabilitiesContext,
+    operator: &str,
+) -> Result<Option<TrustRoots>, Box<dyn std::error::Error>> {
+    match ctx.kv().get(&roots_key(operator))? {
+        Some(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+        None => Ok(None),
+    }
+}
+
+/// The roots are written as a single value so that a reader never observes an operator
+/// paired with a partially updated set of signers
+fn store_roots(
+    ctx: &CapabilitiesContext,
+    roots: &TrustRoots,
+) -> Result<(), Box<dyn std::error::Error>> {
+    ctx.kv()
+        .set(&roots_key(&roots.operator), &serde_json::to_string(roots)?, None)?;
+    Ok(())
+}
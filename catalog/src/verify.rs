@@ -0,0 +1,193 @@
+use crate::catalog;
+use crate::trust;
+use actor::prelude::*;
+use gantry_protocol as protocol;
+use protocol::catalog::{
+    ChainLink, ChainLinkKind, ChainVerification, ChainVerificationRequest, TokenValidation,
+};
+use protocol::token::TokenType;
+
+/// Walks the trust chain from the requested subject (actor → issuing account → operator
+/// or one of its signers), checking signatures, expiry and revocation at each hop
+pub(crate) fn verify_chain(
+    ctx: &CapabilitiesContext,
+    req: &ChainVerificationRequest,
+) -> Result<ChainVerification, Box<dyn std::error::Error>> {
+    ctx.log(&format!("Verifying trust chain: {:?}", req));
+    let mut links = Vec::new();
+
+    let first = token_link(ctx, &req.subject, req.revision, req.as_of)?;
+    let mut issuer = first.issuer.clone();
+    let first_kind = first.kind.clone();
+    links.push(first);
+
+    let operator = match first_kind {
+        ChainLinkKind::Operator => {
+            if links[0].is_valid() {
+                Some(req.subject.to_string())
+            } else {
+                None
+            }
+        }
+        _ => {
+            // Actors are issued by an account, which must in turn be issued by a
+            // trusted operator or one of its signers
+            if let ChainLinkKind::Actor = first_kind {
+                if let Some(account) = issuer.take() {
+                    let mut link = token_link(ctx, &account, None, req.as_of)?;
+                    if link.kind != ChainLinkKind::Account {
+                        link.problems
+                            .push(format!("Actor issuer {} is not an account", account));
+                    }
+                    issuer = link.issuer.clone();
+                    links.push(link);
+                }
+            }
+            match issuer {
+                Some(ref iss) => root_links(ctx, iss, req.as_of, &mut links)?,
+                None => None,
+            }
+        }
+    };
+
+    if let (Some(expected), Some(actual)) = (&req.operator, &operator) {
+        if expected != actual {
+            if let Some(last) = links.last_mut() {
+                last.problems.push(format!(
+                    "Chain terminates at operator {}, not the requested operator {}",
+                    actual, expected
+                ));
+            }
+        }
+    }
+
+    Ok(ChainVerification {
+        subject: req.subject.to_string(),
+        trusted: operator.is_some() && links.iter().all(ChainLink::is_valid),
+        operator,
+        links,
+    })
+}
+
+/// Adds the links for an account's issuer, which is either a trusted operator or one of
+/// its valid signers, returning the operator at the root of the chain if there is one
+fn root_links(
+    ctx: &CapabilitiesContext,
+    issuer: &str,
+    as_of: u64,
+    links: &mut Vec<ChainLink>,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    match trust::operator_for_issuer(ctx, issuer)? {
+        Some(operator) => {
+            if operator != issuer {
+                links.push(ChainLink {
+                    issuer: Some(operator.to_string()),
+                    ..empty_link(issuer, ChainLinkKind::Signer)
+                });
+            }
+            links.push(token_link(ctx, &operator, None, as_of)?);
+            Ok(Some(operator))
+        }
+        None => {
+            let mut link = empty_link(issuer, ChainLinkKind::Signer);
+            link.problems.push(format!(
+                "{} is neither a trusted operator nor a valid signer of one",
+                issuer
+            ));
+            links.push(link);
+            Ok(None)
+        }
+    }
+}
+
+/// Builds the link for a token stored in the catalog. Operators are not required to
+/// have their token stored, but must be trusted by the registry
+fn token_link(
+    ctx: &CapabilitiesContext,
+    subject: &str,
+    revision: Option<u64>,
+    as_of: u64,
+) -> Result<ChainLink, Box<dyn std::error::Error>> {
+    let kind = match catalog::token_type(subject) {
+        TokenType::Actor => ChainLinkKind::Actor,
+        TokenType::Account => ChainLinkKind::Account,
+        TokenType::Operator => ChainLinkKind::Operator,
+    };
+    let is_operator = kind == ChainLinkKind::Operator;
+    let mut link = empty_link(subject, kind);
+    if is_operator && !trust::is_trusted_operator(ctx, subject)? {
+        link.problems
+            .push(format!("Operator {} is not trusted by this registry", subject));
+    }
+
+    let revision = match revision {
+        Some(r) => Some(r),
+        None => catalog::latest_revision(ctx, subject)?,
+    };
+    let stored = match revision {
+        Some(r) => catalog::stored_token(ctx, subject, r)?,
+        None => None,
+    };
+    match stored {
+        Some((claims, validation)) => {
+            link.revision = revision;
+            link.issuer = claims["iss"].as_str().map(|s| s.to_string());
+            assess(&mut link, &claims, validation, as_of);
+        }
+        None if is_operator && revision.is_none() => {}
+        None => {
+            link.signature_valid = false;
+            link.problems.push(match revision {
+                Some(r) => format!("Revision {} of {} is not in the catalog", r, subject),
+                None => format!("{} is not in the catalog", subject),
+            });
+        }
+    }
+
+    if catalog::is_revoked(ctx, subject)? {
+        link.revoked = true;
+        link.problems.push(format!("{} has been revoked", subject));
+    }
+    Ok(link)
+}
+
+fn assess(
+    link: &mut ChainLink,
+    claims: &serde_json::Value,
+    validation: Option<TokenValidation>,
+    as_of: u64,
+) {
+    // Tokens are only ever stored once their signature has been validated
+    link.signature_valid = validation.as_ref().map_or(true, |v| v.signature_valid);
+    if as_of > 0 {
+        link.expired = claims["exp"].as_u64().map_or(false, |exp| exp < as_of);
+        link.cannot_use_yet = claims["nbf"].as_u64().map_or(false, |nbf| nbf > as_of);
+    } else if let Some(ref v) = validation {
+        link.expired = v.expired;
+        link.cannot_use_yet = v.cannot_use_yet;
+    }
+
+    if !link.signature_valid {
+        link.problems.push("Signature is invalid".to_string());
+    }
+    if link.expired {
+        link.problems.push("Token has expired".to_string());
+    }
+    if link.cannot_use_yet {
+        link.problems.push("Token cannot be used yet".to_string());
+    }
+}
+
+fn empty_link(subject: &str, kind: ChainLinkKind) -> ChainLink {
+    ChainLink {
+        subject: subject.to_string(),
+        kind,
+        issuer: None,
+        revision: None,
+        signature_valid: true,
+        expired: false,
+        cannot_use_yet: false,
+        revoked: false,
+        problems: Vec::new(),
+    }
+}
@@ -1,59 +1,264 @@
+use crate::options::{RetryPolicy, Timeouts};
+use crate::transport::Transport;
 use gantry_protocol as protocol;
 use natsclient::{AuthenticationStyle, Client, ClientOptions};
+use protocol::audit::{AuditLog, AuditQuery};
 use protocol::catalog::*;
+use protocol::events::{CatalogEvent, EVENT_SUBJECTS};
+use protocol::meta::ServerInfo;
 use protocol::stream::*;
+use protocol::{decode, encode, encode_empty, ContentType};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use protocol::{serialize, deserialize};
 
 pub const CHUNK_SIZE: u64 = 256 * 1024; // 256KB
+pub const DOWNLOAD_WINDOW: u64 = 16; // 4MB in flight
+
+/// A transport paired with the timeouts and retry policy applied to its requests
+#[derive(Clone)]
+pub(crate) struct Connection {
+    pub(crate) transport: Arc<dyn Transport>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) content_type: ContentType,
+    /// Whether to offer to compress module transfers
+    pub(crate) compression: bool,
+    /// The most download chunks to have in flight at once, or 0 for no limit
+    pub(crate) download_window: u64,
+    /// The compression codec negotiated by the latest upload of each actor
+    pub(crate) upload_codecs: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl Connection {
+    /// Makes a request that is safe to repeat, retrying it according to the retry policy.
+    /// Only reads are made this way
+    fn request(
+        &self,
+        subject: &str,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+        let attempts = self.retry.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            match self.transport.request(subject, payload, timeout) {
+                Ok(reply) => return Ok(reply),
+                Err(e) if attempt >= attempts => return Err(e),
+                Err(e) => {
+                    attempt += 1;
+                    warn!(
+                        "Request on {} failed ({}), making attempt {} of {}",
+                        subject, e, attempt, attempts
+                    );
+                    std::thread::sleep(self.retry.delay_before(attempt));
+                }
+            }
+        }
+    }
+
+    /// Makes a request that changes the registry, or starts a transfer, exactly once. A
+    /// request whose reply is lost may still have taken effect, so repeating it could
+    /// fail spuriously or act twice
+    fn request_once(
+        &self,
+        subject: &str,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+        self.transport.request(subject, payload, timeout)
+    }
+}
 
 pub(crate) fn query(
-    client: &Client,
+    client: &Connection,
     query: &CatalogQuery,
 ) -> Result<CatalogQueryResults, Box<dyn ::std::error::Error>> {
-    let buf = serialize(&query)?;    
+    let buf = encode(client.content_type, query)?;
     let reply = client.request(
         "gantry.catalog.tokens.query",
         &buf,
-        Duration::from_millis(700),
+        client.timeouts.query,
     )?;
 
-    Ok(deserialize::<CatalogQueryResults>(reply.payload.as_ref())?)
+    Ok(decode::<CatalogQueryResults>(reply.as_ref())?.0)
 }
 
-pub(crate) fn put(client: &Client, token: &Token) -> Result<(), Box<dyn ::std::error::Error>> {
-    let buf = serialize(token)?;    
+pub(crate) fn status(
+    client: &Connection,
+    req: &SubjectStatusRequest,
+) -> Result<SubjectStatus, Box<dyn ::std::error::Error>> {
+    let buf = encode(client.content_type, req)?;
     let reply = client.request(
+        protocol::catalog::SUBJECT_CATALOG_STATUS,
+        &buf,
+        client.timeouts.query,
+    )?;
+
+    Ok(decode::<SubjectStatus>(reply.as_ref())?.0)
+}
+
+pub(crate) fn put(
+    client: &Connection,
+    token: &Token,
+) -> Result<CatalogQueryResult, Box<dyn ::std::error::Error>> {
+    let buf = encode(client.content_type, token)?;
+    let reply = client.request_once(
         "gantry.catalog.tokens.put",
         &buf,
-        Duration::from_millis(100),
+        client.timeouts.put,
     )?;
 
-    let res = deserialize::<CatalogQueryResult>(reply.payload.as_ref())?;
-    println!(
-        "Token '{}' with issuer {}, subject {} registered.",
-        res.name, res.issuer, res.subject
-    );
-    Ok(())
+    Ok(decode::<CatalogQueryResult>(reply.as_ref())?.0)
+}
+
+pub(crate) fn delete(
+    client: &Connection,
+    change: &TokenChange,
+) -> Result<CatalogQueryResult, Box<dyn ::std::error::Error>> {
+    let buf = encode(client.content_type, change)?;
+    let reply = client.request_once(
+        protocol::catalog::SUBJECT_CATALOG_DELETE_TOKEN,
+        &buf,
+        client.timeouts.put,
+    )?;
+
+    Ok(decode::<CatalogQueryResult>(reply.as_ref())?.0)
+}
+
+pub(crate) fn reinstate(
+    client: &Connection,
+    change: &TokenChange,
+) -> Result<CatalogQueryResult, Box<dyn ::std::error::Error>> {
+    let buf = encode(client.content_type, change)?;
+    let reply = client.request_once(
+        protocol::catalog::SUBJECT_CATALOG_REINSTATE_TOKEN,
+        &buf,
+        client.timeouts.put,
+    )?;
+
+    Ok(decode::<CatalogQueryResult>(reply.as_ref())?.0)
+}
+
+pub(crate) fn unregister(
+    client: &Connection,
+    change: &TokenChange,
+) -> Result<CatalogQueryResult, Box<dyn ::std::error::Error>> {
+    let buf = encode(client.content_type, change)?;
+    let reply = client.request_once(
+        protocol::catalog::SUBJECT_CATALOG_UNREGISTER_TOKEN,
+        &buf,
+        client.timeouts.put,
+    )?;
+
+    Ok(decode::<CatalogQueryResult>(reply.as_ref())?.0)
+}
+
+pub(crate) fn export(
+    client: &Connection,
+    req: &CatalogExportRequest,
+) -> Result<CatalogExport, Box<dyn ::std::error::Error>> {
+    let buf = encode(client.content_type, req)?;
+    let reply = client.request(
+        protocol::catalog::SUBJECT_CATALOG_EXPORT,
+        &buf,
+        client.timeouts.query,
+    )?;
+
+    Ok(decode::<CatalogExport>(reply.as_ref())?.0)
+}
+
+pub(crate) fn audit(
+    client: &Connection,
+    query: &AuditQuery,
+) -> Result<AuditLog, Box<dyn ::std::error::Error>> {
+    let buf = encode(client.content_type, query)?;
+    let reply = client.request(
+        protocol::audit::SUBJECT_AUDIT_QUERY,
+        &buf,
+        client.timeouts.query,
+    )?;
+
+    Ok(decode::<AuditLog>(reply.as_ref())?.0)
+}
+
+pub(crate) fn verify_chain(
+    client: &Connection,
+    req: &ChainVerificationRequest,
+) -> Result<ChainVerification, Box<dyn ::std::error::Error>> {
+    let buf = encode(client.content_type, req)?;
+    let reply = client.request(
+        protocol::catalog::SUBJECT_CATALOG_TRUST_VERIFY,
+        &buf,
+        client.timeouts.query,
+    )?;
+
+    Ok(decode::<ChainVerification>(reply.as_ref())?.0)
+}
+
+pub(crate) fn trust_roots(
+    client: &Connection,
+) -> Result<TrustedOperators, Box<dyn ::std::error::Error>> {
+    let reply = client.request(
+        protocol::catalog::SUBJECT_CATALOG_TRUST_ROOTS,
+        &encode_empty(client.content_type)?,
+        client.timeouts.query,
+    )?;
+
+    Ok(decode::<TrustedOperators>(reply.as_ref())?.0)
+}
+
+pub(crate) fn server_info(
+    client: &Connection,
+) -> Result<ServerInfo, Box<dyn ::std::error::Error>> {
+    let reply = client.request(
+        protocol::meta::SUBJECT_META_INFO,
+        &encode_empty(client.content_type)?,
+        client.timeouts.query,
+    )?;
+
+    Ok(decode::<ServerInfo>(reply.as_ref())?.0)
+}
+
+pub(crate) fn module_info(
+    client: &Connection,
+    req: &ModuleInfoRequest,
+) -> Result<ModuleInfo, Box<dyn ::std::error::Error>> {
+    let reply = client.request(
+        protocol::stream::SUBJECT_STREAM_INFO,
+        &encode(client.content_type, req)?,
+        client.timeouts.query,
+    )?;
+
+    Ok(decode::<ModuleInfo>(reply.as_ref())?.0)
 }
 
 pub(crate) fn start_upload(
-    client: &Client,
+    client: &Connection,
     req: &UploadRequest,
 ) -> Result<TransferAck, Box<dyn ::std::error::Error>> {
-    let buf = serialize(req)?;
-    
-    let res = client.request(
+    let mut req = req.clone();
+    if client.compression && req.compression.is_empty() {
+        req.compression = offered_codecs();
+    }
+    let buf = encode(client.content_type, &req)?;
+
+    let res = client.request_once(
         protocol::stream::SUBJECT_STREAM_UPLOAD,
         &buf,
-        ::std::time::Duration::from_millis(100),
+        client.timeouts.transfer_start,
     )?;
-    let tack = deserialize::<TransferAck>(res.payload.as_ref())?;
+    let (tack, _) = decode::<TransferAck>(res.as_ref())?;
+    let mut codecs = client.upload_codecs.lock().unwrap();
+    match tack.compression {
+        Some(ref codec) => codecs.insert(req.actor.to_string(), codec.to_string()),
+        None => codecs.remove(&req.actor),
+    };
     Ok(tack)
 }
 
 pub(crate) fn request_download<F>(
-    client: &Client,
+    client: &Connection,
     req: DownloadRequest,
     chunk_handler: F,
 ) -> Result<TransferAck, Box<dyn ::std::error::Error>>
@@ -61,31 +266,117 @@ where
     F: Fn(FileChunk) -> Result<(), Box<dyn ::std::error::Error>> + Sync + Send,
     F: 'static,
 {
-    let buf = serialize(&req)?;
-    
+    let mut req = req;
+    if client.compression && req.compression.is_empty() {
+        req.compression = offered_codecs();
+    }
+    if req.window == 0 {
+        req.window = client.download_window;
+    }
+    if req.transfer.is_empty() {
+        req.transfer = new_transfer();
+    }
+    let buf = encode(client.content_type, &req)?;
+
     let dltopic = format!(
         "{}{}",
         protocol::stream::SUBJECT_STREAM_DOWNLOAD_PREFIX,
-        req.actor
+        req.transfer
     );
 
-    client.subscribe(&dltopic, move |msg| {
-        let chunk = deserialize::<FileChunk>(msg.payload.as_ref()).unwrap();
-        chunk_handler(chunk).unwrap(); // TODO: get rid of unwrap
-        Ok(())
-    })?;
-
-    let res = client.request(
-        protocol::stream::SUBJECT_STREAM_DOWNLOAD,
-        &buf,
-        std::time::Duration::from_millis(100),
+    let credit_topic = format!("{}{}", SUBJECT_STREAM_CREDIT_PREFIX, req.transfer);
+    let windowed = req.window > 0;
+    let transport = client.transport.clone();
+    let content_type = client.content_type;
+    // Set once subscribed, which is before any chunk can arrive, and taken by whichever
+    // of the final chunk, a failed chunk or a failed request ends the transfer
+    let subscription = Arc::new(Mutex::new(None));
+    let sub = subscription.clone();
+    let id = client.transport.subscribe(
+        &dltopic,
+        Box::new(move |msg| {
+            let (chunk, _) = match decode::<FileChunk>(msg) {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    error!("Failed to decode downloaded chunk: {}", e);
+                    return;
+                }
+            };
+            let ack = ChunkAck {
+                success: true,
+                sequence_no: chunk.sequence_no,
+                bytes_sent: chunk.chunk_bytes.len() as u64,
+            };
+            let last = chunk.sequence_no >= chunk.total_chunks;
+            let res = decompress_chunk(chunk).and_then(|chunk| chunk_handler(chunk));
+            if let Err(ref e) = res {
+                error!("Failed to handle downloaded chunk: {}", e);
+            }
+            // Windowed downloads acknowledge every chunk to grant credit for the next, and a
+            // chunk that could not be handled abandons the download, which the server is
+            // told of so that it can let the download go
+            if windowed || res.is_err() {
+                let ack = ChunkAck {
+                    success: res.is_ok(),
+                    ..ack
+                };
+                let res = encode(content_type, &ack)
+                    .and_then(|buf| transport.publish(&credit_topic, &buf));
+                if let Err(e) = res {
+                    error!("Failed to acknowledge downloaded chunk: {}", e);
+                }
+            }
+            if last || res.is_err() {
+                end_subscription(transport.as_ref(), &sub);
+            }
+        }),
     )?;
-    let tack = deserialize::<TransferAck>(res.payload.as_ref())?;
-    Ok(tack)
+    *subscription.lock().unwrap() = Some(id);
+
+    let res = client
+        .request_once(
+            protocol::stream::SUBJECT_STREAM_DOWNLOAD,
+            &buf,
+            client.timeouts.transfer_start,
+        )
+        .and_then(|res| Ok(decode::<TransferAck>(res.as_ref())?.0));
+    match res {
+        // An empty range sends no chunks
+        Ok(ref tack) if tack.success && tack.total_chunks > 0 => {}
+        _ => end_subscription(client.transport.as_ref(), &subscription),
+    }
+    res
+}
+
+/// Unsubscribes from a download's chunks, unless its subscription has already ended
+fn end_subscription(transport: &dyn Transport, subscription: &Mutex<Option<u64>>) {
+    if let Some(id) = subscription.lock().unwrap().take() {
+        if let Err(e) = transport.unsubscribe(id) {
+            error!("Failed to unsubscribe from a finished download: {}", e);
+        }
+    }
+}
+
+pub(crate) fn watch<F>(client: &Connection, handler: F) -> Result<(), Box<dyn ::std::error::Error>>
+where
+    F: Fn(CatalogEvent) + Sync + Send + 'static,
+{
+    let handler = Arc::new(handler);
+    for subject in EVENT_SUBJECTS.iter() {
+        let handler = handler.clone();
+        client.transport.subscribe(
+            subject,
+            Box::new(move |msg| match decode::<CatalogEvent>(msg) {
+                Ok((event, _)) => handler(event),
+                Err(e) => error!("Failed to decode change event: {}", e),
+            }),
+        )?;
+    }
+    Ok(())
 }
 
 pub(crate) fn upload_chunk(
-    c: &Client,
+    c: &Connection,
     sequence_no: u64,
     actor: &str,
     chunk_size: u64,
@@ -93,24 +384,50 @@ pub(crate) fn upload_chunk(
     total_chunks: u64,
     bytes: Vec<u8>,
 ) -> Result<(), Box<dyn ::std::error::Error>> {
+    let codec = c.upload_codecs.lock().unwrap().get(actor).cloned();
+    let chunk_bytes = match codec {
+        Some(ref codec) => compress(codec, &bytes)?,
+        None => bytes,
+    };
     let chunk = protocol::stream::FileChunk {
         actor: actor.to_string(),
-        chunk_bytes: bytes,
+        chunk_bytes,
         chunk_size,
         sequence_no,
         total_bytes,
         total_chunks,
+        compression: codec,
+        offset: 0,
     };
-    let buf = serialize(&chunk)?;    
+    let buf = encode(c.content_type, &chunk)?;
     let subject = format!(
         "{}{}",
         protocol::stream::SUBJECT_STREAM_UPLOAD_PREFIX,
         actor
     );
-    let _res = c.request(&subject, &buf, std::time::Duration::from_millis(2000))?;
+    let _res = c.request_once(&subject, &buf, c.timeouts.chunk)?;
     Ok(())
 }
 
+/// A random name for a download, unique to it. The public key of a throwaway key pair is
+/// as good a source of one as any, and a valid subject token
+fn new_transfer() -> String {
+    nkeys::KeyPair::new_user().public_key()
+}
+
+fn offered_codecs() -> Vec<String> {
+    COMPRESSION_CODECS.iter().map(|c| c.to_string()).collect()
+}
+
+/// Restores the original bytes of a downloaded chunk, so that chunk handlers never see
+/// compressed data
+fn decompress_chunk(mut chunk: FileChunk) -> Result<FileChunk, Box<dyn ::std::error::Error>> {
+    if let Some(codec) = chunk.compression.take() {
+        chunk.chunk_bytes = decompress(&codec, &chunk.chunk_bytes, chunk.chunk_size)?;
+    }
+    Ok(chunk)
+}
+
 pub(crate) fn get_client(
     nats_urls: Vec<String>,
     jwt: Option<&str>,
@@ -0,0 +1,280 @@
+//! An on-disk cache of downloaded actor modules. Module bytes are stored once per
+//! content digest (SHA-256) beneath `blobs/`, and an index maps each actor subject,
+//! revision and module hash (as recorded in the actor's claims) to a digest. When the
+//! cache grows beyond its size limit, the least recently used modules are evicted.
+//!
+//! Modules are checked against the module hash in their embedded claims both when they
+//! are cached and when they are read back. Every change to the index is made while
+//! holding an exclusive lock on `index.lock`, so the cache can be shared by processes
+
+use fs2::FileExt;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INDEX_FILE: &str = "index.json";
+const LOCK_FILE: &str = "index.lock";
+const BLOBS_DIR: &str = "blobs";
+
+/// The default upper bound on the total size of cached modules
+pub const DEFAULT_MAX_BYTES: u64 = 512 * 1024 * 1024; // 512MB
+
+/// A single cached module
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub subject: String,
+    pub revision: u64,
+    pub module_hash: String,
+    /// The SHA-256 digest of the module bytes, in lowercase hex
+    pub digest: String,
+    pub size: u64,
+    /// Seconds since the epoch at which the module was last read from or written to the cache
+    pub last_used: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    entries: Vec<CacheEntry>,
+}
+
+/// A size-bounded, content-addressed module cache rooted at a directory
+#[derive(Debug, Clone)]
+pub struct ModuleCache {
+    root: PathBuf,
+    max_bytes: u64,
+}
+
+impl ModuleCache {
+    /// Opens (creating if necessary) the cache rooted at the given directory
+    pub fn new<P: AsRef<Path>>(
+        root: P,
+        max_bytes: u64,
+    ) -> Result<ModuleCache, Box<dyn ::std::error::Error>> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(root.join(BLOBS_DIR))?;
+        Ok(ModuleCache { root, max_bytes })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Lists the cached modules, most recently used first
+    pub fn entries(&self) -> Result<Vec<CacheEntry>, Box<dyn ::std::error::Error>> {
+        let mut entries = self.load_index()?.entries;
+        entries.sort_by(|a, b| b.last_used.cmp(&a.last_used));
+        Ok(entries)
+    }
+
+    /// Retrieves the bytes of a cached module. Entries whose stored bytes no longer
+    /// match their digest or module hash are discarded and treated as a miss
+    pub fn get(
+        &self,
+        subject: &str,
+        revision: u64,
+        module_hash: &str,
+    ) -> Result<Option<Vec<u8>>, Box<dyn ::std::error::Error>> {
+        let _lock = self.lock()?;
+        let mut index = self.load_index()?;
+        let pos = match index.entries.iter().position(|e| {
+            e.subject == subject && e.revision == revision && e.module_hash == module_hash
+        }) {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+
+        let digest = index.entries[pos].digest.clone();
+        match fs::read(self.blob_path(&digest)) {
+            Ok(bytes)
+                if digest_of(&bytes) == digest
+                    && check_module(subject, module_hash, &bytes).is_ok() =>
+            {
+                index.entries[pos].last_used = now();
+                self.save_index(&index)?;
+                Ok(Some(bytes))
+            }
+            _ => {
+                warn!(
+                    "Discarding corrupt or missing cache entry for {} ({})",
+                    subject, digest
+                );
+                index.entries.remove(pos);
+                self.remove_unreferenced(&index, &digest)?;
+                self.save_index(&index)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Stores a module, replacing any previously cached revisions of the same subject,
+    /// then evicts modules as needed to stay within the size limit. Modules that do not
+    /// match the given subject and module hash are refused
+    pub fn insert(
+        &self,
+        subject: &str,
+        revision: u64,
+        module_hash: &str,
+        bytes: &[u8],
+    ) -> Result<CacheEntry, Box<dyn ::std::error::Error>> {
+        check_module(subject, module_hash, bytes)?;
+        let _lock = self.lock()?;
+        let digest = digest_of(bytes);
+        let path = self.blob_path(&digest);
+        if !path.exists() {
+            write_atomic(&path, bytes)?;
+        }
+
+        let entry = CacheEntry {
+            subject: subject.to_string(),
+            revision,
+            module_hash: module_hash.to_string(),
+            digest,
+            size: bytes.len() as u64,
+            last_used: now(),
+        };
+        let mut index = self.load_index()?;
+        let (stale, mut keep): (Vec<CacheEntry>, Vec<CacheEntry>) = index
+            .entries
+            .drain(..)
+            .partition(|e| e.subject == subject);
+        keep.push(entry.clone());
+        index.entries = keep;
+        for old in stale {
+            self.remove_unreferenced(&index, &old.digest)?;
+        }
+        self.evict(&mut index, self.max_bytes)?;
+        self.save_index(&index)?;
+        Ok(entry)
+    }
+
+    /// Evicts least recently used modules until the cache holds at most `max_bytes`,
+    /// returning the evicted entries
+    pub fn prune(&self, max_bytes: u64) -> Result<Vec<CacheEntry>, Box<dyn ::std::error::Error>> {
+        let _lock = self.lock()?;
+        let mut index = self.load_index()?;
+        let evicted = self.evict(&mut index, max_bytes)?;
+        self.save_index(&index)?;
+        Ok(evicted)
+    }
+
+    fn evict(
+        &self,
+        index: &mut CacheIndex,
+        max_bytes: u64,
+    ) -> Result<Vec<CacheEntry>, Box<dyn ::std::error::Error>> {
+        index.entries.sort_by(|a, b| b.last_used.cmp(&a.last_used));
+        let mut total = 0;
+        let mut evicted = Vec::new();
+        let mut kept = Vec::new();
+        for e in index.entries.drain(..) {
+            if total + e.size <= max_bytes {
+                total += e.size;
+                kept.push(e);
+            } else {
+                evicted.push(e);
+            }
+        }
+        index.entries = kept;
+        for e in &evicted {
+            self.remove_unreferenced(index, &e.digest)?;
+        }
+        Ok(evicted)
+    }
+
+    /// Deletes a blob once no index entry refers to its digest
+    fn remove_unreferenced(
+        &self,
+        index: &CacheIndex,
+        digest: &str,
+    ) -> Result<(), Box<dyn ::std::error::Error>> {
+        if index.entries.iter().any(|e| e.digest == digest) {
+            return Ok(());
+        }
+        match fs::remove_file(self.blob_path(digest)) {
+            Err(ref e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(format!("Failed to remove cached module {}: {}", digest, e).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes an exclusive lock on the index, held until the returned file is dropped
+    fn lock(&self) -> Result<File, Box<dyn ::std::error::Error>> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(self.root.join(LOCK_FILE))?;
+        file.lock_exclusive()?;
+        Ok(file)
+    }
+
+    fn blob_path(&self, digest: &str) -> PathBuf {
+        self.root.join(BLOBS_DIR).join(digest)
+    }
+
+    fn load_index(&self) -> Result<CacheIndex, Box<dyn ::std::error::Error>> {
+        match fs::read(self.root.join(INDEX_FILE)) {
+            Ok(raw) => Ok(serde_json::from_slice(&raw)?),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(CacheIndex::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save_index(&self, index: &CacheIndex) -> Result<(), Box<dyn ::std::error::Error>> {
+        write_atomic(&self.root.join(INDEX_FILE), &serde_json::to_vec(index)?)
+    }
+}
+
+/// Writes to a temporary file and renames it into place, so that concurrent readers
+/// (such as other processes sharing the cache) never observe a partial write
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), Box<dyn ::std::error::Error>> {
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Checks that a module carries an embedded token for the given subject, and that its
+/// bytes match the module hash recorded in that token
+pub(crate) fn check_module(
+    subject: &str,
+    module_hash: &str,
+    bytes: &[u8],
+) -> Result<(), Box<dyn ::std::error::Error>> {
+    // Fails if the module's bytes do not match the hash in its embedded claims
+    let embedded = wascap::wasm::extract_claims(bytes)?
+        .ok_or("The module does not contain an embedded token")?;
+    let embedded_hash = embedded
+        .claims
+        .metadata
+        .as_ref()
+        .map(|m| m.module_hash.as_str())
+        .unwrap_or("");
+    if embedded.claims.subject != subject || embedded_hash != module_hash {
+        return Err(format!("The module does not match {} ({})", subject, module_hash).into());
+    }
+    Ok(())
+}
+
+/// The lowercase hex SHA-256 digest of a module's bytes
+pub fn digest_of(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,212 @@
+//! Keeps a local directory stocked with the newest trusted revision of a set of actors, so
+//! that a host can hot-swap an actor whenever a new revision is published to the registry.
+//!
+//! A [`Follower`] tracks subjects along with a semver requirement on the version in each
+//! actor's claims. Whenever the catalog's latest revision of a followed actor changes and
+//! satisfies the requirement, the follower verifies the revision's trust chain, downloads the
+//! module, checks it against the catalog's record and writes it to `<dir>/<subject>.wasm`
+//! before handing it to the caller. The registry only serves the module of an actor's latest
+//! revision, so a latest revision that does not satisfy the requirement leaves the
+//! previously delivered module in place.
+
+use crate::{cache, ChainVerificationRequest, Client, EventKind};
+use semver::{Version, VersionReq};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+struct FollowTarget {
+    subject: String,
+    version: VersionReq,
+    operator: Option<String>,
+}
+
+/// A newly delivered revision of a followed actor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleUpdate {
+    pub subject: String,
+    pub revision: u64,
+    pub version: String,
+    /// The hash of the module recorded in the actor's signed claims
+    pub module_hash: String,
+    /// The lowercase hex SHA-256 digest of the module
+    pub digest: String,
+    pub path: PathBuf,
+}
+
+pub struct Follower {
+    client: Client,
+    dir: PathBuf,
+    poll_interval: Duration,
+    targets: Vec<FollowTarget>,
+    /// The revision and module hash last delivered for each subject
+    current: HashMap<String, (u64, String)>,
+}
+
+impl Follower {
+    /// Creates a follower that writes modules beneath the given directory. Nothing has been
+    /// delivered yet, so the first check delivers the current revision of every followed actor
+    pub fn new(client: Client, dir: impl Into<PathBuf>) -> Follower {
+        Follower {
+            client,
+            dir: dir.into(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            targets: Vec::new(),
+            current: HashMap::new(),
+        }
+    }
+
+    /// How often [`Follower::run`] polls the catalog, in addition to checking whenever the
+    /// registry announces that a followed actor's module has been uploaded
+    pub fn set_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+    }
+
+    /// Follows an actor whose version satisfies the given semver requirement, e.g. `^1.2`
+    /// or `*` to accept any version (including an actor without one)
+    pub fn follow(&mut self, subject: &str, version: &str) -> Result<(), Box<dyn ::std::error::Error>> {
+        self.add_target(subject, version, None)
+    }
+
+    /// Follows an actor, only accepting revisions whose trust chain terminates at the given operator
+    pub fn follow_operator(
+        &mut self,
+        operator: &str,
+        subject: &str,
+        version: &str,
+    ) -> Result<(), Box<dyn ::std::error::Error>> {
+        self.add_target(subject, version, Some(operator.to_string()))
+    }
+
+    /// The path to which the module of a followed actor is written
+    pub fn module_path(&self, subject: &str) -> PathBuf {
+        self.dir.join(format!("{}.wasm", subject))
+    }
+
+    /// Checks every followed actor once, delivering any new revisions. A failure to deliver
+    /// one actor is logged and retried on the next check rather than affecting the others
+    pub fn check(&mut self) -> Vec<ModuleUpdate> {
+        let mut updates = Vec::new();
+        for i in 0..self.targets.len() {
+            match self.check_target(&self.targets[i]) {
+                Ok(Some(update)) => {
+                    self.current.insert(
+                        update.subject.to_string(),
+                        (update.revision, update.module_hash.to_string()),
+                    );
+                    updates.push(update);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Unable to update actor {}: {}", self.targets[i].subject, e),
+            }
+        }
+        updates
+    }
+
+    /// Checks the followed actors immediately and then whenever a followed module is
+    /// uploaded or the poll interval elapses, invoking the callback with each new revision.
+    /// Only returns if the registry's change events cannot be subscribed to
+    pub fn run<F>(mut self, mut callback: F) -> Result<(), Box<dyn ::std::error::Error>>
+    where
+        F: FnMut(&ModuleUpdate),
+    {
+        let (s, r) = crossbeam::channel::unbounded();
+        let subjects: Vec<String> = self.targets.iter().map(|t| t.subject.to_string()).collect();
+        self.client.watch(move |event| {
+            if event.kind == EventKind::ModuleUploaded && subjects.contains(&event.subject) {
+                let _ = s.send(());
+            }
+        })?;
+        loop {
+            for update in self.check() {
+                callback(&update);
+            }
+            let _ = r.recv_timeout(self.poll_interval);
+            while r.try_recv().is_ok() {}
+        }
+    }
+
+    fn add_target(
+        &mut self,
+        subject: &str,
+        version: &str,
+        operator: Option<String>,
+    ) -> Result<(), Box<dyn ::std::error::Error>> {
+        let version = VersionReq::parse(version)
+            .map_err(|e| format!("Invalid version requirement '{}': {}", version, e))?;
+        self.targets.retain(|t| t.subject != subject);
+        self.targets.push(FollowTarget {
+            subject: subject.to_string(),
+            version,
+            operator,
+        });
+        Ok(())
+    }
+
+    fn check_target(
+        &self,
+        target: &FollowTarget,
+    ) -> Result<Option<ModuleUpdate>, Box<dyn ::std::error::Error>> {
+        let subject = &target.subject;
+        let summary = match self.client.actor_summary(subject)? {
+            Some(summary) => summary,
+            None => {
+                debug!("Followed actor {} is not in the catalog", subject);
+                return Ok(None);
+            }
+        };
+        if self.current.get(subject) == Some(&(summary.revision, summary.module_hash.to_string())) {
+            return Ok(None);
+        }
+        if !satisfies(&target.version, &summary.version) {
+            debug!(
+                "Revision {} of actor {} has version '{}', which does not satisfy {}",
+                summary.revision, subject, summary.version, target.version
+            );
+            return Ok(None);
+        }
+
+        let report = self.client.verify_chain_with(&ChainVerificationRequest {
+            subject: subject.to_string(),
+            revision: Some(summary.revision),
+            operator: target.operator.clone(),
+            as_of: 0,
+        })?;
+        if !report.trusted {
+            return Err(format!("Revision {} is not trusted", summary.revision).into());
+        }
+
+        let bytes = self.client.download_actor_bytes(subject)?;
+        if cache::check_module(subject, &summary.module_hash, &bytes).is_err() {
+            // The new revision's token has been put but its module not yet uploaded
+            return Err(format!(
+                "The stored module does not match revision {}",
+                summary.revision
+            )
+            .into());
+        }
+
+        // Written with a single rename, so that a host never reads a partially written module
+        let path = self.module_path(subject);
+        fs::create_dir_all(&self.dir)?;
+        cache::write_atomic(&path, &bytes)?;
+        Ok(Some(ModuleUpdate {
+            subject: subject.to_string(),
+            revision: summary.revision,
+            version: summary.version,
+            module_hash: summary.module_hash,
+            digest: cache::digest_of(&bytes),
+            path,
+        }))
+    }
+}
+
+fn satisfies(req: &VersionReq, version: &str) -> bool {
+    match Version::parse(version) {
+        Ok(v) => req.matches(&v),
+        Err(_) => *req == VersionReq::any(),
+    }
+}
@@ -1,15 +1,49 @@
-pub use broker::CHUNK_SIZE;
+pub use broker::{CHUNK_SIZE, DOWNLOAD_WINDOW};
 pub use chunks::Chunks;
 use gantry_protocol as protocol;
-pub use protocol::catalog::{CatalogQuery, CatalogQueryResults, Token};
-pub use protocol::stream::{DownloadRequest, FileChunk, TransferAck, UploadRequest};
+pub use protocol::audit::{AuditLog, AuditOperation, AuditQuery, AuditRecord};
+pub use protocol::catalog::{
+    ActorSummary, CatalogExport, CatalogExportRequest, CatalogQuery, CatalogQueryResult,
+    CatalogQueryResults, ChainLink, ChainLinkKind, ChainVerification, ChainVerificationRequest,
+    ExportedToken, QueryType, SubjectStatus, SubjectStatusRequest, Token, TokenChange,
+    TrustRoots, TrustedOperators,
+};
+pub use protocol::events::{CatalogEvent, EventKind};
+pub use protocol::meta::{ServerInfo, PROTOCOL_VERSION};
+pub use protocol::ContentType;
+pub use protocol::stream::{
+    DownloadRequest, FileChunk, ModuleInfo, ModuleInfoRequest, TransferAck, UploadRequest,
+};
 
 pub mod broker;
+pub mod cache;
 pub mod chunks;
+pub mod follow;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+pub mod options;
+pub mod transport;
+
+use broker::Connection;
+pub use cache::{CacheEntry, ModuleCache};
+pub use follow::{Follower, ModuleUpdate};
+#[cfg(feature = "async")]
+pub use nonblocking::AsyncClient;
+pub use options::{RetryPolicy, Timeouts};
+pub use transport::{MessageHandler, Transport};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use wascap::jwt::{Account, Claims};
 
 #[macro_use]
 extern crate serde_derive;
 
+#[macro_use]
+extern crate log;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectionConfiguration {
     pub server_urls: Vec<String>,
@@ -17,57 +51,417 @@ pub struct ConnectionConfiguration {
     pub user_seed: String,
 }
 
+/// The outcome of publishing an actor module with [`Client::publish_actor`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublishedActor {
+    pub registration: CatalogQueryResult,
+    pub bytes: u64,
+    /// The lowercase hex SHA-256 digest of the module
+    pub digest: String,
+}
+
+/// Configures and connects a [`Client`]. Unless a transport is supplied, the client
+/// connects to the given NATS servers (or `nats://localhost:4222` if none are given)
+#[derive(Default)]
+pub struct ClientBuilder {
+    server_urls: Vec<String>,
+    credentials: Option<(String, String)>,
+    transport: Option<Arc<dyn Transport>>,
+    timeouts: Timeouts,
+    retry: RetryPolicy,
+    cache: Option<ModuleCache>,
+    content_type: ContentType,
+    uncompressed: bool,
+    download_window: Option<u64>,
+}
+
+impl ClientBuilder {
+    pub fn new() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    pub fn server_urls(mut self, urls: Vec<String>) -> ClientBuilder {
+        self.server_urls = urls;
+        self
+    }
+
+    /// The user JWT and seed used to authenticate to NATS
+    pub fn credentials(mut self, jwt: &str, seed: &str) -> ClientBuilder {
+        self.credentials = Some((jwt.to_string(), seed.to_string()));
+        self
+    }
+
+    /// Speaks the Gantry protocol over the supplied transport rather than a NATS connection
+    pub fn transport<T>(mut self, transport: T) -> ClientBuilder
+    where
+        T: Transport + 'static,
+    {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> ClientBuilder {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> ClientBuilder {
+        self.retry = retry;
+        self
+    }
+
+    /// The encoding of requests, and so of the server's replies. Defaults to msgpack
+    pub fn content_type(mut self, content_type: ContentType) -> ClientBuilder {
+        self.content_type = content_type;
+        self
+    }
+
+    /// Whether to offer to compress module uploads and downloads. Compression is enabled by
+    /// default, and is only used if the server agrees to it
+    pub fn compression(mut self, enabled: bool) -> ClientBuilder {
+        self.uncompressed = !enabled;
+        self
+    }
+
+    /// The most download chunks the server may send before the client has handled them.
+    /// Defaults to [`DOWNLOAD_WINDOW`], and 0 lets the server send chunks as fast as it can
+    pub fn download_window(mut self, chunks: u64) -> ClientBuilder {
+        self.download_window = Some(chunks);
+        self
+    }
+
+    /// Serves whole-module downloads from the given cache whenever the catalog reports
+    /// that the cached copy is still current
+    pub fn cache(mut self, cache: ModuleCache) -> ClientBuilder {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Creates the client, failing if the NATS connection cannot be established
+    pub fn build(self) -> Result<Client, Box<dyn ::std::error::Error>> {
+        let transport = match self.transport {
+            Some(t) => t,
+            None => {
+                let urls = if self.server_urls.is_empty() {
+                    vec!["nats://localhost:4222".to_string()]
+                } else {
+                    self.server_urls
+                };
+                let (jwt, seed) = match self.credentials {
+                    Some((ref jwt, ref seed)) => (Some(jwt.as_str()), Some(seed.as_str())),
+                    None => (None, None),
+                };
+                Arc::new(broker::get_client(urls, jwt, seed)?) as Arc<dyn Transport>
+            }
+        };
+        Ok(Client {
+            conn: Connection {
+                transport,
+                timeouts: self.timeouts,
+                retry: self.retry,
+                content_type: self.content_type,
+                compression: !self.uncompressed,
+                download_window: self.download_window.unwrap_or(DOWNLOAD_WINDOW),
+                upload_codecs: Default::default(),
+            },
+            cache: self.cache,
+        })
+    }
+}
+
 /// An instance of a Gantry client connection
 #[derive(Clone)]
 pub struct Client {
-    natsclient: natsclient::Client,
+    conn: Connection,
+    cache: Option<ModuleCache>,
 }
 
 impl Client {
-    pub fn new(nats_urls: Vec<String>, jwt: &str, seed: &str) -> Client {
-        Client {
-            natsclient: broker::get_client(nats_urls, Some(jwt), Some(seed)).unwrap(),
-        }
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
     }
 
-    pub fn from_config(config: ConnectionConfiguration) -> Client {
-        Client {
-            natsclient: broker::get_client(
-                config.server_urls,
-                Some(&config.user_jwt),
-                Some(&config.user_seed),
-            )
-            .unwrap(),
-        }
+    pub fn new(
+        nats_urls: Vec<String>,
+        jwt: &str,
+        seed: &str,
+    ) -> Result<Client, Box<dyn ::std::error::Error>> {
+        ClientBuilder::new()
+            .server_urls(nats_urls)
+            .credentials(jwt, seed)
+            .build()
+    }
+
+    pub fn from_config(
+        config: ConnectionConfiguration,
+    ) -> Result<Client, Box<dyn ::std::error::Error>> {
+        Client::new(config.server_urls, &config.user_jwt, &config.user_seed)
+    }
+
+    /// Connects anonymously to a NATS server running on the local machine
+    pub fn default() -> Result<Client, Box<dyn ::std::error::Error>> {
+        ClientBuilder::new().build()
     }
 
-    pub fn default() -> Client {
+    /// Creates a client that speaks the Gantry protocol over the supplied transport
+    /// rather than a NATS connection, using the default timeouts and retry policy
+    pub fn with_transport<T>(transport: T) -> Client
+    where
+        T: Transport + 'static,
+    {
         Client {
-            natsclient: broker::get_client(vec!["nats://localhost:4222".into()], None, None)
-                .unwrap(),
+            conn: Connection {
+                transport: Arc::new(transport),
+                timeouts: Timeouts::default(),
+                retry: RetryPolicy::default(),
+                content_type: ContentType::default(),
+                compression: true,
+                download_window: DOWNLOAD_WINDOW,
+                upload_codecs: Default::default(),
+            },
+            cache: None,
         }
     }
 
-    pub fn put_token(&self, token: &Token) -> Result<(), Box<dyn ::std::error::Error>> {
-        broker::put(&self.natsclient, token)
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.conn.timeouts
+    }
+
+    pub fn cache(&self) -> Option<&ModuleCache> {
+        self.cache.as_ref()
+    }
+
+    /// Puts a token in the catalog, returning the registered subject
+    pub fn put_token(
+        &self,
+        token: &Token,
+    ) -> Result<CatalogQueryResult, Box<dyn ::std::error::Error>> {
+        broker::put(&self.conn, token)
     }
 
     pub fn query_catalog(
         &self,
         query: &CatalogQuery,
     ) -> Result<CatalogQueryResults, Box<dyn ::std::error::Error>> {
-        broker::query(&self.natsclient, query)
+        broker::query(&self.conn, query)
+    }
+
+    /// Reports whether a subject is registered and revoked, without listing the catalog. If a
+    /// revision is given, the subject only counts as registered if that revision is stored
+    pub fn subject_status(
+        &self,
+        subject: &str,
+        revision: Option<u64>,
+    ) -> Result<SubjectStatus, Box<dyn ::std::error::Error>> {
+        broker::status(
+            &self.conn,
+            &SubjectStatusRequest {
+                subject: subject.to_string(),
+                revision,
+            },
+        )
+    }
+
+    /// Retrieves every stored revision of the raw tokens of the given kind
+    pub fn export_tokens(
+        &self,
+        query_type: QueryType,
+    ) -> Result<CatalogExport, Box<dyn ::std::error::Error>> {
+        broker::export(
+            &self.conn,
+            &CatalogExportRequest {
+                query_type,
+                subject: None,
+            },
+        )
+    }
+
+    /// Retrieves every stored revision of the raw token of a single subject of the given
+    /// kind, which is empty if the subject is not stored or has been revoked
+    pub fn export_subject(
+        &self,
+        query_type: QueryType,
+        subject: &str,
+    ) -> Result<CatalogExport, Box<dyn ::std::error::Error>> {
+        broker::export(
+            &self.conn,
+            &CatalogExportRequest {
+                query_type,
+                subject: Some(subject.to_string()),
+            },
+        )
+    }
+
+    /// Invokes the handler for every change event the registry publishes from now on, for
+    /// as long as the underlying connection remains open
+    pub fn watch<F>(&self, handler: F) -> Result<(), Box<dyn ::std::error::Error>>
+    where
+        F: Fn(CatalogEvent) + Sync + Send + 'static,
+    {
+        broker::watch(&self.conn, handler)
+    }
+
+    /// Retrieves the operators, and their signing keys, that the catalog currently trusts
+    pub fn trust_roots(&self) -> Result<TrustedOperators, Box<dyn ::std::error::Error>> {
+        broker::trust_roots(&self.conn)
+    }
+
+    /// Retrieves the server's protocol version and the optional features it supports
+    pub fn server_info(&self) -> Result<ServerInfo, Box<dyn ::std::error::Error>> {
+        broker::server_info(&self.conn)
+    }
+
+    /// Describes the module stored for an actor without downloading it. If a revision is
+    /// given, the module only counts as uploaded if it was uploaded for that revision.
+    /// Servers that support this advertise the `module.info` feature
+    pub fn module_info(
+        &self,
+        actor: &str,
+        revision: Option<u64>,
+    ) -> Result<ModuleInfo, Box<dyn ::std::error::Error>> {
+        broker::module_info(
+            &self.conn,
+            &ModuleInfoRequest {
+                actor: actor.to_string(),
+                revision,
+            },
+        )
+    }
+
+    /// Retrieves the records of the audit log that match the query, oldest first
+    pub fn audit_log(&self, query: &AuditQuery) -> Result<AuditLog, Box<dyn ::std::error::Error>> {
+        broker::audit(&self.conn, query)
+    }
+
+    /// Revokes a subject, removing it from catalog listings. The revocation is proven with
+    /// the seed of the subject's issuer, of its operator, or of one of the operator's signers
+    pub fn remove_token(
+        &self,
+        subject: &str,
+        seed: &str,
+    ) -> Result<CatalogQueryResult, Box<dyn ::std::error::Error>> {
+        let request = protocol::catalog::SUBJECT_CATALOG_DELETE_TOKEN;
+        self.revoke(&token_change(seed, request, subject, None)?)
+    }
+
+    /// Revokes a subject with a change whose proof was created elsewhere, such as by a
+    /// caller of the HTTP gateway (see [`change_proof`])
+    pub fn revoke(
+        &self,
+        change: &TokenChange,
+    ) -> Result<CatalogQueryResult, Box<dyn ::std::error::Error>> {
+        broker::delete(&self.conn, change)
+    }
+
+    /// Lifts the revocation of a subject, listing it in the catalog again. Proven in the
+    /// same way as a revocation
+    pub fn reinstate_token(
+        &self,
+        subject: &str,
+        seed: &str,
+    ) -> Result<CatalogQueryResult, Box<dyn ::std::error::Error>> {
+        let request = protocol::catalog::SUBJECT_CATALOG_REINSTATE_TOKEN;
+        broker::reinstate(&self.conn, &token_change(seed, request, subject, None)?)
+    }
+
+    /// Removes a single revision of a subject, without revoking the subject. Proven in the
+    /// same way as a revocation
+    pub fn unregister_token(
+        &self,
+        subject: &str,
+        revision: u64,
+        seed: &str,
+    ) -> Result<CatalogQueryResult, Box<dyn ::std::error::Error>> {
+        let request = protocol::catalog::SUBJECT_CATALOG_UNREGISTER_TOKEN;
+        broker::unregister(
+            &self.conn,
+            &token_change(seed, request, subject, Some(revision))?,
+        )
+    }
+
+    /// Registers the token embedded in a signed actor module and uploads the module. If the
+    /// upload fails, the revision registered by this call is rolled back so that the catalog
+    /// does not list an actor with no module. A revision that was already stored before the
+    /// call is never rolled back. The rollback is proven with the given seed, which must
+    /// belong to the actor's issuer, its operator or one of the operator's signers; without a
+    /// seed, a failed registration is left in place and reported in the error
+    pub fn publish_actor(
+        &self,
+        module: &[u8],
+        seed: Option<&str>,
+    ) -> Result<PublishedActor, Box<dyn ::std::error::Error>> {
+        let embedded = wascap::wasm::extract_claims(module)?
+            .ok_or("The module does not contain an embedded token")?;
+        let subject = embedded.claims.subject.to_string();
+        // Read as the catalog reads it when the token is put
+        let revision = serde_json::to_value(&embedded.claims)?["wascap"]["rev"]
+            .as_u64()
+            .unwrap_or(0);
+        let token = Token {
+            raw_token: embedded.jwt,
+            decoded_token_json: "".to_string(),
+            validation_result: None,
+            received_at: 0,
+        };
+        let created = !self.subject_status(&subject, Some(revision))?.registered;
+
+        let registration = self.put_token(&token)?;
+        if let Err(e) = self.upload_actor(&subject, module) {
+            if !created {
+                return Err(format!("Failed to upload the module for {}: {}", subject, e).into());
+            }
+            let rollback = match seed {
+                Some(seed) => self.unregister_token(&subject, revision, seed),
+                None => Err("no seed was given to prove the rollback".into()),
+            };
+            return match rollback {
+                Ok(_) => Err(format!(
+                    "Failed to upload the module for {}, its registration was rolled back: {}",
+                    subject, e
+                )
+                .into()),
+                Err(re) => Err(format!(
+                    "Failed to upload the module for {} ({}), and its registration could not be rolled back: {}",
+                    subject, e, re
+                )
+                .into()),
+            };
+        }
+
+        Ok(PublishedActor {
+            registration,
+            bytes: module.len() as u64,
+            digest: cache::digest_of(module),
+        })
+    }
+
+    /// Verifies the trust chain of the latest revision of the given subject, from the
+    /// subject up to the trusted operator at the root of the chain
+    pub fn verify_chain(
+        &self,
+        subject: &str,
+    ) -> Result<ChainVerification, Box<dyn ::std::error::Error>> {
+        self.verify_chain_with(&ChainVerificationRequest {
+            subject: subject.to_string(),
+            revision: None,
+            operator: None,
+            as_of: 0,
+        })
     }
 
-    pub fn remove_token(&self, _token: &Token) -> Result<(), Box<dyn ::std::error::Error>> {
-        unimplemented!()
+    /// Verifies a trust chain, optionally pinned to a revision and required operator
+    pub fn verify_chain_with(
+        &self,
+        req: &ChainVerificationRequest,
+    ) -> Result<ChainVerification, Box<dyn ::std::error::Error>> {
+        broker::verify_chain(&self.conn, req)
     }
 
     pub fn start_upload(
         &self,
         req: &UploadRequest,
     ) -> Result<TransferAck, Box<dyn ::std::error::Error>> {
-        broker::start_upload(&self.natsclient, req)
+        broker::start_upload(&self.conn, req)
     }
 
     pub fn upload_chunk(
@@ -80,7 +474,7 @@ impl Client {
         bytes: Vec<u8>,
     ) -> Result<(), Box<dyn ::std::error::Error>> {
         broker::upload_chunk(
-            &self.natsclient,
+            &self.conn,
             sequence_no,
             actor,
             chunk_size,
@@ -101,7 +495,278 @@ impl Client {
     {
         let req = DownloadRequest {
             actor: actor.to_string(),
+            operator: None,
+            ..Default::default()
+        };
+        broker::request_download(&self.conn, req, chunk_handler)
+    }
+
+    /// Downloads an actor, failing if it does not belong to the given operator's trust chain
+    pub fn download_operator_actor<F>(
+        &self,
+        operator: &str,
+        actor: &str,
+        chunk_handler: F,
+    ) -> Result<TransferAck, Box<dyn ::std::error::Error>>
+    where
+        F: Fn(FileChunk) -> Result<(), Box<dyn ::std::error::Error>> + Sync + Send,
+        F: 'static,
+    {
+        let req = DownloadRequest {
+            actor: actor.to_string(),
+            operator: Some(operator.to_string()),
+            ..Default::default()
+        };
+        broker::request_download(&self.conn, req, chunk_handler)
+    }
+
+    /// Downloads `length` bytes of an actor module starting at `offset`, or the rest of the
+    /// module if `length` is 0. Chunks are trimmed to the range, and the final chunk is the
+    /// one whose `sequence_no` is the acknowledgement's `total_chunks`. An empty range
+    /// sends no chunks
+    pub fn download_actor_range<F>(
+        &self,
+        actor: &str,
+        offset: u64,
+        length: u64,
+        chunk_handler: F,
+    ) -> Result<TransferAck, Box<dyn ::std::error::Error>>
+    where
+        F: Fn(FileChunk) -> Result<(), Box<dyn ::std::error::Error>> + Sync + Send,
+        F: 'static,
+    {
+        let req = DownloadRequest {
+            actor: actor.to_string(),
+            offset,
+            length,
+            ..Default::default()
+        };
+        broker::request_download(&self.conn, req, chunk_handler)
+    }
+
+    /// Downloads a range of an actor module's bytes (see [`Client::download_actor_range`]),
+    /// waiting up to the download timeout for every chunk to arrive
+    pub fn download_actor_range_bytes(
+        &self,
+        actor: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+        let mut bytes = Vec::new();
+        self.receive_chunks(
+            DownloadRequest {
+                actor: actor.to_string(),
+                offset,
+                length,
+                ..Default::default()
+            },
+            |chunk| {
+                bytes.extend_from_slice(&chunk.chunk_bytes);
+                Ok(())
+            },
+        )?;
+        Ok(bytes)
+    }
+
+    /// Downloads an actor module to a file, continuing from the end of whatever the file
+    /// already holds. If the download is interrupted, calling this again fetches only the
+    /// bytes that are still missing. Returns the size of the module
+    pub fn resume_download(
+        &self,
+        actor: &str,
+        path: &Path,
+    ) -> Result<u64, Box<dyn ::std::error::Error>> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut position = file.metadata()?.len();
+        let req = DownloadRequest {
+            actor: actor.to_string(),
+            offset: position,
+            ..Default::default()
         };
-        broker::request_download(&self.natsclient, req, chunk_handler)
+        let ack = self.receive_chunks(req, |chunk| {
+            if chunk.offset != position {
+                return Err(format!(
+                    "Received bytes {} onward of actor {} while expecting byte {}",
+                    chunk.offset, actor, position
+                )
+                .into());
+            }
+            file.write_all(&chunk.chunk_bytes)?;
+            position += chunk.chunk_bytes.len() as u64;
+            Ok(())
+        })?;
+        file.flush()?;
+        Ok(ack.total_bytes)
+    }
+
+    /// Uploads the raw bytes of an actor module, which must already have its token in the catalog
+    pub fn upload_actor(
+        &self,
+        actor: &str,
+        bytes: &[u8],
+    ) -> Result<TransferAck, Box<dyn ::std::error::Error>> {
+        let total_bytes = bytes.len() as u64;
+        let total_chunks = total_bytes / CHUNK_SIZE;
+        let ack = self.start_upload(&UploadRequest {
+            actor: actor.to_string(),
+            chunk_size: CHUNK_SIZE,
+            total_bytes,
+            total_chunks,
+            compression: vec![],
+        })?;
+        for (i, chunk) in bytes.chunks(CHUNK_SIZE as usize).enumerate() {
+            self.upload_chunk(
+                i as u64,
+                actor,
+                CHUNK_SIZE,
+                total_bytes,
+                total_chunks,
+                chunk.to_vec(),
+            )?;
+        }
+        Ok(ack)
     }
+
+    /// Retrieves the catalog's summary of the latest revision of an actor
+    pub fn actor_summary(
+        &self,
+        actor: &str,
+    ) -> Result<Option<ActorSummary>, Box<dyn ::std::error::Error>> {
+        let results = self.query_catalog(&CatalogQuery {
+            query_type: QueryType::Actor,
+            issuer: None,
+            operator: None,
+            subject: Some(actor.to_string()),
+        })?;
+        Ok(results
+            .results
+            .into_iter()
+            .find(|r| r.subject == actor)
+            .and_then(|r| r.actor))
+    }
+
+    /// Downloads an actor module in its entirety, waiting up to the download timeout
+    /// for every chunk to arrive. If the client has a cache, the catalog is first asked
+    /// for the actor's latest revision and module hash, and a matching cached copy is
+    /// returned without streaming
+    pub fn download_actor_bytes(&self, actor: &str) -> Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+        let cache = match self.cache {
+            Some(ref c) => c,
+            None => return self.stream_actor_bytes(actor),
+        };
+        let summary = self
+            .actor_summary(actor)?
+            .ok_or_else(|| format!("Actor {} is not in the catalog", actor))?;
+        if let Some(bytes) = cache.get(actor, summary.revision, &summary.module_hash)? {
+            debug!("Serving actor {} from the module cache", actor);
+            return Ok(bytes);
+        }
+        let bytes = self.stream_actor_bytes(actor)?;
+        if let Err(e) = cache.insert(actor, summary.revision, &summary.module_hash, &bytes) {
+            warn!("Failed to cache actor {}: {}", actor, e);
+        }
+        Ok(bytes)
+    }
+
+    fn stream_actor_bytes(&self, actor: &str) -> Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+        let mut bytes = Vec::new();
+        self.receive_chunks(
+            DownloadRequest {
+                actor: actor.to_string(),
+                ..Default::default()
+            },
+            |chunk| {
+                bytes.extend_from_slice(&chunk.chunk_bytes);
+                Ok(())
+            },
+        )?;
+        Ok(bytes)
+    }
+
+    /// Requests a download and passes its chunks to `sink` in the order they arrive,
+    /// waiting up to the download timeout for the final chunk
+    fn receive_chunks<F>(
+        &self,
+        req: DownloadRequest,
+        mut sink: F,
+    ) -> Result<TransferAck, Box<dyn ::std::error::Error>>
+    where
+        F: FnMut(FileChunk) -> Result<(), Box<dyn ::std::error::Error>>,
+    {
+        let actor = req.actor.to_string();
+        let (s, r) = crossbeam::channel::unbounded();
+        let ack = broker::request_download(&self.conn, req, move |chunk| {
+            s.send(chunk).map_err(|e| e.to_string().into())
+        })?;
+        if !ack.success {
+            return Err(format!("Download of actor {} was refused", actor).into());
+        }
+        if ack.total_chunks == 0 {
+            return Ok(ack);
+        }
+
+        let deadline = Instant::now() + self.conn.timeouts.download;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let chunk = r
+                .recv_timeout(remaining)
+                .map_err(|_| format!("Timed out downloading actor {}", actor))?;
+            let last = chunk.sequence_no >= chunk.total_chunks;
+            sink(chunk)?;
+            if last {
+                break;
+            }
+        }
+        Ok(ack)
+    }
+}
+
+/// Creates the proof of a change to a subject (see [`TokenChange`]), signed with the given
+/// seed and naming the subject on which the change will be requested
+pub fn change_proof(
+    seed: &str,
+    request_subject: &str,
+    subject: &str,
+    revision: Option<u64>,
+) -> Result<String, Box<dyn ::std::error::Error>> {
+    let key = nkeys::KeyPair::from_seed(seed)?;
+    let mut claims = Claims::<Account>::new(
+        protocol::catalog::change_proof_name(request_subject, revision),
+        key.public_key(),
+        subject.to_string(),
+        vec![],
+    );
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    claims.expires = Some(now + protocol::catalog::CHANGE_PROOF_TTL);
+    Ok(claims.encode(&key)?)
+}
+
+fn token_change(
+    seed: &str,
+    request_subject: &str,
+    subject: &str,
+    revision: Option<u64>,
+) -> Result<TokenChange, Box<dyn ::std::error::Error>> {
+    Ok(TokenChange {
+        subject: subject.to_string(),
+        revision,
+        proof: change_proof(seed, request_subject, subject, revision)?,
+        ..Default::default()
+    })
+}
+
+/// Creates a bearer token for the registry's HTTP gateway, signed with the seed of an
+/// account or operator in the registry's trust chain. The gateway refuses tokens that
+/// live for more than an hour
+pub fn bearer_token(seed: &str, ttl: Duration) -> Result<String, Box<dyn ::std::error::Error>> {
+    let key = nkeys::KeyPair::from_seed(seed)?;
+    let mut claims = Claims::<Account>::new(
+        "gantry http gateway".to_string(),
+        key.public_key(),
+        key.public_key(),
+        vec![],
+    );
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    claims.expires = Some(now + ttl.as_secs());
+    Ok(claims.encode(&key)?)
 }
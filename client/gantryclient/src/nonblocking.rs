@@ -0,0 +1,190 @@
+//! An asynchronous flavor of the Gantry client for use within a tokio runtime, enabled
+//! with the `async` feature. Request/reply calls are run on tokio's blocking pool, while
+//! downloaded chunks are delivered as a stream so no thread waits on a transfer
+
+use crate::{
+    AuditLog, AuditQuery, CatalogEvent, CatalogExport, CatalogQuery, CatalogQueryResult, CatalogQueryResults, ChainVerification,
+    ChainVerificationRequest, Client, FileChunk, ModuleInfo, PublishedActor, QueryType, ServerInfo, Token, TransferAck, TrustedOperators,
+    UploadRequest,
+};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use futures::StreamExt;
+use std::error::Error;
+use std::path::PathBuf;
+
+type AsyncResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// A Gantry client whose operations return futures
+#[derive(Clone)]
+pub struct AsyncClient {
+    client: Client,
+}
+
+impl From<Client> for AsyncClient {
+    fn from(client: Client) -> Self {
+        AsyncClient { client }
+    }
+}
+
+impl AsyncClient {
+    pub fn new(client: Client) -> AsyncClient {
+        AsyncClient { client }
+    }
+
+    /// The blocking client that this client delegates to
+    pub fn blocking(&self) -> &Client {
+        &self.client
+    }
+
+    pub async fn put_token(&self, token: Token) -> AsyncResult<CatalogQueryResult> {
+        self.run(move |c| c.put_token(&token)).await
+    }
+
+    pub async fn query_catalog(&self, query: CatalogQuery) -> AsyncResult<CatalogQueryResults> {
+        self.run(move |c| c.query_catalog(&query)).await
+    }
+
+    pub async fn export_tokens(&self, query_type: QueryType) -> AsyncResult<CatalogExport> {
+        self.run(move |c| c.export_tokens(query_type)).await
+    }
+
+    pub async fn audit_log(&self, query: AuditQuery) -> AsyncResult<AuditLog> {
+        self.run(move |c| c.audit_log(&query)).await
+    }
+
+    pub async fn server_info(&self) -> AsyncResult<ServerInfo> {
+        self.run(|c| c.server_info()).await
+    }
+
+    pub async fn module_info(&self, actor: &str, revision: Option<u64>) -> AsyncResult<ModuleInfo> {
+        let actor = actor.to_string();
+        self.run(move |c| c.module_info(&actor, revision)).await
+    }
+
+    pub async fn trust_roots(&self) -> AsyncResult<TrustedOperators> {
+        self.run(|c| c.trust_roots()).await
+    }
+
+    pub async fn remove_token(&self, subject: &str, seed: &str) -> AsyncResult<CatalogQueryResult> {
+        let (subject, seed) = (subject.to_string(), seed.to_string());
+        self.run(move |c| c.remove_token(&subject, &seed)).await
+    }
+
+    pub async fn reinstate_token(
+        &self,
+        subject: &str,
+        seed: &str,
+    ) -> AsyncResult<CatalogQueryResult> {
+        let (subject, seed) = (subject.to_string(), seed.to_string());
+        self.run(move |c| c.reinstate_token(&subject, &seed)).await
+    }
+
+    pub async fn verify_chain_with(
+        &self,
+        req: ChainVerificationRequest,
+    ) -> AsyncResult<ChainVerification> {
+        self.run(move |c| c.verify_chain_with(&req)).await
+    }
+
+    pub async fn start_upload(&self, req: UploadRequest) -> AsyncResult<TransferAck> {
+        self.run(move |c| c.start_upload(&req)).await
+    }
+
+    pub async fn upload_actor(&self, actor: &str, bytes: Vec<u8>) -> AsyncResult<TransferAck> {
+        let actor = actor.to_string();
+        self.run(move |c| c.upload_actor(&actor, &bytes)).await
+    }
+
+    pub async fn publish_actor(
+        &self,
+        module: Vec<u8>,
+        seed: Option<String>,
+    ) -> AsyncResult<PublishedActor> {
+        self.run(move |c| c.publish_actor(&module, seed.as_deref())).await
+    }
+
+    /// Starts downloading an actor, yielding its chunks in the order they arrive. The
+    /// stream ends after the final chunk
+    pub async fn download_actor(
+        &self,
+        actor: &str,
+        operator: Option<&str>,
+    ) -> AsyncResult<(TransferAck, UnboundedReceiver<FileChunk>)> {
+        let (s, r) = unbounded();
+        let actor = actor.to_string();
+        let operator = operator.map(|o| o.to_string());
+        let ack = self
+            .run(move |c| {
+                let handler = move |chunk: FileChunk| {
+                    let last = chunk.sequence_no >= chunk.total_chunks;
+                    s.unbounded_send(chunk).map_err(|e| e.to_string())?;
+                    if last {
+                        s.close_channel();
+                    }
+                    Ok(())
+                };
+                match operator {
+                    Some(ref operator) => c.download_operator_actor(operator, &actor, handler),
+                    None => c.download_actor(&actor, handler),
+                }
+            })
+            .await?;
+        Ok((ack, r))
+    }
+
+    /// Downloads an actor module in its entirety, failing if every chunk has not arrived
+    /// within the download timeout. Clients with a module cache consult it first
+    pub async fn download_actor_bytes(&self, actor: &str) -> AsyncResult<Vec<u8>> {
+        if self.client.cache().is_some() {
+            let actor = actor.to_string();
+            return self.run(move |c| c.download_actor_bytes(&actor)).await;
+        }
+        let (ack, mut chunks) = self.download_actor(actor, None).await?;
+        if !ack.success {
+            return Err(format!("Download of actor {} was refused", actor).into());
+        }
+        if ack.total_chunks == 0 {
+            return Ok(Vec::new());
+        }
+        let collect = async {
+            let mut bytes = Vec::with_capacity(ack.total_bytes as usize);
+            while let Some(chunk) = chunks.next().await {
+                bytes.extend_from_slice(&chunk.chunk_bytes);
+            }
+            bytes
+        };
+        tokio::time::timeout(self.client.timeouts().download, collect)
+            .await
+            .map_err(|_| format!("Timed out downloading actor {}", actor).into())
+    }
+
+    /// Downloads an actor module to a file, continuing from the end of whatever the file
+    /// already holds (see [`Client::resume_download`])
+    pub async fn resume_download(&self, actor: &str, path: PathBuf) -> AsyncResult<u64> {
+        let actor = actor.to_string();
+        self.run(move |c| c.resume_download(&actor, &path)).await
+    }
+
+    /// Subscribes to the registry's change events, yielding them as they are published
+    pub async fn watch(&self) -> AsyncResult<UnboundedReceiver<CatalogEvent>> {
+        let (s, r) = unbounded();
+        self.run(move |c| {
+            c.watch(move |event| {
+                let _ = s.unbounded_send(event);
+            })
+        })
+        .await?;
+        Ok(r)
+    }
+
+    async fn run<T, F>(&self, f: F) -> AsyncResult<T>
+    where
+        F: FnOnce(&Client) -> Result<T, Box<dyn Error>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let client = self.client.clone();
+        tokio::task::spawn_blocking(move || f(&client).map_err(|e| e.to_string()))
+            .await?
+            .map_err(|e| e.into())
+    }
+}
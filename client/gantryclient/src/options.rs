@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/// How long the client waits for replies to each kind of Gantry request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeouts {
+    /// Catalog queries, trust root lookups and trust chain verification
+    pub query: Duration,
+    /// Putting and removing tokens
+    pub put: Duration,
+    /// Requests that start an upload or download
+    pub transfer_start: Duration,
+    /// Acknowledgement of a single uploaded chunk
+    pub chunk: Duration,
+    /// Receipt of every chunk of a download, when waiting for a complete module
+    pub download: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            query: Duration::from_millis(700),
+            put: Duration::from_millis(100),
+            transfer_start: Duration::from_millis(100),
+            chunk: Duration::from_millis(2000),
+            download: Duration::from_secs(60),
+        }
+    }
+}
+
+/// How failed reads (queries, trust lookups, verification, exports, the audit log and
+/// server and module information) are retried. Requests that change the registry or
+/// start a transfer are never retried, since a request whose reply was lost may already
+/// have taken effect
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// The total number of attempts made for a request, including the first
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled for each subsequent retry
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy that never retries
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            backoff: Duration::from_millis(0),
+        }
+    }
+
+    /// The delay to wait before making the given (1-based) attempt
+    pub(crate) fn delay_before(&self, attempt: u32) -> Duration {
+        if attempt <= 1 {
+            Duration::from_millis(0)
+        } else {
+            self.backoff * 2u32.saturating_pow(attempt - 2)
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::none()
+    }
+}
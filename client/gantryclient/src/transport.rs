@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/// A handler invoked with the payload of each message received on a subscription
+pub type MessageHandler = Box<dyn Fn(&[u8]) + Sync + Send>;
+
+/// The message broker connection over which the Gantry protocol is spoken. The client
+/// uses NATS by default, but any broker that supports request/reply and subscriptions
+/// (such as the in-process broker used by the server's integration tests) can be used
+pub trait Transport: Sync + Send {
+    /// Publishes a message and waits for a single reply
+    fn request(
+        &self,
+        subject: &str,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Box<dyn ::std::error::Error>>;
+
+    /// Publishes a message without waiting for a reply
+    fn publish(&self, subject: &str, payload: &[u8]) -> Result<(), Box<dyn ::std::error::Error>>;
+
+    /// Invokes the handler for every message published on the given subject, returning an
+    /// identifier with which to unsubscribe
+    fn subscribe(
+        &self,
+        subject: &str,
+        handler: MessageHandler,
+    ) -> Result<u64, Box<dyn ::std::error::Error>>;
+
+    /// Cancels a subscription, after which its handler is no longer invoked
+    fn unsubscribe(&self, id: u64) -> Result<(), Box<dyn ::std::error::Error>>;
+}
+
+impl Transport for natsclient::Client {
+    fn request(
+        &self,
+        subject: &str,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+        let reply = natsclient::Client::request(self, subject, payload, timeout)?;
+        Ok(reply.payload)
+    }
+
+    fn publish(&self, subject: &str, payload: &[u8]) -> Result<(), Box<dyn ::std::error::Error>> {
+        natsclient::Client::publish(self, subject, payload, None)?;
+        Ok(())
+    }
+
+    fn subscribe(
+        &self,
+        subject: &str,
+        handler: MessageHandler,
+    ) -> Result<u64, Box<dyn ::std::error::Error>> {
+        let sid = natsclient::Client::subscribe(self, subject, move |msg| {
+            handler(msg.payload.as_ref());
+            Ok(())
+        })?;
+        Ok(sid)
+    }
+
+    fn unsubscribe(&self, id: u64) -> Result<(), Box<dyn ::std::error::Error>> {
+        natsclient::Client::unsubscribe(self, id)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! Catalog archives, used to back up a registry or promote its contents to another.
+//! An archive is a gzipped tarball holding a `manifest.json` that lists every exported
+//! token revision, in the order in which they must be replayed, along with the bytes of
+//! any exported actor modules beneath `modules/`
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use gantryclient::{cache::digest_of, Client, ExportedToken, QueryType, Token};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+const MANIFEST: &str = "manifest.json";
+const MODULES_DIR: &str = "modules";
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    /// Operators first, then accounts, then actors, so that every token's issuer is
+    /// already in the catalog by the time the token is replayed
+    tokens: Vec<ExportedToken>,
+    modules: Vec<ModuleEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleEntry {
+    pub subject: String,
+    pub bytes: u64,
+    /// The lowercase hex SHA-256 digest of the module
+    pub digest: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportSummary {
+    pub path: String,
+    pub tokens: usize,
+    pub modules: Vec<ModuleEntry>,
+    /// Actors whose modules could not be downloaded
+    pub missing_modules: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportSummary {
+    pub path: String,
+    pub tokens: usize,
+    pub modules: usize,
+    pub failures: Vec<ImportFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportFailure {
+    pub subject: String,
+    pub revision: Option<u64>,
+    pub error: String,
+}
+
+/// Writes every token revision in the catalog, and optionally every actor module, to an archive
+pub fn export(
+    client: &Client,
+    path: &Path,
+    include_modules: bool,
+) -> Result<ExportSummary, Box<dyn ::std::error::Error>> {
+    let mut tokens = Vec::new();
+    tokens.extend(client.export_tokens(QueryType::Operator)?.tokens);
+    tokens.extend(client.export_tokens(QueryType::Account)?.tokens);
+    let actor_tokens = client.export_tokens(QueryType::Actor)?.tokens;
+    let mut actors: Vec<String> = actor_tokens.iter().map(|t| t.subject.to_string()).collect();
+    actors.dedup();
+    tokens.extend(actor_tokens);
+
+    let mut modules = Vec::new();
+    let mut missing_modules = Vec::new();
+    let mut module_bytes = Vec::new();
+    if include_modules {
+        for actor in actors {
+            match client.download_actor_bytes(&actor) {
+                Ok(bytes) => {
+                    modules.push(ModuleEntry {
+                        subject: actor.to_string(),
+                        bytes: bytes.len() as u64,
+                        digest: digest_of(&bytes),
+                    });
+                    module_bytes.push(bytes);
+                }
+                Err(e) => {
+                    warn!("Unable to export the module for {}: {}", actor, e);
+                    missing_modules.push(actor);
+                }
+            }
+        }
+    }
+
+    let manifest = Manifest {
+        version: ARCHIVE_VERSION,
+        tokens,
+        modules,
+    };
+    let mut builder = tar::Builder::new(GzEncoder::new(File::create(path)?, Compression::default()));
+    append(&mut builder, MANIFEST, &serde_json::to_vec_pretty(&manifest)?)?;
+    for (entry, bytes) in manifest.modules.iter().zip(module_bytes.iter()) {
+        append(&mut builder, &module_path(&entry.subject), bytes)?;
+    }
+    builder.into_inner()?.finish()?;
+
+    Ok(ExportSummary {
+        path: path.to_string_lossy().to_string(),
+        tokens: manifest.tokens.len(),
+        modules: manifest.modules,
+        missing_modules,
+    })
+}
+
+/// Replays an archive into the registry. Every token goes through the usual put path and
+/// so is validated afresh; a token that is rejected is reported without stopping the import
+pub fn import(client: &Client, path: &Path) -> Result<ImportSummary, Box<dyn ::std::error::Error>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(path)?));
+    let mut files = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf)?;
+        files.insert(name, buf);
+    }
+    let manifest: Manifest = serde_json::from_slice(
+        files
+            .get(MANIFEST)
+            .ok_or("The archive does not contain a manifest")?,
+    )?;
+    if manifest.version > ARCHIVE_VERSION {
+        return Err(format!("Unsupported archive version {}", manifest.version).into());
+    }
+
+    let mut summary = ImportSummary {
+        path: path.to_string_lossy().to_string(),
+        tokens: 0,
+        modules: 0,
+        failures: Vec::new(),
+    };
+    for exported in &manifest.tokens {
+        let token = Token {
+            raw_token: exported.raw_token.to_string(),
+            decoded_token_json: "".to_string(),
+            validation_result: None,
+            received_at: 0,
+        };
+        match client.put_token(&token) {
+            Ok(_) => summary.tokens += 1,
+            Err(e) => summary.failures.push(ImportFailure {
+                subject: exported.subject.to_string(),
+                revision: Some(exported.revision),
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    for module in &manifest.modules {
+        let res = match files.get(&module_path(&module.subject)) {
+            Some(bytes) if digest_of(bytes) == module.digest => client
+                .upload_actor(&module.subject, bytes)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            Some(_) => Err("Module digest does not match the manifest".to_string()),
+            None => Err("Module is missing from the archive".to_string()),
+        };
+        match res {
+            Ok(_) => summary.modules += 1,
+            Err(error) => summary.failures.push(ImportFailure {
+                subject: module.subject.to_string(),
+                revision: None,
+                error,
+            }),
+        }
+    }
+
+    Ok(summary)
+}
+
+fn append<W: std::io::Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    bytes: &[u8],
+) -> Result<(), Box<dyn ::std::error::Error>> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, bytes)?;
+    Ok(())
+}
+
+fn module_path(subject: &str) -> String {
+    format!("{}/{}.wasm", MODULES_DIR, subject)
+}
@@ -0,0 +1,230 @@
+//! Named connection contexts, stored in `~/.gantry/contexts.yaml`. Each context holds
+//! the server URLs of a registry and, optionally, the path of a NATS credentials file
+//! containing the user JWT and seed. Seeds are never written to the contexts file itself;
+//! when a context is created from a JWT and seed, they are written to a credentials file
+//! beneath `~/.gantry/credentials/` that only the current user can read
+
+use gantryclient::ConnectionConfiguration;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const CONTEXTS_FILE: &str = "contexts.yaml";
+const CREDENTIALS_DIR: &str = "credentials";
+const LEGACY_CONFIG_FILE: &str = "config.yaml";
+pub const CONTEXT_ENV_VAR: &str = "GANTRY_CONTEXT";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Contexts {
+    pub current: Option<String>,
+    #[serde(default)]
+    pub contexts: BTreeMap<String, Context>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Context {
+    pub server_urls: Vec<String>,
+    /// A NATS credentials file holding the user JWT and seed. Connections are anonymous without one
+    pub credentials: Option<PathBuf>,
+}
+
+/// A context as reported by the CLI's commands
+#[derive(Debug, Clone, Serialize)]
+pub struct ContextSummary {
+    pub name: String,
+    pub current: bool,
+    pub server_urls: Vec<String>,
+    pub credentials: Option<PathBuf>,
+}
+
+impl Context {
+    pub fn connection_config(&self) -> Result<ConnectionConfiguration, Box<dyn ::std::error::Error>> {
+        let (user_jwt, user_seed) = match self.credentials {
+            Some(ref path) => read_creds(path)?,
+            None => (String::new(), String::new()),
+        };
+        Ok(ConnectionConfiguration {
+            server_urls: self.server_urls.clone(),
+            user_jwt,
+            user_seed,
+        })
+    }
+}
+
+impl Contexts {
+    pub fn load() -> Result<Contexts, Box<dyn ::std::error::Error>> {
+        let path = gantry_dir()?.join(CONTEXTS_FILE);
+        if !path.exists() {
+            return Ok(Contexts::default());
+        }
+        let mut buf = Vec::new();
+        File::open(path)?.read_to_end(&mut buf)?;
+        Ok(serde_yaml::from_slice(&buf)?)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn ::std::error::Error>> {
+        let dir = gantry_dir()?;
+        fs::create_dir_all(&dir)?;
+        let mut file = File::create(dir.join(CONTEXTS_FILE))?;
+        file.write_all(&serde_yaml::to_vec(self)?)?;
+        Ok(())
+    }
+
+    pub fn summary(&self, name: &str) -> Option<ContextSummary> {
+        self.contexts.get(name).map(|ctx| ContextSummary {
+            name: name.to_string(),
+            current: self.current.as_deref() == Some(name),
+            server_urls: ctx.server_urls.clone(),
+            credentials: ctx.credentials.clone(),
+        })
+    }
+
+    pub fn summaries(&self) -> Vec<ContextSummary> {
+        self.contexts
+            .keys()
+            .filter_map(|name| self.summary(name))
+            .collect()
+    }
+
+    /// Adds (or replaces) a context, making it current if there is no current context
+    pub fn add(&mut self, name: &str, context: Context) {
+        self.contexts.insert(name.to_string(), context);
+        if self.current.is_none() {
+            self.current = Some(name.to_string());
+        }
+    }
+
+    pub fn use_context(&mut self, name: &str) -> Result<(), Box<dyn ::std::error::Error>> {
+        if !self.contexts.contains_key(name) {
+            return Err(format!("No context named '{}'", name).into());
+        }
+        self.current = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Removes a context along with any credentials file that was written for it
+    pub fn remove(&mut self, name: &str) -> Result<Context, Box<dyn ::std::error::Error>> {
+        let context = self
+            .contexts
+            .remove(name)
+            .ok_or_else(|| format!("No context named '{}'", name))?;
+        if let Some(ref creds) = context.credentials {
+            if *creds == owned_creds_path(name)? && creds.exists() {
+                fs::remove_file(creds)?;
+            }
+        }
+        if self.current.as_deref() == Some(name) {
+            self.current = None;
+        }
+        Ok(context)
+    }
+
+    /// Resolves the connection configuration to use. The context is chosen from the
+    /// explicitly selected name, then `GANTRY_CONTEXT`, then the current context. With
+    /// no contexts at all, a configuration written by older versions of `gantry login`
+    /// is honored. Returns `None` if there is nothing to connect with
+    pub fn resolve(
+        &self,
+        selected: Option<&str>,
+    ) -> Result<Option<ConnectionConfiguration>, Box<dyn ::std::error::Error>> {
+        let env = std::env::var(CONTEXT_ENV_VAR).ok();
+        let name = selected
+            .map(|s| s.to_string())
+            .or(env)
+            .or_else(|| self.current.clone());
+        match name {
+            Some(name) => match self.contexts.get(&name) {
+                Some(ctx) => Ok(Some(ctx.connection_config()?)),
+                None => Err(format!("No context named '{}'", name).into()),
+            },
+            None => legacy_config(),
+        }
+    }
+}
+
+/// Writes a NATS credentials file for the named context, readable only by the current user
+pub fn store_creds(name: &str, jwt: &str, seed: &str) -> Result<PathBuf, Box<dyn ::std::error::Error>> {
+    let path = owned_creds_path(name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+        restrict_dir(dir)?;
+    }
+    let mut opts = OpenOptions::new();
+    opts.create(true).write(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o600);
+    }
+    let mut file = opts.open(&path)?;
+    write!(
+        file,
+        "-----BEGIN NATS USER JWT-----\n{}\n------END NATS USER JWT------\n\n\
+         -----BEGIN USER NKEY SEED-----\n{}\n------END USER NKEY SEED------\n",
+        jwt.trim(),
+        seed.trim()
+    )?;
+    Ok(path)
+}
+
+/// Reads the user JWT and seed from a NATS credentials file
+pub fn read_creds(path: &Path) -> Result<(String, String), Box<dyn ::std::error::Error>> {
+    let mut raw = String::new();
+    File::open(path)
+        .map_err(|e| format!("Unable to read credentials file {}: {}", path.display(), e))?
+        .read_to_string(&mut raw)?;
+    let jwt = section(&raw, "NATS USER JWT");
+    let seed = section(&raw, "USER NKEY SEED");
+    match (jwt, seed) {
+        (Some(jwt), Some(seed)) => Ok((jwt, seed)),
+        _ => Err(format!(
+            "{} is not a valid NATS credentials file",
+            path.display()
+        )
+        .into()),
+    }
+}
+
+/// Finds the first non-empty line following a `-----BEGIN {name}-----` marker
+fn section(raw: &str, name: &str) -> Option<String> {
+    let mut lines = raw.lines().map(|l| l.trim());
+    lines.find(|l| l.starts_with("-----BEGIN") && l.contains(name))?;
+    lines
+        .find(|l| !l.is_empty())
+        .filter(|l| !l.starts_with("---"))
+        .map(|l| l.to_string())
+}
+
+fn legacy_config() -> Result<Option<ConnectionConfiguration>, Box<dyn ::std::error::Error>> {
+    let path = gantry_dir()?.join(LEGACY_CONFIG_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut buf = Vec::new();
+    File::open(path)?.read_to_end(&mut buf)?;
+    Ok(Some(serde_yaml::from_slice(&buf)?))
+}
+
+fn owned_creds_path(name: &str) -> Result<PathBuf, Box<dyn ::std::error::Error>> {
+    Ok(gantry_dir()?
+        .join(CREDENTIALS_DIR)
+        .join(format!("{}.creds", name)))
+}
+
+pub fn gantry_dir() -> Result<PathBuf, Box<dyn ::std::error::Error>> {
+    let home = dirs::home_dir().ok_or("Unable to locate the home directory")?;
+    Ok(home.join(".gantry"))
+}
+
+#[cfg(unix)]
+fn restrict_dir(dir: &Path) -> Result<(), Box<dyn ::std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_dir(_dir: &Path) -> Result<(), Box<dyn ::std::error::Error>> {
+    Ok(())
+}
@@ -1,14 +1,27 @@
 #[macro_use]
 extern crate log;
 
+#[macro_use]
+extern crate serde_derive;
+
+mod archive;
+mod context;
+mod output;
+
+use context::{Context, Contexts};
+use output::{emit, OutputFormat, TransferSummary};
 use gantry_protocol as protocol;
-use gantryclient::{Chunks, Client, ConnectionConfiguration, CHUNK_SIZE};
+use gantryclient::{
+    AuditQuery, Chunks, Client, ClientBuilder, ContentType, FileChunk, ModuleCache, CHUNK_SIZE,
+    PROTOCOL_VERSION,
+};
 use protocol::catalog::*;
 use std::io::Read;
 use std::io::{self, Write};
 use std::{
     fs::{File, OpenOptions},
     path::{Path, PathBuf}, str::FromStr, sync::{RwLock, Arc},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
@@ -22,10 +35,32 @@ extern crate serde_yaml;
     name = "gantry", 
     about = "A command line utility for accessing a Gantry waSCC registry")]
 struct Cli {
+    #[structopt(flatten)]
+    options: GlobalOptions,
+
     #[structopt(flatten)]
     command: CliCommand,
 }
 
+#[derive(Debug, StructOpt, Clone)]
+struct GlobalOptions {
+    /// The named connection context to use. Defaults to $GANTRY_CONTEXT, then the current context
+    #[structopt(long = "context", global = true)]
+    context: Option<String>,
+
+    /// The format of command results: json, yaml or table
+    #[structopt(long = "output", global = true, default_value = "table")]
+    output: OutputFormat,
+
+    /// The encoding of messages sent to the registry: msgpack or protobuf
+    #[structopt(long = "content-type", global = true, default_value = "msgpack")]
+    content_type: ContentType,
+
+    /// Transfer modules without compression, even if the registry supports it
+    #[structopt(long = "no-compression", global = true)]
+    no_compression: bool,
+}
+
 #[derive(Debug, Clone, StructOpt)]
 enum CliCommand {
     /// Query the Gantry registry
@@ -34,16 +69,130 @@ enum CliCommand {
     /// Puts a token in the registry
     #[structopt(name = "put")]
     Put(PutCommand),
+    /// Revokes a subject, removing it from the registry
+    #[structopt(name = "delete")]
+    Delete(ChangeCommand),
+    /// Reinstates a revoked subject
+    #[structopt(name = "reinstate")]
+    Reinstate(ChangeCommand),
+    /// Removes a single revision of a subject without revoking the subject
+    #[structopt(name = "unregister")]
+    Unregister(UnregisterCommand),
+    /// Verifies the trust chain of a token from its subject up to a trusted operator
+    #[structopt(name = "verify")]
+    Verify(VerifyCommand),
     /// Downloads an actor module from the registry
     #[structopt(name = "download")]
     Download(DownloadCommand),
     /// Uploads an actor module to the registry
     #[structopt(name = "upload")]
     Upload(UploadCommand),
+    /// Registers the token embedded in a signed actor module and uploads the module
+    #[structopt(name = "publish")]
+    Publish(PublishCommand),
+    /// Prints the registry's change events as they happen, until interrupted
+    #[structopt(name = "watch")]
+    Watch(WatchCommand),
+    /// Queries the audit log of registry mutations
+    #[structopt(name = "audit")]
+    Audit(AuditCommand),
+    /// Shows the server's protocol version and the optional features it supports
+    #[structopt(name = "info")]
+    Info,
+    /// Exports every token in the registry, and optionally every actor module, to an archive
+    #[structopt(name = "export")]
+    Export(ExportCommand),
+    /// Replays an exported archive into the registry
+    #[structopt(name = "import")]
+    Import(ImportCommand),
+    /// Manages the local cache of downloaded actor modules
+    #[structopt(name = "cache")]
+    Cache(CacheCommand),
+    /// Manages named connection contexts
+    #[structopt(name = "context")]
+    Context(ContextCommand),
     /// Stores connection information to a Gantry server
-    Login,
+    #[structopt(name = "login")]
+    Login(LoginCommand),
     /// Removes stored connection information, if it exists
     Logout,
+    /// Creates a short-lived bearer token for the registry's HTTP gateway
+    #[structopt(name = "http-token")]
+    HttpToken(HttpTokenCommand),
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct LoginCommand {
+    /// The server URLs (comma-delimited)
+    #[structopt(short = "u", long = "url", env = "GANTRY_URL")]
+    urls: Option<String>,
+
+    /// A NATS credentials file containing the user JWT and seed
+    #[structopt(long = "creds", env = "GANTRY_CREDS", parse(from_os_str), conflicts_with_all = &["jwt", "seed"])]
+    creds: Option<PathBuf>,
+
+    /// The user JWT
+    #[structopt(long = "jwt", env = "GANTRY_JWT", hide_env_values = true)]
+    jwt: Option<String>,
+
+    /// The user seed
+    #[structopt(long = "seed", env = "GANTRY_SEED", hide_env_values = true)]
+    seed: Option<String>,
+
+    /// Verifies that the registry can be reached by querying the catalog. On its own,
+    /// checks the selected context without changing it
+    #[structopt(long = "check")]
+    check: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct HttpTokenCommand {
+    /// The seed of an account or operator in the registry's trust chain
+    #[structopt(long = "seed", env = "GANTRY_SEED", hide_env_values = true)]
+    seed: String,
+
+    /// How long the token is valid for, at most an hour
+    #[structopt(long = "ttl", default_value = "15m", parse(try_from_str = humantime::parse_duration))]
+    ttl: Duration,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+enum ContextCommand {
+    /// Adds a context, or replaces an existing context of the same name
+    #[structopt(name = "add")]
+    Add {
+        /// The name of the context
+        name: String,
+
+        /// The server URLs (comma-delimited)
+        #[structopt(short = "u", long = "url")]
+        urls: String,
+
+        /// A NATS credentials file containing the user JWT and seed
+        #[structopt(long = "creds", parse(from_os_str), conflicts_with_all = &["jwt", "seed"])]
+        creds: Option<PathBuf>,
+
+        /// The user JWT. Stored, along with the seed, in a credentials file only you can read
+        #[structopt(long = "jwt", requires = "seed")]
+        jwt: Option<String>,
+
+        /// The user seed
+        #[structopt(long = "seed", requires = "jwt")]
+        seed: Option<String>,
+    },
+    /// Makes a context the current context
+    #[structopt(name = "use")]
+    Use {
+        name: String,
+    },
+    /// Lists the contexts, marking the current context
+    #[structopt(name = "list")]
+    List,
+    /// Removes a context and any credentials stored for it
+    #[structopt(name = "remove")]
+    Remove {
+        name: String,
+    },
 }
 
 #[derive(Debug, Clone, StructOpt)]
@@ -51,6 +200,92 @@ struct DownloadCommand {
     /// The public key of the actor to download
     #[structopt(short = "a", long = "actor")]
     actor: String,
+
+    /// Only download the actor if it belongs to this operator's trust chain
+    #[structopt(short = "o", long = "operator")]
+    operator: Option<String>,
+
+    /// Serve the module from the local cache (~/.gantry/cache) when the catalog reports
+    /// that the cached copy is current, caching it otherwise. Not supported together
+    /// with --operator
+    #[structopt(short = "c", long = "cache")]
+    cache: bool,
+
+    /// Continue an interrupted download, fetching only the bytes missing from the
+    /// existing <actor>.wasm. Not supported together with --operator or --cache
+    #[structopt(short = "r", long = "resume")]
+    resume: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct PublishCommand {
+    /// Path to the signed actor module
+    #[structopt(parse(from_os_str))]
+    actor_path: PathBuf,
+
+    /// The seed of the actor's issuer, of its operator, or of one of the operator's
+    /// signers, with which the registration is rolled back if the upload fails
+    #[structopt(long = "seed", env = "GANTRY_SEED", hide_env_values = true)]
+    seed: String,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct WatchCommand {
+    /// Only print events for this subject
+    #[structopt(short = "s", long = "subject")]
+    subject: Option<String>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct AuditCommand {
+    /// Only show records for this subject
+    #[structopt(short = "s", long = "subject")]
+    subject: Option<String>,
+
+    /// Only show records from this time on: an RFC 3339 time, seconds since the epoch,
+    /// or a duration before now such as 24h
+    #[structopt(long = "since", parse(try_from_str = parse_time))]
+    since: Option<u64>,
+
+    /// Only show records from before this time, in the same formats as --since
+    #[structopt(long = "until", parse(try_from_str = parse_time))]
+    until: Option<u64>,
+
+    /// Only show this many of the most recent matching records
+    #[structopt(short = "n", long = "limit")]
+    limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct ExportCommand {
+    /// Path of the archive to write
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    file: PathBuf,
+
+    /// Include the bytes of every actor module in the archive
+    #[structopt(short = "m", long = "modules")]
+    modules: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct ImportCommand {
+    /// Path of the archive to import
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    file: PathBuf,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+enum CacheCommand {
+    /// Lists the cached actor modules, most recently used first
+    #[structopt(name = "ls")]
+    List,
+    /// Evicts the least recently used modules until the cache fits within the given size
+    #[structopt(name = "prune")]
+    Prune {
+        /// The maximum size of the cache in bytes. Defaults to emptying the cache
+        #[structopt(short = "m", long = "max-bytes", default_value = "0")]
+        max_bytes: u64,
+    },
 }
 
 #[derive(Debug, Clone, StructOpt)]
@@ -60,6 +295,21 @@ struct UploadCommand {
     actor_path: PathBuf,
 }
 
+#[derive(Debug, Clone, StructOpt)]
+struct VerifyCommand {
+    /// The public key of the actor, account, or operator to verify
+    #[structopt(short = "s", long = "subject")]
+    subject: String,
+
+    /// The revision to verify. Defaults to the latest revision
+    #[structopt(short = "r", long = "revision")]
+    revision: Option<u64>,
+
+    /// Require the chain to terminate at this operator
+    #[structopt(short = "o", long = "operator")]
+    operator: Option<String>,
+}
+
 #[derive(Debug, Clone, StructOpt)]
 struct PutCommand {
     /// The raw, encoded token to insert
@@ -67,6 +317,28 @@ struct PutCommand {
     token: String,
 }
 
+#[derive(Debug, Clone, StructOpt)]
+struct ChangeCommand {
+    /// The public key of the actor, account, or operator to change
+    #[structopt(short = "s", long = "subject")]
+    subject: String,
+
+    /// The seed of the subject's issuer, of its operator, or of one of the operator's
+    /// signers, with which the change is proven
+    #[structopt(long = "seed", env = "GANTRY_SEED", hide_env_values = true)]
+    seed: String,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct UnregisterCommand {
+    #[structopt(flatten)]
+    change: ChangeCommand,
+
+    /// The revision to remove
+    #[structopt(short = "r", long = "revision")]
+    revision: u64,
+}
+
 #[derive(Debug, Clone, StructOpt)]
 struct GetCommand {
     /// The kind of tokens to retrieve
@@ -76,6 +348,10 @@ struct GetCommand {
     /// Optionally filter token results by issuer
     #[structopt(short = "i", long = "issuer")]
     issuer: Option<String>,    
+
+    /// Optionally restrict token results to an operator's trust chain
+    #[structopt(short = "o", long = "operator")]
+    operator: Option<String>,
 }
 
 #[derive(Debug, Clone, StructOpt, PartialEq)]
@@ -103,6 +379,21 @@ impl FromStr for TokenKind {
      }    
 }
 
+fn parse_time(src: &str) -> Result<u64, String> {
+    if let Ok(secs) = src.parse::<u64>() {
+        return Ok(secs);
+    }
+    let time = match humantime::parse_duration(src) {
+        Ok(ago) => SystemTime::now() - ago,
+        Err(_) => humantime::parse_rfc3339_weak(src)
+            .map_err(|e| format!("Invalid time '{}': {}", src, e))?,
+    };
+    Ok(time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0))
+}
+
 fn to_catalog_query_type(cmd: &GetCommand) -> QueryType {
     match cmd.kind {
         TokenKind::Actor => QueryType::Actor,
@@ -111,84 +402,140 @@ fn to_catalog_query_type(cmd: &GetCommand) -> QueryType {
     }
 }
 
-fn handle_command(cmd: CliCommand) -> Result<(), Box<dyn ::std::error::Error>> {
+fn handle_command(cmd: CliCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
     match cmd {
-        CliCommand::Get(get_cmd) => query(get_cmd),
-        CliCommand::Put(put_cmd) => put(put_cmd),
-        CliCommand::Download(download_cmd) => download(download_cmd),
-        CliCommand::Upload(upload_cmd) => upload(upload_cmd),
-        CliCommand::Login => login(),
-        CliCommand::Logout => logout(),
+        CliCommand::Get(get_cmd) => query(get_cmd, opts),
+        CliCommand::Put(put_cmd) => put(put_cmd, opts),
+        CliCommand::Delete(delete_cmd) => delete(delete_cmd, opts),
+        CliCommand::Reinstate(reinstate_cmd) => reinstate(reinstate_cmd, opts),
+        CliCommand::Unregister(unregister_cmd) => unregister(unregister_cmd, opts),
+        CliCommand::Verify(verify_cmd) => verify(verify_cmd, opts),
+        CliCommand::Download(download_cmd) => download(download_cmd, opts),
+        CliCommand::Upload(upload_cmd) => upload(upload_cmd, opts),
+        CliCommand::Publish(publish_cmd) => publish(publish_cmd, opts),
+        CliCommand::Watch(watch_cmd) => watch(watch_cmd, opts),
+        CliCommand::Audit(audit_cmd) => audit(audit_cmd, opts),
+        CliCommand::Info => info(opts),
+        CliCommand::Export(export_cmd) => export(export_cmd, opts),
+        CliCommand::Import(import_cmd) => import(import_cmd, opts),
+        CliCommand::Cache(cache_cmd) => cache(cache_cmd, opts),
+        CliCommand::Context(context_cmd) => manage_context(context_cmd, opts),
+        CliCommand::Login(login_cmd) => login(login_cmd, opts),
+        CliCommand::Logout => logout(opts),
+        CliCommand::HttpToken(token_cmd) => {
+            println!("{}", gantryclient::bearer_token(&token_cmd.seed, token_cmd.ttl)?);
+            Ok(())
+        }
     }
 }
 
-fn query(cmd: GetCommand) -> Result<(), Box<dyn ::std::error::Error>> {
+fn query(cmd: GetCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
     let query = CatalogQuery {
         query_type: to_catalog_query_type(&cmd),
         issuer: cmd.issuer,
+        operator: cmd.operator,
+        subject: None,
     };
-    let client = client();
+    let client = client(opts)?;
     let results = client.query_catalog(&query)?;
-    if results.results.is_empty() {
-        println!("No results.");
-        return Ok(());
-    }
-
-    let mut table = term_table::Table::new();
-    table.max_column_width = 60;
-
-    table.style = term_table::TableStyle::extended();
-    table.add_row(term_table::row::Row::new(vec![
-        term_table::table_cell::TableCell::new_with_alignment(
-            "Gantry Query Results",
-            2,
-            term_table::table_cell::Alignment::Center,
-        ),
-    ]));
-    table.add_row(term_table::row::Row::new(vec![
-        term_table::table_cell::TableCell::new_with_alignment(
-            "Name",
-            1,
-            term_table::table_cell::Alignment::Center,
-        ),
-        term_table::table_cell::TableCell::new_with_alignment(
-            "Subject / Issuer",
-            1,
-            term_table::table_cell::Alignment::Center,
-        ),
-    ]));
-
-    for res in results.results {
-        table.add_row(term_table::row::Row::new(vec![
-            term_table::table_cell::TableCell::new_with_alignment(
-                res.name,
-                1,
-                term_table::table_cell::Alignment::Center,
-            ),
-            term_table::table_cell::TableCell::new_with_alignment(
-                format!("{}\n{}", res.subject, res.issuer),
-                1,
-                term_table::table_cell::Alignment::Center,
-            ),
-        ]));
-    }
-    println!("{}", table.render());
-    Ok(())
+    let describe_modules = cmd.kind == TokenKind::Actor
+        && client
+            .server_info()
+            .map(|info| info.supports(protocol::meta::FEATURE_MODULE_INFO))
+            .unwrap_or(false);
+    let listing = output::QueryListing {
+        results: results
+            .results
+            .into_iter()
+            .map(|result| {
+                let module = if describe_modules {
+                    client
+                        .module_info(&result.subject, None)
+                        .map_err(|e| warn!("Unable to describe the module of {}: {}", result.subject, e))
+                        .ok()
+                } else {
+                    None
+                };
+                output::QueryRow { result, module }
+            })
+            .collect(),
+    };
+    emit(opts.output, &listing, output::query_table)
 }
 
-fn put(cmd: PutCommand) -> Result<(), Box<dyn ::std::error::Error>> {
+fn put(cmd: PutCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
     let token = Token {
         raw_token: cmd.token.clone(),
         decoded_token_json: "".to_string(),
         validation_result: None,
+        received_at: 0,
     };
-    let client = client();
-    client.put_token(&token)?;
-    Ok(())
+    let client = client(opts)?;
+    let res = client.put_token(&token)?;
+    emit(opts.output, &res, |res| {
+        format!(
+            "Token '{}' with issuer {}, subject {} registered.",
+            res.name, res.issuer, res.subject
+        )
+    })
 }
 
-fn download(cmd: DownloadCommand) -> Result<(), Box<dyn ::std::error::Error>> {    
-    let client = client();
+fn delete(cmd: ChangeCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let client = client(opts)?;
+    let res = client.remove_token(&cmd.subject, &cmd.seed)?;
+    emit(opts.output, &res, |res| {
+        format!("Token '{}' with subject {} revoked.", res.name, res.subject)
+    })
+}
+
+fn reinstate(cmd: ChangeCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let client = client(opts)?;
+    let res = client.reinstate_token(&cmd.subject, &cmd.seed)?;
+    emit(opts.output, &res, |res| {
+        format!("Token '{}' with subject {} reinstated.", res.name, res.subject)
+    })
+}
+
+fn unregister(
+    cmd: UnregisterCommand,
+    opts: &GlobalOptions,
+) -> Result<(), Box<dyn ::std::error::Error>> {
+    let client = client(opts)?;
+    let res = client.unregister_token(&cmd.change.subject, cmd.revision, &cmd.change.seed)?;
+    emit(opts.output, &res, |res| {
+        format!(
+            "Revision {} of token '{}' with subject {} unregistered.",
+            cmd.revision, res.name, res.subject
+        )
+    })
+}
+
+fn verify(cmd: VerifyCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let req = ChainVerificationRequest {
+        subject: cmd.subject,
+        revision: cmd.revision,
+        operator: cmd.operator,
+        as_of: 0,
+    };
+    let client = client(opts)?;
+    let report = client.verify_chain_with(&req)?;
+    emit(opts.output, &report, output::verify_table)
+}
+
+fn download(cmd: DownloadCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {    
+    if cmd.cache {
+        if cmd.operator.is_some() {
+            return Err("Cached downloads cannot be restricted to an operator".into());
+        }
+        return download_cached(cmd, opts);
+    }
+    if cmd.resume {
+        if cmd.operator.is_some() {
+            return Err("Resumed downloads cannot be restricted to an operator".into());
+        }
+        return download_resumed(cmd, opts);
+    }
+    let client = client(opts)?;
     use indicatif::{ProgressBar, ProgressStyle};
 
     let pb = ProgressBar::new(0);
@@ -201,7 +548,7 @@ fn download(cmd: DownloadCommand) -> Result<(), Box<dyn ::std::error::Error>> {
     let (s,r) = crossbeam::channel::unbounded();
     let bytevec = Arc::new(RwLock::new(Vec::new()));
     let b = bytevec.clone();
-    let _ack = client.download_actor(&cmd.actor,  move |chunk| {          
+    let handler = move |chunk: FileChunk| {          
         bytevec.write().unwrap().extend_from_slice(&chunk.chunk_bytes);
         pb.set_length(chunk.total_bytes);
         pb.set_position(chunk.sequence_no * chunk.chunk_size);
@@ -210,7 +557,11 @@ fn download(cmd: DownloadCommand) -> Result<(), Box<dyn ::std::error::Error>> {
             s.send(true).unwrap();            
         }
         Ok(())        
-    })?;
+    };
+    let _ack = match cmd.operator {
+        Some(ref operator) => client.download_operator_actor(operator, &cmd.actor, handler)?,
+        None => client.download_actor(&cmd.actor, handler)?,
+    };
     let _ = r.recv().unwrap();
     let vec = b.read().unwrap();
     let mut file = OpenOptions::new()
@@ -221,118 +572,431 @@ fn download(cmd: DownloadCommand) -> Result<(), Box<dyn ::std::error::Error>> {
 
     file.write(&vec).unwrap(); 
     file.flush()?;       
-        
+
+    let summary = transfer_summary(&client, &cmd.actor, &vec, &filename);
+    emit(opts.output, &summary, |s| output::transfer_text("Downloaded", s))
+}
+
+fn download_cached(cmd: DownloadCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let client = client_builder(opts)?.cache(module_cache()?).build()?;
+    let bytes = client.download_actor_bytes(&cmd.actor)?;
+    let filename = format!("{}.wasm", cmd.actor);
+    let mut file = File::create(&filename)?;
+    file.write_all(&bytes)?;
+    file.flush()?;
+
+    let summary = transfer_summary(&client, &cmd.actor, &bytes, &filename);
+    emit(opts.output, &summary, |s| output::transfer_text("Downloaded", s))
+}
+
+fn download_resumed(cmd: DownloadCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let client = client(opts)?;
+    let filename = format!("{}.wasm", cmd.actor);
+    client.resume_download(&cmd.actor, Path::new(&filename))?;
+    let bytes = ::std::fs::read(&filename)?;
+
+    let summary = transfer_summary(&client, &cmd.actor, &bytes, &filename);
+    emit(opts.output, &summary, |s| output::transfer_text("Downloaded", s))
+}
+
+/// Summarizes a transfer, looking up the actor's latest revision in the catalog
+fn transfer_summary(client: &Client, actor: &str, bytes: &[u8], path: &str) -> TransferSummary {
+    let revision = match client.actor_summary(actor) {
+        Ok(summary) => summary.map(|s| s.revision),
+        Err(e) => {
+            warn!("Unable to determine the revision of {}: {}", actor, e);
+            None
+        }
+    };
+    TransferSummary {
+        subject: actor.to_string(),
+        revision,
+        bytes: bytes.len() as u64,
+        digest: gantryclient::cache::digest_of(bytes),
+        path: path.to_string(),
+    }
+}
+
+fn publish(cmd: PublishCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let module = ::std::fs::read(&cmd.actor_path)?;
+    let client = client(opts)?;
+    let published = client.publish_actor(&module, Some(&cmd.seed))?;
+    emit(opts.output, &published, |p| {
+        format!(
+            "Published '{}' ({}): {} bytes, sha256 {}",
+            p.registration.name, p.registration.subject, p.bytes, p.digest
+        )
+    })
+}
+
+fn watch(cmd: WatchCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let client = client(opts)?;
+    let (s, r) = crossbeam::channel::unbounded();
+    client.watch(move |event| {
+        let _ = s.send(event);
+    })?;
+    for event in r.iter() {
+        if cmd.subject.as_ref().map_or(true, |s| *s == event.subject) {
+            output::emit_line(opts.output, &event, output::event_text)?;
+        }
+    }
     Ok(())
 }
 
-fn upload(cmd: UploadCommand) -> Result<(), Box<dyn ::std::error::Error>> {
+fn audit(cmd: AuditCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let query = AuditQuery {
+        subject: cmd.subject,
+        since: cmd.since,
+        until: cmd.until,
+        limit: cmd.limit,
+    };
+    let client = client(opts)?;
+    let log = client.audit_log(&query)?;
+    emit(opts.output, &log, output::audit_table)
+}
+
+fn info(opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let client = client(opts)?;
+    let info = client.server_info()?;
+    emit(opts.output, &info, |i| {
+        format!(
+            "Gantry server {} speaking protocol version {} (client speaks {})\nFeatures: {}\nContent types: {}",
+            i.server_version,
+            i.protocol_version,
+            PROTOCOL_VERSION,
+            i.features.join(", "),
+            i.content_types.join(", ")
+        )
+    })
+}
+
+fn export(cmd: ExportCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let client = client(opts)?;
+    let summary = archive::export(&client, &cmd.file, cmd.modules)?;
+    emit(opts.output, &summary, |s| {
+        let mut text = format!(
+            "Exported {} token(s) and {} module(s) to {}.",
+            s.tokens,
+            s.modules.len(),
+            s.path
+        );
+        for actor in &s.missing_modules {
+            text.push_str(&format!("\nNo module could be exported for {}", actor));
+        }
+        text
+    })
+}
+
+fn import(cmd: ImportCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let client = client(opts)?;
+    let summary = archive::import(&client, &cmd.file)?;
+    emit(opts.output, &summary, |s| {
+        let mut text = format!(
+            "Imported {} token(s) and {} module(s) from {}.",
+            s.tokens, s.modules, s.path
+        );
+        for f in &s.failures {
+            text.push_str(&format!("\nFailed to import {}: {}", f.subject, f.error));
+        }
+        text
+    })?;
+    if summary.failures.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} item(s) could not be imported", summary.failures.len()).into())
+    }
+}
+
+fn cache(cmd: CacheCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let cache = module_cache()?;
+    match cmd {
+        CacheCommand::List => {
+            let entries = cache.entries()?;
+            emit(opts.output, &entries, |e| output::cache_table(e))
+        }
+        CacheCommand::Prune { max_bytes } => {
+            let evicted = cache.prune(max_bytes)?;
+            emit(opts.output, &evicted, |evicted| {
+                let mut lines: Vec<String> = evicted
+                    .iter()
+                    .map(|e| format!("Evicted {} ({} bytes)", e.subject, e.size))
+                    .collect();
+                lines.push(format!("{} module(s) evicted.", evicted.len()));
+                lines.join("\n")
+            })
+        }
+    }
+}
+
+fn module_cache() -> Result<ModuleCache, Box<dyn ::std::error::Error>> {
+    let home = dirs::home_dir().ok_or("Unable to locate the home directory")?;
+    ModuleCache::new(
+        Path::join(&home, ".gantry/cache"),
+        gantryclient::cache::DEFAULT_MAX_BYTES,
+    )
+}
+
+fn upload(cmd: UploadCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
     use indicatif::{ProgressBar, ProgressStyle};
 
     let mut f = ::std::fs::File::open(&cmd.actor_path)?;
     let mut buf = Vec::new();
     f.read_to_end(&mut buf)?;
-    let claims = wascap::wasm::extract_claims(&buf)?.unwrap();
-    let fsize = f.metadata().unwrap().len();
+    let claims = wascap::wasm::extract_claims(&buf)?
+        .ok_or("The module does not contain embedded claims")?;
+    let fsize = f.metadata()?.len();
     let actor = claims.claims.subject;
 
-    println!("Uploading {}", actor);
+    if opts.output == OutputFormat::Table {
+        println!("Uploading {}", actor);
+    }
 
     let pb = ProgressBar::new(0);
     pb.set_style(ProgressStyle::default_bar()
         .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
         .progress_chars("#>-"));
 
-    pb.set_length(f.metadata()?.len());
+    pb.set_length(fsize);
 
     let req = protocol::stream::UploadRequest {
         actor: actor.to_string(),
         chunk_size: CHUNK_SIZE,
         total_bytes: fsize,
         total_chunks: fsize / CHUNK_SIZE,
+        compression: vec![],
     };
-    let client = Client::default();
+    let client = client(opts)?;
     let _ack = client.start_upload(&req)?;
 
     let f = ::std::fs::File::open(&cmd.actor_path)?;
     let chunks = Chunks::new(f, CHUNK_SIZE as usize);
-    chunks.enumerate().for_each(|(i, chunk)| {
-        let chunk = chunk.unwrap();
+    for (i, chunk) in chunks.enumerate() {
+        let chunk = chunk?;
         pb.set_position(i as u64 * CHUNK_SIZE + chunk.len() as u64);
-        client
-            .upload_chunk(
-                i as u64,
-                &actor,
-                fsize,
-                CHUNK_SIZE,
-                fsize / CHUNK_SIZE,
-                chunk,
-            )
-            .unwrap();
-    });
+        client.upload_chunk(
+            i as u64,
+            &actor,
+            CHUNK_SIZE,
+            fsize,
+            fsize / CHUNK_SIZE,
+            chunk,
+        )?;
+    }
     pb.finish_with_message("uploaded");
-    Ok(())
-}
 
-fn get_client() -> Result<Client, Box<dyn ::std::error::Error>> {
-    let file_path = Path::join(&dirs::home_dir().unwrap(), ".gantry/config.yaml");
-    let mut file = File::open(file_path)?;
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf)?;
-    let config: ConnectionConfiguration = serde_yaml::from_slice(&buf)?;
-    Ok(Client::from_config(config))
+    let summary = transfer_summary(
+        &client,
+        &actor,
+        &buf,
+        &cmd.actor_path.to_string_lossy(),
+    );
+    emit(opts.output, &summary, |s| output::transfer_text("Uploaded", s))
 }
 
-fn client() -> Client {
-    match get_client() {
-        Ok(c) => c,
-        Err(_) => Client::default(),
+/// Configures a client from the selected connection context, connecting anonymously to a
+/// local NATS server if no context has been set up
+fn client_builder(opts: &GlobalOptions) -> Result<ClientBuilder, Box<dyn ::std::error::Error>> {
+    let builder = Client::builder()
+        .content_type(opts.content_type)
+        .compression(!opts.no_compression);
+    match Contexts::load()?.resolve(opts.context.as_deref())? {
+        Some(config) if config.user_jwt.is_empty() => Ok(builder.server_urls(config.server_urls)),
+        Some(config) => Ok(builder
+            .server_urls(config.server_urls)
+            .credentials(&config.user_jwt, &config.user_seed)),
+        None => Ok(builder),
     }
 }
 
-fn logout() -> Result<(), Box<dyn ::std::error::Error>> {
-    let file_path = Path::join(&dirs::home_dir().unwrap(), ".gantry/config.yaml");
-    match ::std::fs::remove_file(file_path) {
-        Ok(_) => {
-            println!("Connection information removed.");
-            Ok(())
+fn client(opts: &GlobalOptions) -> Result<Client, Box<dyn ::std::error::Error>> {
+    client_builder(opts)?.build()
+}
+
+fn manage_context(cmd: ContextCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let mut contexts = Contexts::load()?;
+    match cmd {
+        ContextCommand::Add {
+            name,
+            urls,
+            creds,
+            jwt,
+            seed,
+        } => {
+            let credentials = credentials(&name, creds, jwt, seed)?;
+            contexts.add(
+                &name,
+                Context {
+                    server_urls: split_urls(&urls),
+                    credentials,
+                },
+            );
+            contexts.save()?;
+            emit(opts.output, &contexts.summary(&name), |_| {
+                format!("Context '{}' added.", name)
+            })
+        }
+        ContextCommand::Use { name } => {
+            contexts.use_context(&name)?;
+            contexts.save()?;
+            emit(opts.output, &contexts.summary(&name), |_| {
+                format!("Now using context '{}'.", name)
+            })
+        }
+        ContextCommand::List => emit(opts.output, &contexts.summaries(), |summaries| {
+            if summaries.is_empty() {
+                return "No contexts.".to_string();
+            }
+            summaries
+                .iter()
+                .map(|c| {
+                    format!(
+                        "{} {} ({})",
+                        if c.current { "*" } else { " " },
+                        c.name,
+                        c.server_urls.join(",")
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n")
+        }),
+        ContextCommand::Remove { name } => {
+            let summary = contexts.summary(&name);
+            contexts.remove(&name)?;
+            contexts.save()?;
+            emit(opts.output, &summary, |_| {
+                format!("Context '{}' removed.", name)
+            })
         }
-        Err(e) => Err(format!("Failed to delete configuration: {}", e).into()),
     }
 }
 
-fn login() -> Result<(), Box<dyn ::std::error::Error>> {
-    print!("Paste the user JWT for authentication to NATS: ");
-    io::stdout().flush().unwrap();
-    let jwt: String = read!("{}\n");
+/// The context named by `--context` or `GANTRY_CONTEXT`, if any
+fn selected_context(opts: &GlobalOptions) -> Option<String> {
+    opts.context
+        .clone()
+        .or_else(|| std::env::var(context::CONTEXT_ENV_VAR).ok())
+}
 
-    print!("Paste the user seed: ");
-    io::stdout().flush().unwrap();
-    let seed: String = read!("{}\n");
+fn logout(opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let mut contexts = Contexts::load()?;
+    match selected_context(opts).or_else(|| contexts.current.clone()) {
+        Some(name) => {
+            let summary = contexts.summary(&name);
+            contexts.remove(&name)?;
+            contexts.save()?;
+            emit(opts.output, &summary, |_| {
+                format!("Connection information for context '{}' removed.", name)
+            })
+        }
+        None => {
+            let file_path = context::gantry_dir()?.join("config.yaml");
+            match ::std::fs::remove_file(file_path) {
+                Ok(_) => {
+                    println!("Connection information removed.");
+                    Ok(())
+                }
+                Err(e) => Err(format!("Failed to delete configuration: {}", e).into()),
+            }
+        }
+    }
+}
 
-    print!("Enter the server URLs (comma-delimited): ");
-    io::stdout().flush().unwrap();
-    let urls: String = read!("{}\n");
+fn login(cmd: LoginCommand, opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let supplied = cmd.urls.is_some() || cmd.creds.is_some() || cmd.jwt.is_some() || cmd.seed.is_some();
+    if cmd.check && !supplied {
+        return check_login(opts);
+    }
 
-    let url_vec: Vec<String> = urls
-        .split(',')
-        .into_iter()
-        .map(|s| remove_whitespace(s))
-        .collect();
-    let config = ConnectionConfiguration {
-        server_urls: url_vec,
-        user_jwt: jwt,
-        user_seed: seed,
+    let name = selected_context(opts).unwrap_or_else(|| "default".to_string());
+    let (urls, credentials) = if supplied {
+        let urls = cmd
+            .urls
+            .ok_or("The server URLs must be supplied with --url or GANTRY_URL")?;
+        (urls, credentials(&name, cmd.creds, cmd.jwt, cmd.seed)?)
+    } else {
+        print!("Paste the user JWT for authentication to NATS: ");
+        io::stdout().flush().unwrap();
+        let jwt: String = read!("{}\n");
+
+        print!("Paste the user seed: ");
+        io::stdout().flush().unwrap();
+        let seed: String = read!("{}\n");
+
+        print!("Enter the server URLs (comma-delimited): ");
+        io::stdout().flush().unwrap();
+        let urls: String = read!("{}\n");
+
+        (urls, Some(context::store_creds(&name, &jwt, &seed)?))
     };
-    let yaml = serde_yaml::to_vec(&config)?;
-    let dir_path = Path::join(&dirs::home_dir().unwrap(), ".gantry/");
-    ::std::fs::create_dir_all(&dir_path)?;
-    let file_path = Path::join(&dir_path, "config.yaml");
-    let mut file = File::create(file_path)?;
-    file.write_all(&yaml)?;
 
-    println!("Credentials stored.");
+    let mut contexts = Contexts::load()?;
+    contexts.add(
+        &name,
+        Context {
+            server_urls: split_urls(&urls),
+            credentials,
+        },
+    );
+    contexts.use_context(&name)?;
+    contexts.save()?;
+
+    if cmd.check {
+        return check_login(&GlobalOptions {
+            context: Some(name),
+            output: opts.output,
+            content_type: opts.content_type,
+            no_compression: opts.no_compression,
+        });
+    }
+    emit(opts.output, &contexts.summary(&name), |_| {
+        format!("Credentials stored in context '{}'.", name)
+    })
+}
 
-    Ok(())
+/// Determines the credentials file for a context: either an existing NATS credentials
+/// file, which must be readable, or one written from the supplied JWT and seed
+fn credentials(
+    name: &str,
+    creds: Option<PathBuf>,
+    jwt: Option<String>,
+    seed: Option<String>,
+) -> Result<Option<PathBuf>, Box<dyn ::std::error::Error>> {
+    match (creds, jwt, seed) {
+        (Some(path), _, _) => {
+            context::read_creds(&path)?;
+            Ok(Some(path.canonicalize()?))
+        }
+        (None, Some(jwt), Some(seed)) => Ok(Some(context::store_creds(name, &jwt, &seed)?)),
+        (None, None, None) => Ok(None),
+        _ => Err("The user JWT and seed must be supplied together".into()),
+    }
+}
+
+/// Confirms that the registry is reachable with the selected context's credentials
+fn check_login(opts: &GlobalOptions) -> Result<(), Box<dyn ::std::error::Error>> {
+    let client = client(opts)?;
+    client
+        .query_catalog(&CatalogQuery {
+            query_type: QueryType::Account,
+            issuer: None,
+            operator: None,
+            subject: None,
+        })
+        .map_err(|e| format!("Unable to query the registry: {}", e))?;
+    let check = output::ConnectionCheck {
+        context: selected_context(opts).or_else(|| Contexts::load().ok().and_then(|c| c.current)),
+        connected: true,
+    };
+    emit(opts.output, &check, |_| {
+        "Successfully connected to the registry.".to_string()
+    })
+}
+
+fn split_urls(urls: &str) -> Vec<String> {
+    urls.split(',')
+        .map(|s| remove_whitespace(s))
+        .filter(|s| !s.is_empty())
+        .collect()
 }
 
 fn remove_whitespace(s: &str) -> String {
@@ -344,10 +1008,11 @@ fn main() -> Result<(), Box<dyn ::std::error::Error>> {
     let cmd = args.command;
     env_logger::init();
 
-    match handle_command(cmd) {
+    match handle_command(cmd, &args.options) {
         Ok(_) => {}
         Err(e) => {
             println!("Command line failure: {}", e);
+            std::process::exit(1);
         }
     }
     Ok(())
@@ -0,0 +1,268 @@
+//! Rendering of command results as JSON, YAML or human-readable tables
+
+use gantryclient::{
+    AuditLog, CacheEntry, CatalogEvent, CatalogQueryResult, ChainVerification, EventKind, ModuleInfo,
+};
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+use term_table::row::Row;
+use term_table::table_cell::{Alignment, TableCell};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Yaml,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            _ => Err(format!("Unknown output format '{}' (expected json, yaml or table)", s)),
+        }
+    }
+}
+
+/// The outcome of an upload or download, for scripting against
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferSummary {
+    pub subject: String,
+    /// The actor's latest revision in the catalog, if it could be determined
+    pub revision: Option<u64>,
+    pub bytes: u64,
+    /// The lowercase hex SHA-256 digest of the module
+    pub digest: String,
+    pub path: String,
+}
+
+/// The results of `gantry get`. Actors are listed with their stored module, when the
+/// server can describe it
+#[derive(Debug, Serialize)]
+pub struct QueryListing {
+    pub results: Vec<QueryRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueryRow {
+    #[serde(flatten)]
+    pub result: CatalogQueryResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub module: Option<ModuleInfo>,
+}
+
+/// The outcome of `gantry login --check`
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionCheck {
+    pub context: Option<String>,
+    pub connected: bool,
+}
+
+/// Prints a result in the requested format, using the supplied function to render it
+/// for the table format
+pub fn emit<T, F>(format: OutputFormat, value: &T, text: F) -> Result<(), Box<dyn ::std::error::Error>>
+where
+    T: Serialize,
+    F: FnOnce(&T) -> String,
+{
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(value)?),
+        OutputFormat::Yaml => println!("{}", serde_yaml::to_string(value)?),
+        OutputFormat::Table => println!("{}", text(value)),
+    }
+    Ok(())
+}
+
+/// Prints one of a stream of results, flushing it immediately. JSON results are written
+/// one per line and YAML results as separate documents, so that each can be parsed as it arrives
+pub fn emit_line<T, F>(format: OutputFormat, value: &T, text: F) -> Result<(), Box<dyn ::std::error::Error>>
+where
+    T: Serialize,
+    F: FnOnce(&T) -> String,
+{
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string(value)?),
+        OutputFormat::Yaml => println!("{}", serde_yaml::to_string(value)?),
+        OutputFormat::Table => println!("{}", text(value)),
+    }
+    std::io::stdout().flush()?;
+    Ok(())
+}
+
+pub fn query_table(listing: &QueryListing) -> String {
+    if listing.results.is_empty() {
+        return "No results.".to_string();
+    }
+    let modules = listing.results.iter().any(|r| r.module.is_some());
+    let columns = if modules { 3 } else { 2 };
+    let mut table = new_table(60);
+    table.add_row(Row::new(vec![centered("Gantry Query Results", columns)]));
+    let mut header = vec![centered("Name", 1), centered("Subject / Issuer", 1)];
+    if modules {
+        header.push(centered("Binary Uploaded", 1));
+    }
+    table.add_row(Row::new(header));
+
+    for row in &listing.results {
+        let res = &row.result;
+        let mut cells = vec![
+            centered(&res.name, 1),
+            centered(format!("{}\n{}", res.subject, res.issuer), 1),
+        ];
+        if modules {
+            cells.push(centered(module_text(row.module.as_ref()), 1));
+        }
+        table.add_row(Row::new(cells));
+    }
+    table.render()
+}
+
+fn module_text(module: Option<&ModuleInfo>) -> String {
+    match module {
+        Some(m) if m.exists => {
+            let mut text = format!("yes, {} bytes", m.total_bytes);
+            if let Some(revision) = m.revision {
+                text.push_str(&format!("\nrevision {}", revision));
+            }
+            if let Some(at) = m.uploaded_at {
+                let time = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(at));
+                text.push_str(&format!("\n{}", time));
+            }
+            text
+        }
+        Some(_) => "no".to_string(),
+        None => "-".to_string(),
+    }
+}
+
+pub fn verify_table(report: &ChainVerification) -> String {
+    let mut table = new_table(60);
+    table.add_row(Row::new(vec![centered(
+        format!(
+            "Trust chain for {}: {}",
+            report.subject,
+            if report.trusted { "TRUSTED" } else { "NOT TRUSTED" }
+        ),
+        3,
+    )]));
+    table.add_row(Row::new(vec![
+        centered("Kind", 1),
+        centered("Subject / Revision", 1),
+        centered("Status", 1),
+    ]));
+
+    for link in &report.links {
+        let revision = link
+            .revision
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let status = if link.is_valid() {
+            "OK".to_string()
+        } else {
+            link.problems.join("\n")
+        };
+        table.add_row(Row::new(vec![
+            centered(format!("{:?}", link.kind), 1),
+            centered(format!("{}\n{}", link.subject, revision), 1),
+            centered(status, 1),
+        ]));
+    }
+    table.render()
+}
+
+pub fn audit_table(log: &AuditLog) -> String {
+    if log.records.is_empty() {
+        return "No audit records.".to_string();
+    }
+    let mut table = new_table(60);
+    table.add_row(Row::new(vec![
+        centered("#", 1),
+        centered("Time", 1),
+        centered("Operation", 1),
+        centered("Subject / Caller", 1),
+        centered("Outcome", 1),
+    ]));
+    for r in &log.records {
+        let time = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(r.timestamp));
+        let subject = match r.revision {
+            Some(rev) => format!("{} (revision {})", r.subject, rev),
+            None => r.subject.to_string(),
+        };
+        let outcome = match r.error {
+            Some(ref e) => format!("FAILED: {}", e),
+            None => "OK".to_string(),
+        };
+        table.add_row(Row::new(vec![
+            TableCell::new(r.sequence),
+            TableCell::new(time),
+            TableCell::new(format!("{:?}", r.operation)),
+            TableCell::new(format!("{}\n{}", subject, r.caller.as_deref().unwrap_or("-"))),
+            TableCell::new(outcome),
+        ]));
+    }
+    table.render()
+}
+
+pub fn cache_table(entries: &[CacheEntry]) -> String {
+    if entries.is_empty() {
+        return "The module cache is empty.".to_string();
+    }
+    let mut table = new_table(70);
+    table.add_row(Row::new(vec![
+        centered("Subject / Digest", 1),
+        centered("Revision", 1),
+        centered("Bytes", 1),
+    ]));
+    for e in entries {
+        table.add_row(Row::new(vec![
+            TableCell::new(format!("{}\n{}", e.subject, e.digest)),
+            TableCell::new(e.revision),
+            TableCell::new(e.size),
+        ]));
+    }
+    table.render()
+}
+
+pub fn event_text(event: &CatalogEvent) -> String {
+    let kind = match event.kind {
+        EventKind::TokenAdded => "token added",
+        EventKind::TokenRevoked => "token revoked",
+        EventKind::TokenReinstated => "token reinstated",
+        EventKind::ModuleUploaded => "module uploaded",
+    };
+    let detail = match event.kind {
+        EventKind::ModuleUploaded => format!("{} bytes", event.total_bytes.unwrap_or(0)),
+        _ => format!(
+            "revision {}, issuer {}",
+            event
+                .revision
+                .map(|r| r.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            event.issuer.as_deref().unwrap_or("-")
+        ),
+    };
+    format!("{:<16} {} ({})", kind, event.subject, detail)
+}
+
+pub fn transfer_text(action: &str, summary: &TransferSummary) -> String {
+    format!(
+        "{} {} ({}): {} bytes, sha256 {}",
+        action, summary.subject, summary.path, summary.bytes, summary.digest
+    )
+}
+
+fn new_table<'a>(max_column_width: usize) -> term_table::Table<'a> {
+    let mut table = term_table::Table::new();
+    table.max_column_width = max_column_width;
+    table.style = term_table::TableStyle::extended();
+    table
+}
+
+fn centered<'a, T: ToString>(data: T, col_span: usize) -> TableCell<'a> {
+    TableCell::new_with_alignment(data, col_span, Alignment::Center)
+}
@@ -0,0 +1,14 @@
+fn main() {
+    prost_build::compile_protos(
+        &[
+            "proto/gantry/v1/envelope.proto",
+            "proto/gantry/v1/catalog.proto",
+            "proto/gantry/v1/stream.proto",
+            "proto/gantry/v1/meta.proto",
+            "proto/gantry/v1/audit.proto",
+            "proto/gantry/v1/events.proto",
+        ],
+        &["proto"],
+    )
+    .unwrap();
+}
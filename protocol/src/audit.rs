@@ -0,0 +1,70 @@
+//! # Gantry audit protocol
+//!
+//! The catalog keeps an append-only audit log of every attempted mutation of the registry,
+//! successful or not: putting, revoking, reinstating and unregistering tokens, and uploading
+//! modules.
+//! Records cannot be altered or removed through Gantry. The log is queried with
+//! `gantry.catalog.audit.query`, and is returned in the content type of the query. Actors have no clock, so every record's timestamp is
+//! stamped by the server as the request arrives.
+//!
+//! The streams actor reports uploads by sending records to the catalog on
+//! `gantry.catalog.audit.append`. That subject is only accepted from the streams actor
+//! and is never delivered from the message broker.
+
+pub static SUBJECT_AUDIT_QUERY: &str = "gantry.catalog.audit.query";
+pub static SUBJECT_AUDIT_APPEND: &str = "gantry.catalog.audit.append";
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Copy)]
+pub enum AuditOperation {
+    PutToken,
+    RevokeToken,
+    UnregisterToken,
+    UploadModule,
+    ReinstateToken,
+}
+
+/// A single attempted mutation of the registry
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct AuditRecord {
+    /// The position of the record in the log, starting at 1. Assigned when the log is queried
+    pub sequence: u64,
+    /// Seconds since the epoch at which the server received the request
+    pub timestamp: u64,
+    pub operation: AuditOperation,
+    pub subject: String,
+    pub revision: Option<u64>,
+    /// The identity that authorized the mutation, if known: for revocations, reinstatements
+    /// and unregistrations, the key that signed the change's proof. It is unknown for puts
+    /// and uploads, since the message broker does not reveal who published a request
+    pub caller: Option<String>,
+    pub success: bool,
+    /// Why the operation failed, if it did
+    pub error: Option<String>,
+}
+
+/// A query of the audit log. The time range is inclusive of `since` and exclusive of `until`
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct AuditQuery {
+    pub subject: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// Return only this many of the most recent matching records
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct AuditLog {
+    /// Matching records, oldest first
+    pub records: Vec<AuditRecord>,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        self.subject
+            .as_ref()
+            .map(|s| *s == record.subject)
+            .unwrap_or(true)
+            && self.since.map(|since| record.timestamp >= since).unwrap_or(true)
+            && self.until.map(|until| record.timestamp < until).unwrap_or(true)
+    }
+}
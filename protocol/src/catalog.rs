@@ -5,23 +5,82 @@
 //! * `put` - Adds a token to the catalog
 //! * `query` - Queries the catalog
 //! * `delete` - Removes an actor from the catalog. This operation _marks an actor as removed_, but does not remove the corresponding entry from underlying storage
+//! * `reinstate` - Restores a subject that was deleted, listing it in the catalog again
+//! * `unregister` - Removes a single revision of a token from the catalog without revoking its subject, so that it may be put again. Used to roll back a failed publish
+//! * `trust roots` - Retrieves the operators, and their signing keys, that the catalog currently trusts
+//! * `verify` - Walks the trust chain of a token from its subject up to a trusted operator, reporting on each link
+//! * `export` - Retrieves every stored revision of the raw tokens of a given kind, for backup or migration
+//! * `status` - Reports whether a single subject (or one revision of it) is registered and whether it has been revoked, without listing the catalog
+//!
+//! A single catalog can hold tokens from several trusted operators. Every stored
+//! account is scoped to the operator that issued it (directly or through one of the
+//! operator's signers), and every actor inherits the scope of its issuing account.
+//!
+//! Stored tokens are public, so changes to a subject that is already in the catalog (`delete`,
+//! `reinstate` and `unregister`) are requested with a [`TokenChange`] carrying a proof signed
+//! by a key with authority over the subject, rather than with the subject's token.
 
 pub static SUBJECT_CATALOG_PUT_TOKEN: &str = "gantry.catalog.tokens.put";
 pub static SUBJECT_CATALOG_DELETE_TOKEN: &str = "gantry.catalog.tokens.delete";
+pub static SUBJECT_CATALOG_UNREGISTER_TOKEN: &str = "gantry.catalog.tokens.unregister";
 pub static SUBJECT_CATALOG_QUERY: &str = "gantry.catalog.tokens.query";
+pub static SUBJECT_CATALOG_TRUST_ROOTS: &str = "gantry.catalog.trust.roots";
+pub static SUBJECT_CATALOG_TRUST_VERIFY: &str = "gantry.catalog.trust.verify";
+pub static SUBJECT_CATALOG_EXPORT: &str = "gantry.catalog.tokens.export";
+pub static SUBJECT_CATALOG_STATUS: &str = "gantry.catalog.tokens.status";
+pub static SUBJECT_CATALOG_REINSTATE_TOKEN: &str = "gantry.catalog.tokens.reinstate";
+
+/// The longest lifetime, in seconds, that the server accepts for the proof of a [`TokenChange`]
+pub const CHANGE_PROOF_TTL: u64 = 5 * 60;
 
 /// A token contains the raw string for a JWT signed with the ed25519 signature
 /// format. Actors, Accounts, Operators are all identified by tokens
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct Token {
     pub raw_token: String,
+    #[serde(default)]
     pub decoded_token_json: String,
     pub validation_result: Option<TokenValidation>,
+    /// The time (seconds since the epoch) at which the server received the token, stamped
+    /// by the server for the audit log
+    #[serde(default)]
+    pub received_at: u64,
+}
+
+/// A request to change the standing of a subject that is already in the catalog. The
+/// `proof` is a JWT whose subject is the subject being changed and whose name is
+/// [`change_proof_name`] for the request, expiring within [`CHANGE_PROOF_TTL`]. It must
+/// be signed by the subject's issuer, by the operator at the root of the subject's trust
+/// chain, or by one of that operator's signers. The server checks the proof and stamps the
+/// key that signed it as the `signer`, leaving it empty if the proof is not valid
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct TokenChange {
+    pub subject: String,
+    /// The revision affected by the change, for changes that apply to a single revision
+    /// (`unregister` requires one)
+    pub revision: Option<u64>,
+    pub proof: String,
+    pub signer: Option<String>,
+    /// The time (seconds since the epoch) at which the server received the request, stamped
+    /// by the server for the audit log
+    pub received_at: u64,
+}
+
+/// The name that the proof of a change must carry, which binds the proof to the subject
+/// on which the change is requested (and to the revision it affects), so that it cannot
+/// be replayed as a different change
+pub fn change_proof_name(request_subject: &str, revision: Option<u64>) -> String {
+    match revision {
+        Some(revision) => format!("{} {}", request_subject, revision),
+        None => request_subject.to_string(),
+    }
 }
 
 /// A protocol-specific message version of the validation result that the wascap
 /// library provides
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
 pub struct TokenValidation {
     pub expired: bool,
     pub expires_human: String,
@@ -43,6 +102,9 @@ pub struct ActorSummary {
     pub revision: u64,
     pub account: String,
     pub name: String,
+    /// The hash of the module's bytes recorded in the actor's signed claims
+    #[serde(default)]
+    pub module_hash: String,
 }
 
 #[derive(Debug, PartialEq, Deserialize, Serialize)]
@@ -55,6 +117,12 @@ pub struct AccountSummary {
 pub struct CatalogQuery {
     pub query_type: QueryType,
     pub issuer: Option<String>,
+    /// Restricts results to the trust chain of the given operator
+    #[serde(default)]
+    pub operator: Option<String>,
+    /// Restricts results to a single subject
+    #[serde(default)]
+    pub subject: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Deserialize, Serialize)]
@@ -68,6 +136,62 @@ pub struct CatalogQueryResult {
     pub issuer: String,
     pub name: String,
     pub actor: Option<ActorSummary>,
+    /// The trusted operator at the root of this token's trust chain
+    #[serde(default)]
+    pub operator: Option<String>,
+}
+
+/// A request for the status of a subject. If a revision is given, the subject only counts
+/// as registered if that revision is stored
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct SubjectStatusRequest {
+    pub subject: String,
+    pub revision: Option<u64>,
+}
+
+/// The status of a subject in the catalog. A revoked (deleted) subject keeps its stored
+/// revisions, while an unregistered revision is no longer stored
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct SubjectStatus {
+    pub subject: String,
+    pub registered: bool,
+    /// The highest stored revision, if any revision is stored
+    pub latest_revision: Option<u64>,
+    pub revoked: bool,
+    /// The trusted operator at the root of the subject's trust chain
+    pub operator: Option<String>,
+}
+
+impl SubjectStatus {
+    /// Whether the subject is registered and has not been revoked
+    pub fn is_active(&self) -> bool {
+        self.registered && !self.revoked
+    }
+}
+
+/// A request to export the raw tokens of every subject of the given kind that has not been revoked
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct CatalogExportRequest {
+    pub query_type: QueryType,
+    /// Restricts the export to a single subject
+    #[serde(default)]
+    pub subject: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct CatalogExport {
+    /// Ordered by subject, then by ascending revision
+    pub tokens: Vec<ExportedToken>,
+}
+
+/// A single revision of a token, exactly as it was originally put into the catalog
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ExportedToken {
+    pub subject: String,
+    pub revision: u64,
+    pub raw_token: String,
 }
 
 #[derive(Debug, PartialEq, Deserialize, Serialize)]
@@ -76,3 +200,71 @@ pub enum QueryType {
     Account,
     Operator,
 }
+
+/// An operator and its valid signing keys that the catalog treats as a root
+/// of trust for stored tokens. This configuration is persisted by the catalog and
+/// replaced whenever a newer revision of the operator's JWT is put into the catalog
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct TrustRoots {
+    pub operator: String,
+    pub revision: u64,
+    pub issued_at: u64,
+    pub signers: Vec<String>,
+}
+
+/// The full set of operators trusted by the catalog
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct TrustedOperators {
+    pub operators: Vec<TrustRoots>,
+}
+
+/// A request to verify the trust chain of a subject stored in the catalog. If no
+/// revision is supplied, the latest revision is verified. If an operator is supplied,
+/// the chain is only considered trusted if it terminates at that operator. The `as_of`
+/// time (seconds since the epoch) used for expiry checks is stamped by the server
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct ChainVerificationRequest {
+    pub subject: String,
+    pub revision: Option<u64>,
+    pub operator: Option<String>,
+    #[serde(default)]
+    pub as_of: u64,
+}
+
+/// The outcome of a trust chain verification. The links are ordered from the
+/// requested subject up to the operator at the root of the chain
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ChainVerification {
+    pub subject: String,
+    pub operator: Option<String>,
+    pub trusted: bool,
+    pub links: Vec<ChainLink>,
+}
+
+/// A single hop in a trust chain
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ChainLink {
+    pub subject: String,
+    pub kind: ChainLinkKind,
+    pub issuer: Option<String>,
+    pub revision: Option<u64>,
+    pub signature_valid: bool,
+    pub expired: bool,
+    pub cannot_use_yet: bool,
+    pub revoked: bool,
+    pub problems: Vec<String>,
+}
+
+impl ChainLink {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub enum ChainLinkKind {
+    Actor,
+    Account,
+    Operator,
+    Signer,
+}
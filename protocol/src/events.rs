@@ -0,0 +1,60 @@
+//! # Gantry change events
+//!
+//! The catalog and streams actors publish an event whenever the contents of the registry
+//! change, so that consumers such as hosts can react to new actor revisions without polling.
+//! Events are fire-and-forget; a consumer that is not subscribed when an event is published
+//! will not see it. The following events are published:
+//! * `token.added` - A token revision was put into the catalog
+//! * `token.revoked` - A subject was revoked (deleted) from the catalog
+//! * `token.reinstated` - A revoked subject was restored to the catalog
+//! * `module.uploaded` - The final chunk of an actor module was stored
+//!
+//! Each event is encoded in the content type of the request that caused it, so consumers
+//! should decode events with [`crate::decode`], which accepts either encoding.
+
+pub static SUBJECT_EVENT_TOKEN_ADDED: &str = "gantry.events.token.added";
+pub static SUBJECT_EVENT_TOKEN_REVOKED: &str = "gantry.events.token.revoked";
+pub static SUBJECT_EVENT_MODULE_UPLOADED: &str = "gantry.events.module.uploaded";
+pub static SUBJECT_EVENT_TOKEN_REINSTATED: &str = "gantry.events.token.reinstated";
+
+/// Every subject on which change events are published
+pub static EVENT_SUBJECTS: [&str; 4] = [
+    SUBJECT_EVENT_TOKEN_ADDED,
+    SUBJECT_EVENT_TOKEN_REVOKED,
+    SUBJECT_EVENT_TOKEN_REINSTATED,
+    SUBJECT_EVENT_MODULE_UPLOADED,
+];
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Copy)]
+pub enum EventKind {
+    TokenAdded,
+    TokenRevoked,
+    ModuleUploaded,
+    TokenReinstated,
+}
+
+impl EventKind {
+    /// The subject on which events of this kind are published
+    pub fn subject(&self) -> &'static str {
+        match self {
+            EventKind::TokenAdded => SUBJECT_EVENT_TOKEN_ADDED,
+            EventKind::TokenRevoked => SUBJECT_EVENT_TOKEN_REVOKED,
+            EventKind::ModuleUploaded => SUBJECT_EVENT_MODULE_UPLOADED,
+            EventKind::TokenReinstated => SUBJECT_EVENT_TOKEN_REINSTATED,
+        }
+    }
+}
+
+/// A change to the registry. Fields that do not apply to an event's kind are empty:
+/// token events carry the token's issuer and revision, while module events carry the
+/// size of the uploaded module
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct CatalogEvent {
+    pub kind: EventKind,
+    pub subject: String,
+    pub issuer: Option<String>,
+    pub revision: Option<u64>,
+    /// The trusted operator at the root of the subject's trust chain
+    pub operator: Option<String>,
+    pub total_bytes: Option<u64>,
+}
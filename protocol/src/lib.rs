@@ -6,26 +6,180 @@ use rmps::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
 use std::io::Cursor;
 
+/// The envelope in which every message is sent. Its fields, and those of the message
+/// within it, are encoded by name, so a peer ignores fields it does not know about and
+/// fields it expects but does not find fall back to their defaults
+#[derive(Serialize)]
+struct SealedEnvelope<'a, T> {
+    gantry: u32,
+    body: &'a T,
+}
+
+#[derive(Deserialize)]
+struct Envelope<T> {
+    gantry: u32,
+    body: T,
+}
+
+/// Encodes a message, inside an envelope stamped with [`meta::PROTOCOL_VERSION`]
 pub fn serialize<T>(item: T) -> ::std::result::Result<Vec<u8>, Box<dyn ::std::error::Error>>
 where
     T: Serialize,
 {
     let mut buf = Vec::new();
-    item.serialize(&mut Serializer::new(&mut buf))?;
+    let envelope = SealedEnvelope {
+        gantry: meta::PROTOCOL_VERSION,
+        body: &item,
+    };
+    envelope.serialize(&mut Serializer::new(&mut buf).with_struct_map())?;
     Ok(buf)
 }
 
+/// Decodes a message, whether it was sent in an envelope or (by a version 0 peer) bare
 pub fn deserialize<'de, T: Deserialize<'de>>(
     buf: &[u8],
 ) -> ::std::result::Result<T, Box<dyn ::std::error::Error>> {
     let mut de = Deserializer::new(Cursor::new(buf));
-    match Deserialize::deserialize(&mut de) {
+    let res = if is_enveloped(buf) {
+        Envelope::<T>::deserialize(&mut de).map(|e| e.body)
+    } else {
+        Deserialize::deserialize(&mut de)
+    };
+    match res {
         Ok(t) => Ok(t),
         Err(e) => Err(format!("Failed to de-serialize: {}", e).into()),
     }
 }
 
+/// The encoding of a message on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContentType {
+    /// The default: msgpack, with fields encoded by name
+    #[default]
+    MsgPack,
+    /// Protobuf, using the schemas in the crate's `proto` directory
+    Protobuf,
+}
+
+impl ContentType {
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ContentType::MsgPack => "application/msgpack",
+            ContentType::Protobuf => "application/x-protobuf",
+        }
+    }
+
+    /// Identifies the encoding of a message. Protobuf envelopes always begin with the tag
+    /// of their version field, a byte that never begins a msgpack message
+    pub fn of(buf: &[u8]) -> ContentType {
+        match buf.first() {
+            Some(0x08) => ContentType::Protobuf,
+            _ => ContentType::MsgPack,
+        }
+    }
+}
+
+impl std::str::FromStr for ContentType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ContentType, String> {
+        match s {
+            "msgpack" | "application/msgpack" => Ok(ContentType::MsgPack),
+            "protobuf" | "application/x-protobuf" => Ok(ContentType::Protobuf),
+            _ => Err(format!("Unknown content type '{}'", s)),
+        }
+    }
+}
+
+/// Encodes a message in the given content type
+pub fn encode<T>(
+    content_type: ContentType,
+    item: &T,
+) -> ::std::result::Result<Vec<u8>, Box<dyn ::std::error::Error>>
+where
+    T: Serialize + proto::ProtoMessage,
+{
+    match content_type {
+        ContentType::MsgPack => serialize(item),
+        ContentType::Protobuf => {
+            let mut body = Vec::new();
+            prost::Message::encode(&item.to_proto(), &mut body)?;
+            seal_proto(body)
+        }
+    }
+}
+
+/// The payload of a request that carries no message, such as `gantry.meta.info`
+pub fn encode_empty(
+    content_type: ContentType,
+) -> ::std::result::Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+    match content_type {
+        ContentType::MsgPack => Ok(Vec::new()),
+        ContentType::Protobuf => seal_proto(Vec::new()),
+    }
+}
+
+/// Decodes a message in either content type, returning it along with the content type
+/// in which it was encoded, so that a reply can be encoded the same way
+pub fn decode<T>(
+    buf: &[u8],
+) -> ::std::result::Result<(T, ContentType), Box<dyn ::std::error::Error>>
+where
+    T: serde::de::DeserializeOwned + proto::ProtoMessage,
+{
+    match ContentType::of(buf) {
+        ContentType::MsgPack => Ok((deserialize(buf)?, ContentType::MsgPack)),
+        ContentType::Protobuf => {
+            let envelope = open_proto(buf)?;
+            let proto = <T::Proto as prost::Message>::decode(envelope.body.as_slice())
+                .map_err(|e| format!("Failed to decode protobuf message: {}", e))?;
+            Ok((T::from_proto(proto)?, ContentType::Protobuf))
+        }
+    }
+}
+
+fn seal_proto(body: Vec<u8>) -> ::std::result::Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+    let envelope = proto::v1::Envelope {
+        gantry: meta::PROTOCOL_VERSION,
+        body,
+    };
+    let mut buf = Vec::new();
+    prost::Message::encode(&envelope, &mut buf)?;
+    Ok(buf)
+}
+
+fn open_proto(buf: &[u8]) -> ::std::result::Result<proto::v1::Envelope, Box<dyn ::std::error::Error>> {
+    <proto::v1::Envelope as prost::Message>::decode(buf)
+        .map_err(|e| format!("Failed to decode protobuf envelope: {}", e).into())
+}
+
+/// The protocol version with which a message was encoded, or 0 for a bare message
+pub fn version_of(buf: &[u8]) -> u32 {
+    if ContentType::of(buf) == ContentType::Protobuf {
+        return open_proto(buf).map(|e| e.gantry).unwrap_or(0);
+    }
+    if !is_enveloped(buf) {
+        return 0;
+    }
+    let mut de = Deserializer::new(Cursor::new(buf));
+    Envelope::<serde::de::IgnoredAny>::deserialize(&mut de)
+        .map(|e| e.gantry)
+        .unwrap_or(0)
+}
+
+// Bare messages are encoded positionally, as msgpack arrays, while envelopes are maps
+fn is_enveloped(buf: &[u8]) -> bool {
+    match buf.first() {
+        Some(b) => (0x80..=0x8f).contains(b) || *b == 0xde || *b == 0xdf,
+        None => false,
+    }
+}
+
+pub mod audit;
 pub mod catalog;
+pub mod events;
+pub mod meta;
+pub mod proto;
 pub mod stream;
 
 pub mod token {
@@ -0,0 +1,69 @@
+//! # Gantry protocol metadata
+//!
+//! Every message is sent inside a versioned envelope (see [`crate::serialize`]), and a
+//! server describes itself in reply to `gantry.meta.info`: the newest protocol version it
+//! speaks and the optional features it supports. Clients should check for a feature
+//! before relying on it rather than comparing server versions.
+//!
+//! Messages may be encoded as msgpack or as protobuf (see [`crate::proto`]), and the server
+//! replies in the encoding of the request. A request without a message, such as this one,
+//! is answered with msgpack unless it is an empty protobuf envelope.
+//!
+//! Protocol versions:
+//! * `0` - Bare, positionally encoded messages. Still accepted, never produced
+//! * `1` - Enveloped messages whose fields are encoded by name, so that fields can be
+//!   added without breaking older peers
+
+use crate::ContentType;
+
+pub static SUBJECT_META_INFO: &str = "gantry.meta.info";
+
+/// The newest version of the protocol spoken by this crate
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub static FEATURE_EVENTS: &str = "events";
+pub static FEATURE_AUDIT: &str = "audit";
+pub static FEATURE_TRUST_VERIFY: &str = "trust.verify";
+pub static FEATURE_EXPORT: &str = "export";
+pub static FEATURE_UNREGISTER: &str = "unregister";
+pub static FEATURE_MODULE_INFO: &str = "module.info";
+
+/// Every feature implemented by a server built against this version of the protocol
+pub static FEATURES: [&str; 6] = [
+    FEATURE_EVENTS,
+    FEATURE_AUDIT,
+    FEATURE_TRUST_VERIFY,
+    FEATURE_EXPORT,
+    FEATURE_UNREGISTER,
+    FEATURE_MODULE_INFO,
+];
+
+/// The reply to a `gantry.meta.info` request
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ServerInfo {
+    pub protocol_version: u32,
+    pub server_version: String,
+    pub features: Vec<String>,
+    /// The MIME types of the encodings the server accepts (see [`crate::ContentType`])
+    pub content_types: Vec<String>,
+}
+
+impl ServerInfo {
+    /// Describes a server built against this version of the protocol
+    pub fn current(server_version: &str) -> ServerInfo {
+        ServerInfo {
+            protocol_version: PROTOCOL_VERSION,
+            server_version: server_version.to_string(),
+            features: FEATURES.iter().map(|f| f.to_string()).collect(),
+            content_types: vec![
+                ContentType::MsgPack.mime().to_string(),
+                ContentType::Protobuf.mime().to_string(),
+            ],
+        }
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
@@ -0,0 +1,742 @@
+//! # Protobuf wire format
+//!
+//! The canonical `.proto` schemas for the catalog, stream, audit and event messages live in
+//! the crate's `proto` directory, for teams writing clients in other languages. The types
+//! generated from them are in [`v1`], and every message type that can be sent as protobuf
+//! implements [`ProtoMessage`] to convert to and from its generated counterpart.
+//!
+//! Protobuf has no notion of an absent string, so optional strings are sent as empty
+//! strings, while optional integers are wrapped in an `OptionalUint64` message.
+
+use crate::audit::*;
+use crate::catalog::*;
+use crate::events::{CatalogEvent, EventKind};
+use crate::meta::ServerInfo;
+use crate::stream::*;
+
+pub mod v1 {
+    include!(concat!(env!("OUT_DIR"), "/gantry.v1.rs"));
+}
+
+/// A message with a protobuf representation
+pub trait ProtoMessage: Sized {
+    type Proto: prost::Message + Default;
+
+    fn to_proto(&self) -> Self::Proto;
+
+    /// Fails if the message holds a value, such as an enum variant, with no native equivalent
+    fn from_proto(proto: Self::Proto) -> Result<Self, Box<dyn ::std::error::Error>>;
+}
+
+fn opt_string(s: &Option<String>) -> String {
+    s.clone().unwrap_or_default()
+}
+
+fn string_opt(s: String) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+fn opt_u64(v: Option<u64>) -> Option<v1::OptionalUint64> {
+    v.map(|value| v1::OptionalUint64 { value })
+}
+
+fn query_type_to_proto(q: &QueryType) -> i32 {
+    (match q {
+        QueryType::Actor => v1::QueryType::Actor,
+        QueryType::Account => v1::QueryType::Account,
+        QueryType::Operator => v1::QueryType::Operator,
+    }) as i32
+}
+
+fn query_type_from_proto(q: i32) -> Result<QueryType, Box<dyn ::std::error::Error>> {
+    match v1::QueryType::from_i32(q) {
+        Some(v1::QueryType::Actor) => Ok(QueryType::Actor),
+        Some(v1::QueryType::Account) => Ok(QueryType::Account),
+        Some(v1::QueryType::Operator) => Ok(QueryType::Operator),
+        None => Err(format!("Unknown query type {}", q).into()),
+    }
+}
+
+impl ProtoMessage for Token {
+    type Proto = v1::Token;
+
+    fn to_proto(&self) -> v1::Token {
+        v1::Token {
+            raw_token: self.raw_token.to_string(),
+            decoded_token_json: self.decoded_token_json.to_string(),
+            validation_result: self.validation_result.as_ref().map(|v| v1::TokenValidation {
+                expired: v.expired,
+                expires_human: v.expires_human.to_string(),
+                not_before_human: v.not_before_human.to_string(),
+                cannot_use_yet: v.cannot_use_yet,
+                signature_valid: v.signature_valid,
+            }),
+            received_at: self.received_at,
+        }
+    }
+
+    fn from_proto(p: v1::Token) -> Result<Token, Box<dyn ::std::error::Error>> {
+        Ok(Token {
+            raw_token: p.raw_token,
+            decoded_token_json: p.decoded_token_json,
+            validation_result: p.validation_result.map(|v| TokenValidation {
+                expired: v.expired,
+                expires_human: v.expires_human,
+                not_before_human: v.not_before_human,
+                cannot_use_yet: v.cannot_use_yet,
+                signature_valid: v.signature_valid,
+            }),
+            received_at: p.received_at,
+        })
+    }
+}
+
+impl ProtoMessage for CatalogQuery {
+    type Proto = v1::CatalogQuery;
+
+    fn to_proto(&self) -> v1::CatalogQuery {
+        v1::CatalogQuery {
+            query_type: query_type_to_proto(&self.query_type),
+            issuer: opt_string(&self.issuer),
+            operator: opt_string(&self.operator),
+            subject: opt_string(&self.subject),
+        }
+    }
+
+    fn from_proto(p: v1::CatalogQuery) -> Result<CatalogQuery, Box<dyn ::std::error::Error>> {
+        Ok(CatalogQuery {
+            query_type: query_type_from_proto(p.query_type)?,
+            issuer: string_opt(p.issuer),
+            operator: string_opt(p.operator),
+            subject: string_opt(p.subject),
+        })
+    }
+}
+
+impl ProtoMessage for CatalogQueryResult {
+    type Proto = v1::CatalogQueryResult;
+
+    fn to_proto(&self) -> v1::CatalogQueryResult {
+        v1::CatalogQueryResult {
+            subject: self.subject.to_string(),
+            issuer: self.issuer.to_string(),
+            name: self.name.to_string(),
+            actor: self.actor.as_ref().map(|a| v1::ActorSummary {
+                public_key: a.public_key.to_string(),
+                capabilities: a.capabilities.clone(),
+                provider: a.provider,
+                tags: a.tags.clone(),
+                version: a.version.to_string(),
+                revision: a.revision,
+                account: a.account.to_string(),
+                name: a.name.to_string(),
+                module_hash: a.module_hash.to_string(),
+            }),
+            operator: opt_string(&self.operator),
+        }
+    }
+
+    fn from_proto(p: v1::CatalogQueryResult) -> Result<CatalogQueryResult, Box<dyn ::std::error::Error>> {
+        Ok(CatalogQueryResult {
+            subject: p.subject,
+            issuer: p.issuer,
+            name: p.name,
+            actor: p.actor.map(|a| ActorSummary {
+                public_key: a.public_key,
+                capabilities: a.capabilities,
+                provider: a.provider,
+                tags: a.tags,
+                version: a.version,
+                revision: a.revision,
+                account: a.account,
+                name: a.name,
+                module_hash: a.module_hash,
+            }),
+            operator: string_opt(p.operator),
+        })
+    }
+}
+
+impl ProtoMessage for CatalogQueryResults {
+    type Proto = v1::CatalogQueryResults;
+
+    fn to_proto(&self) -> v1::CatalogQueryResults {
+        v1::CatalogQueryResults {
+            results: self.results.iter().map(|r| r.to_proto()).collect(),
+        }
+    }
+
+    fn from_proto(p: v1::CatalogQueryResults) -> Result<CatalogQueryResults, Box<dyn ::std::error::Error>> {
+        Ok(CatalogQueryResults {
+            results: p
+                .results
+                .into_iter()
+                .map(CatalogQueryResult::from_proto)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl ProtoMessage for TokenChange {
+    type Proto = v1::TokenChange;
+
+    fn to_proto(&self) -> v1::TokenChange {
+        v1::TokenChange {
+            subject: self.subject.to_string(),
+            revision: opt_u64(self.revision),
+            proof: self.proof.to_string(),
+            signer: opt_string(&self.signer),
+            received_at: self.received_at,
+        }
+    }
+
+    fn from_proto(p: v1::TokenChange) -> Result<TokenChange, Box<dyn ::std::error::Error>> {
+        Ok(TokenChange {
+            subject: p.subject,
+            revision: p.revision.map(|r| r.value),
+            proof: p.proof,
+            signer: string_opt(p.signer),
+            received_at: p.received_at,
+        })
+    }
+}
+
+impl ProtoMessage for SubjectStatusRequest {
+    type Proto = v1::SubjectStatusRequest;
+
+    fn to_proto(&self) -> v1::SubjectStatusRequest {
+        v1::SubjectStatusRequest {
+            subject: self.subject.to_string(),
+            revision: opt_u64(self.revision),
+        }
+    }
+
+    fn from_proto(
+        p: v1::SubjectStatusRequest,
+    ) -> Result<SubjectStatusRequest, Box<dyn ::std::error::Error>> {
+        Ok(SubjectStatusRequest {
+            subject: p.subject,
+            revision: p.revision.map(|r| r.value),
+        })
+    }
+}
+
+impl ProtoMessage for SubjectStatus {
+    type Proto = v1::SubjectStatus;
+
+    fn to_proto(&self) -> v1::SubjectStatus {
+        v1::SubjectStatus {
+            subject: self.subject.to_string(),
+            registered: self.registered,
+            latest_revision: opt_u64(self.latest_revision),
+            revoked: self.revoked,
+            operator: opt_string(&self.operator),
+        }
+    }
+
+    fn from_proto(p: v1::SubjectStatus) -> Result<SubjectStatus, Box<dyn ::std::error::Error>> {
+        Ok(SubjectStatus {
+            subject: p.subject,
+            registered: p.registered,
+            latest_revision: p.latest_revision.map(|r| r.value),
+            revoked: p.revoked,
+            operator: string_opt(p.operator),
+        })
+    }
+}
+
+impl ProtoMessage for CatalogExportRequest {
+    type Proto = v1::CatalogExportRequest;
+
+    fn to_proto(&self) -> v1::CatalogExportRequest {
+        v1::CatalogExportRequest {
+            query_type: query_type_to_proto(&self.query_type),
+            subject: opt_string(&self.subject),
+        }
+    }
+
+    fn from_proto(p: v1::CatalogExportRequest) -> Result<CatalogExportRequest, Box<dyn ::std::error::Error>> {
+        Ok(CatalogExportRequest {
+            query_type: query_type_from_proto(p.query_type)?,
+            subject: string_opt(p.subject),
+        })
+    }
+}
+
+impl ProtoMessage for CatalogExport {
+    type Proto = v1::CatalogExport;
+
+    fn to_proto(&self) -> v1::CatalogExport {
+        v1::CatalogExport {
+            tokens: self
+                .tokens
+                .iter()
+                .map(|t| v1::ExportedToken {
+                    subject: t.subject.to_string(),
+                    revision: t.revision,
+                    raw_token: t.raw_token.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn from_proto(p: v1::CatalogExport) -> Result<CatalogExport, Box<dyn ::std::error::Error>> {
+        Ok(CatalogExport {
+            tokens: p
+                .tokens
+                .into_iter()
+                .map(|t| ExportedToken {
+                    subject: t.subject,
+                    revision: t.revision,
+                    raw_token: t.raw_token,
+                })
+                .collect(),
+        })
+    }
+}
+
+impl ProtoMessage for TrustedOperators {
+    type Proto = v1::TrustedOperators;
+
+    fn to_proto(&self) -> v1::TrustedOperators {
+        v1::TrustedOperators {
+            operators: self
+                .operators
+                .iter()
+                .map(|r| v1::TrustRoots {
+                    operator: r.operator.to_string(),
+                    revision: r.revision,
+                    issued_at: r.issued_at,
+                    signers: r.signers.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn from_proto(p: v1::TrustedOperators) -> Result<TrustedOperators, Box<dyn ::std::error::Error>> {
+        Ok(TrustedOperators {
+            operators: p
+                .operators
+                .into_iter()
+                .map(|r| TrustRoots {
+                    operator: r.operator,
+                    revision: r.revision,
+                    issued_at: r.issued_at,
+                    signers: r.signers,
+                })
+                .collect(),
+        })
+    }
+}
+
+impl ProtoMessage for ChainVerificationRequest {
+    type Proto = v1::ChainVerificationRequest;
+
+    fn to_proto(&self) -> v1::ChainVerificationRequest {
+        v1::ChainVerificationRequest {
+            subject: self.subject.to_string(),
+            revision: opt_u64(self.revision),
+            operator: opt_string(&self.operator),
+            as_of: self.as_of,
+        }
+    }
+
+    fn from_proto(
+        p: v1::ChainVerificationRequest,
+    ) -> Result<ChainVerificationRequest, Box<dyn ::std::error::Error>> {
+        Ok(ChainVerificationRequest {
+            subject: p.subject,
+            revision: p.revision.map(|r| r.value),
+            operator: string_opt(p.operator),
+            as_of: p.as_of,
+        })
+    }
+}
+
+impl ProtoMessage for ChainVerification {
+    type Proto = v1::ChainVerification;
+
+    fn to_proto(&self) -> v1::ChainVerification {
+        v1::ChainVerification {
+            subject: self.subject.to_string(),
+            operator: opt_string(&self.operator),
+            trusted: self.trusted,
+            links: self
+                .links
+                .iter()
+                .map(|l| v1::ChainLink {
+                    subject: l.subject.to_string(),
+                    kind: (match l.kind {
+                        ChainLinkKind::Actor => v1::ChainLinkKind::ChainLinkActor,
+                        ChainLinkKind::Account => v1::ChainLinkKind::ChainLinkAccount,
+                        ChainLinkKind::Operator => v1::ChainLinkKind::ChainLinkOperator,
+                        ChainLinkKind::Signer => v1::ChainLinkKind::ChainLinkSigner,
+                    }) as i32,
+                    issuer: opt_string(&l.issuer),
+                    revision: opt_u64(l.revision),
+                    signature_valid: l.signature_valid,
+                    expired: l.expired,
+                    cannot_use_yet: l.cannot_use_yet,
+                    revoked: l.revoked,
+                    problems: l.problems.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn from_proto(p: v1::ChainVerification) -> Result<ChainVerification, Box<dyn ::std::error::Error>> {
+        let mut links = Vec::new();
+        for l in p.links {
+            let kind = match v1::ChainLinkKind::from_i32(l.kind) {
+                Some(v1::ChainLinkKind::ChainLinkActor) => ChainLinkKind::Actor,
+                Some(v1::ChainLinkKind::ChainLinkAccount) => ChainLinkKind::Account,
+                Some(v1::ChainLinkKind::ChainLinkOperator) => ChainLinkKind::Operator,
+                Some(v1::ChainLinkKind::ChainLinkSigner) => ChainLinkKind::Signer,
+                None => return Err(format!("Unknown chain link kind {}", l.kind).into()),
+            };
+            links.push(ChainLink {
+                subject: l.subject,
+                kind,
+                issuer: string_opt(l.issuer),
+                revision: l.revision.map(|r| r.value),
+                signature_valid: l.signature_valid,
+                expired: l.expired,
+                cannot_use_yet: l.cannot_use_yet,
+                revoked: l.revoked,
+                problems: l.problems,
+            });
+        }
+        Ok(ChainVerification {
+            subject: p.subject,
+            operator: string_opt(p.operator),
+            trusted: p.trusted,
+            links,
+        })
+    }
+}
+
+impl ProtoMessage for DownloadRequest {
+    type Proto = v1::DownloadRequest;
+
+    fn to_proto(&self) -> v1::DownloadRequest {
+        v1::DownloadRequest {
+            actor: self.actor.to_string(),
+            operator: opt_string(&self.operator),
+            compression: self.compression.clone(),
+            window: self.window,
+            offset: self.offset,
+            length: self.length,
+            transfer: self.transfer.to_string(),
+        }
+    }
+
+    fn from_proto(p: v1::DownloadRequest) -> Result<DownloadRequest, Box<dyn ::std::error::Error>> {
+        Ok(DownloadRequest {
+            actor: p.actor,
+            operator: string_opt(p.operator),
+            compression: p.compression,
+            window: p.window,
+            offset: p.offset,
+            length: p.length,
+            transfer: p.transfer,
+        })
+    }
+}
+
+impl ProtoMessage for UploadRequest {
+    type Proto = v1::UploadRequest;
+
+    fn to_proto(&self) -> v1::UploadRequest {
+        v1::UploadRequest {
+            actor: self.actor.to_string(),
+            total_bytes: self.total_bytes,
+            chunk_size: self.chunk_size,
+            total_chunks: self.total_chunks,
+            compression: self.compression.clone(),
+        }
+    }
+
+    fn from_proto(p: v1::UploadRequest) -> Result<UploadRequest, Box<dyn ::std::error::Error>> {
+        Ok(UploadRequest {
+            actor: p.actor,
+            total_bytes: p.total_bytes,
+            chunk_size: p.chunk_size,
+            total_chunks: p.total_chunks,
+            compression: p.compression,
+        })
+    }
+}
+
+impl ProtoMessage for TransferAck {
+    type Proto = v1::TransferAck;
+
+    fn to_proto(&self) -> v1::TransferAck {
+        v1::TransferAck {
+            success: self.success,
+            actor: self.actor.to_string(),
+            total_bytes: self.total_bytes,
+            chunk_size: self.chunk_size,
+            total_chunks: self.total_chunks,
+            compression: opt_string(&self.compression),
+        }
+    }
+
+    fn from_proto(p: v1::TransferAck) -> Result<TransferAck, Box<dyn ::std::error::Error>> {
+        Ok(TransferAck {
+            success: p.success,
+            actor: p.actor,
+            total_bytes: p.total_bytes,
+            chunk_size: p.chunk_size,
+            total_chunks: p.total_chunks,
+            compression: string_opt(p.compression),
+        })
+    }
+}
+
+impl ProtoMessage for ChunkAck {
+    type Proto = v1::ChunkAck;
+
+    fn to_proto(&self) -> v1::ChunkAck {
+        v1::ChunkAck {
+            success: self.success,
+            sequence_no: self.sequence_no,
+            bytes_sent: self.bytes_sent,
+        }
+    }
+
+    fn from_proto(p: v1::ChunkAck) -> Result<ChunkAck, Box<dyn ::std::error::Error>> {
+        Ok(ChunkAck {
+            success: p.success,
+            sequence_no: p.sequence_no,
+            bytes_sent: p.bytes_sent,
+        })
+    }
+}
+
+impl ProtoMessage for FileChunk {
+    type Proto = v1::FileChunk;
+
+    fn to_proto(&self) -> v1::FileChunk {
+        v1::FileChunk {
+            sequence_no: self.sequence_no,
+            actor: self.actor.to_string(),
+            total_bytes: self.total_bytes,
+            chunk_size: self.chunk_size,
+            total_chunks: self.total_chunks,
+            chunk_bytes: self.chunk_bytes.clone(),
+            compression: opt_string(&self.compression),
+            offset: self.offset,
+        }
+    }
+
+    fn from_proto(p: v1::FileChunk) -> Result<FileChunk, Box<dyn ::std::error::Error>> {
+        Ok(FileChunk {
+            sequence_no: p.sequence_no,
+            actor: p.actor,
+            total_bytes: p.total_bytes,
+            chunk_size: p.chunk_size,
+            total_chunks: p.total_chunks,
+            chunk_bytes: p.chunk_bytes,
+            compression: string_opt(p.compression),
+            offset: p.offset,
+        })
+    }
+}
+
+impl ProtoMessage for ModuleInfoRequest {
+    type Proto = v1::ModuleInfoRequest;
+
+    fn to_proto(&self) -> v1::ModuleInfoRequest {
+        v1::ModuleInfoRequest {
+            actor: self.actor.to_string(),
+            revision: opt_u64(self.revision),
+        }
+    }
+
+    fn from_proto(
+        p: v1::ModuleInfoRequest,
+    ) -> Result<ModuleInfoRequest, Box<dyn ::std::error::Error>> {
+        Ok(ModuleInfoRequest {
+            actor: p.actor,
+            revision: p.revision.map(|r| r.value),
+        })
+    }
+}
+
+impl ProtoMessage for ModuleInfo {
+    type Proto = v1::ModuleInfo;
+
+    fn to_proto(&self) -> v1::ModuleInfo {
+        v1::ModuleInfo {
+            actor: self.actor.to_string(),
+            exists: self.exists,
+            total_bytes: self.total_bytes,
+            digest: opt_string(&self.digest),
+            revision: opt_u64(self.revision),
+            uploaded_at: opt_u64(self.uploaded_at),
+            uploader: opt_string(&self.uploader),
+        }
+    }
+
+    fn from_proto(p: v1::ModuleInfo) -> Result<ModuleInfo, Box<dyn ::std::error::Error>> {
+        Ok(ModuleInfo {
+            actor: p.actor,
+            exists: p.exists,
+            total_bytes: p.total_bytes,
+            digest: string_opt(p.digest),
+            revision: p.revision.map(|r| r.value),
+            uploaded_at: p.uploaded_at.map(|r| r.value),
+            uploader: string_opt(p.uploader),
+        })
+    }
+}
+
+impl ProtoMessage for ServerInfo {
+    type Proto = v1::ServerInfo;
+
+    fn to_proto(&self) -> v1::ServerInfo {
+        v1::ServerInfo {
+            protocol_version: self.protocol_version,
+            server_version: self.server_version.to_string(),
+            features: self.features.clone(),
+            content_types: self.content_types.clone(),
+        }
+    }
+
+    fn from_proto(p: v1::ServerInfo) -> Result<ServerInfo, Box<dyn ::std::error::Error>> {
+        Ok(ServerInfo {
+            protocol_version: p.protocol_version,
+            server_version: p.server_version,
+            features: p.features,
+            content_types: p.content_types,
+        })
+    }
+}
+
+impl ProtoMessage for AuditRecord {
+    type Proto = v1::AuditRecord;
+
+    fn to_proto(&self) -> v1::AuditRecord {
+        v1::AuditRecord {
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+            operation: (match self.operation {
+                AuditOperation::PutToken => v1::AuditOperation::AuditPutToken,
+                AuditOperation::RevokeToken => v1::AuditOperation::AuditRevokeToken,
+                AuditOperation::UnregisterToken => v1::AuditOperation::AuditUnregisterToken,
+                AuditOperation::UploadModule => v1::AuditOperation::AuditUploadModule,
+                AuditOperation::ReinstateToken => v1::AuditOperation::AuditReinstateToken,
+            }) as i32,
+            subject: self.subject.to_string(),
+            revision: opt_u64(self.revision),
+            caller: opt_string(&self.caller),
+            success: self.success,
+            error: opt_string(&self.error),
+        }
+    }
+
+    fn from_proto(p: v1::AuditRecord) -> Result<AuditRecord, Box<dyn ::std::error::Error>> {
+        let operation = match v1::AuditOperation::from_i32(p.operation) {
+            Some(v1::AuditOperation::AuditPutToken) => AuditOperation::PutToken,
+            Some(v1::AuditOperation::AuditRevokeToken) => AuditOperation::RevokeToken,
+            Some(v1::AuditOperation::AuditUnregisterToken) => AuditOperation::UnregisterToken,
+            Some(v1::AuditOperation::AuditUploadModule) => AuditOperation::UploadModule,
+            Some(v1::AuditOperation::AuditReinstateToken) => AuditOperation::ReinstateToken,
+            None => return Err(format!("Unknown audit operation {}", p.operation).into()),
+        };
+        Ok(AuditRecord {
+            sequence: p.sequence,
+            timestamp: p.timestamp,
+            operation,
+            subject: p.subject,
+            revision: p.revision.map(|r| r.value),
+            caller: string_opt(p.caller),
+            success: p.success,
+            error: string_opt(p.error),
+        })
+    }
+}
+
+impl ProtoMessage for AuditQuery {
+    type Proto = v1::AuditQuery;
+
+    fn to_proto(&self) -> v1::AuditQuery {
+        v1::AuditQuery {
+            subject: opt_string(&self.subject),
+            since: opt_u64(self.since),
+            until: opt_u64(self.until),
+            limit: opt_u64(self.limit),
+        }
+    }
+
+    fn from_proto(p: v1::AuditQuery) -> Result<AuditQuery, Box<dyn ::std::error::Error>> {
+        Ok(AuditQuery {
+            subject: string_opt(p.subject),
+            since: p.since.map(|r| r.value),
+            until: p.until.map(|r| r.value),
+            limit: p.limit.map(|r| r.value),
+        })
+    }
+}
+
+impl ProtoMessage for AuditLog {
+    type Proto = v1::AuditLog;
+
+    fn to_proto(&self) -> v1::AuditLog {
+        v1::AuditLog {
+            records: self.records.iter().map(|r| r.to_proto()).collect(),
+        }
+    }
+
+    fn from_proto(p: v1::AuditLog) -> Result<AuditLog, Box<dyn ::std::error::Error>> {
+        Ok(AuditLog {
+            records: p
+                .records
+                .into_iter()
+                .map(AuditRecord::from_proto)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl ProtoMessage for CatalogEvent {
+    type Proto = v1::CatalogEvent;
+
+    fn to_proto(&self) -> v1::CatalogEvent {
+        v1::CatalogEvent {
+            kind: (match self.kind {
+                EventKind::TokenAdded => v1::EventKind::EventTokenAdded,
+                EventKind::TokenRevoked => v1::EventKind::EventTokenRevoked,
+                EventKind::ModuleUploaded => v1::EventKind::EventModuleUploaded,
+                EventKind::TokenReinstated => v1::EventKind::EventTokenReinstated,
+            }) as i32,
+            subject: self.subject.to_string(),
+            issuer: opt_string(&self.issuer),
+            revision: opt_u64(self.revision),
+            operator: opt_string(&self.operator),
+            total_bytes: opt_u64(self.total_bytes),
+        }
+    }
+
+    fn from_proto(p: v1::CatalogEvent) -> Result<CatalogEvent, Box<dyn ::std::error::Error>> {
+        let kind = match v1::EventKind::from_i32(p.kind) {
+            Some(v1::EventKind::EventTokenAdded) => EventKind::TokenAdded,
+            Some(v1::EventKind::EventTokenRevoked) => EventKind::TokenRevoked,
+            Some(v1::EventKind::EventModuleUploaded) => EventKind::ModuleUploaded,
+            Some(v1::EventKind::EventTokenReinstated) => EventKind::TokenReinstated,
+            None => return Err(format!("Unknown event kind {}", p.kind).into()),
+        };
+        Ok(CatalogEvent {
+            kind,
+            subject: p.subject,
+            issuer: string_opt(p.issuer),
+            revision: p.revision.map(|r| r.value),
+            operator: string_opt(p.operator),
+            total_bytes: p.total_bytes.map(|r| r.value),
+        })
+    }
+}
@@ -5,6 +5,9 @@
 //! of a module's metadata. The following operations are available for streaming:
 //! * `stream_put` - Send the raw bytes for a module to Gantry, corresponding to a specific public key+revision pair
 //! * `stream_get` - Retrieve the raw bytes for a module to Gantry, corresponding to a specific public key+revision pair
+//!
+//! Chunks may be compressed in transit, and downloads may be windowed, ranged and run
+//! concurrently; see [`DownloadRequest`] and [`FileChunk`].
 
 // Requests to initiate transfers
 pub static SUBJECT_STREAM_DOWNLOAD: &str = "gantry.stream.get";
@@ -14,41 +17,101 @@ pub static SUBJECT_STREAM_UPLOAD: &str = "gantry.stream.put";
 pub static SUBJECT_STREAM_DOWNLOAD_PREFIX: &str = "gantry.stream.download.";
 pub static SUBJECT_STREAM_UPLOAD_PREFIX: &str = "gantry.stream.upload.";
 
-/// A request to download a file from Gantry
+// Topic on which a client acknowledges download chunks, granting credit for more
+pub static SUBJECT_STREAM_CREDIT_PREFIX: &str = "gantry.stream.credit.";
+
+// Describes a stored module without transferring it
+pub static SUBJECT_STREAM_INFO: &str = "gantry.stream.info";
+
+// Exchanged between the streams actor and the catalog, never through the message broker.
+// Actors have no clock, so the server stamps the time of each recorded upload, and only
+// accepts records from the streams actor
+pub static SUBJECT_MODULE_RECORD: &str = "gantry.catalog.modules.record";
+pub static SUBJECT_MODULE_LOOKUP: &str = "gantry.catalog.modules.lookup";
+
+pub static COMPRESSION_ZSTD: &str = "zstd";
+
+/// The codecs with which chunks can be compressed, most preferred first
+pub static COMPRESSION_CODECS: [&str; 1] = [COMPRESSION_ZSTD];
 
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
+/// A request to download a file from Gantry. If an operator is supplied, the download
+/// is refused unless the actor belongs to that operator's trust chain.
+///
+/// Chunks are pushed to the client on `gantry.stream.download.{transfer}`, which it should
+/// subscribe to before making the request. With a `window`, no more than that many chunks
+/// are unacknowledged at once, and the client grants another by publishing a [`ChunkAck`]
+/// on `gantry.stream.credit.{transfer}`. A range set with `offset` and `length` sends only
+/// the chunks overlapping it, trimmed to it, which keep the sequence numbers they have in
+/// a whole download. The server holds a limited number of downloads at once, abandoning
+/// the one that has gone longest without progress when another starts
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct DownloadRequest {
     pub actor: String,
+    #[serde(default)]
+    pub operator: Option<String>,
+    /// The codecs with which the client can decompress chunks, most preferred first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub compression: Vec<String>,
+    /// The most chunks that may be unacknowledged at once, or 0 for no limit
+    #[serde(skip_serializing_if = "is_zero")]
+    pub window: u64,
+    /// The first byte of the module to download
+    #[serde(skip_serializing_if = "is_zero")]
+    pub offset: u64,
+    /// The number of bytes to download, or 0 for the rest of the module
+    #[serde(skip_serializing_if = "is_zero")]
+    pub length: u64,
+    /// Identifies this download among any others of the same actor, and names the subjects
+    /// on which its chunks and credit are exchanged. Must be a single subject token. If
+    /// empty, the actor's public key is used, shared with every other such download
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub transfer: String,
 }
 
 /// A request to upload a file to Gantry
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
 pub struct UploadRequest {
     pub actor: String,
     pub total_bytes: u64,
     pub chunk_size: u64,
     pub total_chunks: u64,
+    /// The codecs with which the client can compress chunks, most preferred first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub compression: Vec<String>,
 }
 
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
+/// The reply to an upload or download request. For a download, `total_chunks` is the
+/// sequence number of the final chunk that will be sent, or 0 if there are none
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct TransferAck {
     pub success: bool,
     pub actor: String,
     pub total_bytes: u64,
     pub chunk_size: u64,
     pub total_chunks: u64,
+    /// The codec chosen for the transfer's chunks, if they are compressed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
 }
 
-/// Acknowledgement of a single chunk
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
+/// Acknowledgement of a single chunk, uploaded or downloaded. Acknowledging a download
+/// chunk without success abandons the rest of the download
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct ChunkAck {
     pub success: bool,
     pub sequence_no: u64,
     pub bytes_sent: u64,
 }
 
-/// A single chunk of a file
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
+/// A single chunk of a file. The sizes and sequence number always refer to the
+/// uncompressed module, whatever codec the chunk's bytes are compressed with in transit.
+/// An upload is complete once every one of its chunks has been stored, in any order
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct FileChunk {
     pub sequence_no: u64,
     pub actor: String,
@@ -56,4 +119,73 @@ pub struct FileChunk {
     pub chunk_size: u64,
     pub total_chunks: u64,
     pub chunk_bytes: Vec<u8>,
+    /// The codec with which `chunk_bytes` is compressed, if it is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    /// The position of `chunk_bytes` within the module, for downloaded chunks
+    #[serde(skip_serializing_if = "is_zero")]
+    pub offset: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// A request to describe the module stored for an actor. If a revision is given, the
+/// stored module only counts as uploaded if it was uploaded for that revision
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ModuleInfoRequest {
+    pub actor: String,
+    pub revision: Option<u64>,
+}
+
+/// The module stored for an actor, which is only ever its latest upload. Modules uploaded
+/// before uploads were recorded have a size, but no digest, revision, upload time or
+/// uploader
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ModuleInfo {
+    pub actor: String,
+    /// Whether a module has been uploaded (for the requested revision, if there was one)
+    pub exists: bool,
+    pub total_bytes: u64,
+    /// The lowercase hex SHA-256 digest of the module, as read back from storage
+    pub digest: Option<String>,
+    /// The latest revision of the actor's token when the upload was authorized
+    pub revision: Option<u64>,
+    /// Seconds since the epoch at which the final chunk was stored
+    pub uploaded_at: Option<u64>,
+    /// The account that issued that revision. The message broker does not reveal who
+    /// published the upload itself
+    pub uploader: Option<String>,
+}
+
+/// Chooses the first of the offered codecs that is also supported
+pub fn negotiate_compression(offered: &[String], supported: &[&str]) -> Option<String> {
+    offered
+        .iter()
+        .find(|codec| supported.contains(&codec.as_str()))
+        .cloned()
+}
+
+#[cfg(feature = "compression")]
+pub fn compress(codec: &str, bytes: &[u8]) -> Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+    match codec {
+        "zstd" => Ok(zstd::block::compress(bytes, 0)?),
+        _ => Err(format!("Unsupported compression codec '{}'", codec).into()),
+    }
+}
+
+/// Decompresses a chunk, which can be no larger than `chunk_size` once decompressed
+#[cfg(feature = "compression")]
+pub fn decompress(
+    codec: &str,
+    bytes: &[u8],
+    chunk_size: u64,
+) -> Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+    match codec {
+        "zstd" => Ok(zstd::block::decompress(bytes, chunk_size as usize)?),
+        _ => Err(format!("Unsupported compression codec '{}'", codec).into()),
+    }
 }
@@ -0,0 +1,449 @@
+//! Pins the wire format of the protocol's messages. Each message is compared against a
+//! golden file beneath `tests/golden`; a deliberate change to the format is recorded by
+//! running the tests with `GANTRY_BLESS=1` and committing the rewritten files.
+
+#[macro_use]
+extern crate serde_derive;
+
+use gantry_protocol::audit::{AuditLog, AuditOperation, AuditQuery, AuditRecord};
+use gantry_protocol::catalog::{
+    change_proof_name, CatalogExportRequest, CatalogQuery, ChainLink, ChainLinkKind,
+    ChainVerification, QueryType, Token, TokenChange, TokenValidation,
+    SUBJECT_CATALOG_DELETE_TOKEN,
+};
+use gantry_protocol::events::{CatalogEvent, EventKind};
+use gantry_protocol::meta::{ServerInfo, PROTOCOL_VERSION};
+use gantry_protocol::stream::{
+    negotiate_compression, ChunkAck, DownloadRequest, FileChunk, ModuleInfo, TransferAck,
+    UploadRequest, COMPRESSION_CODECS,
+};
+use gantry_protocol::proto::ProtoMessage;
+use gantry_protocol::{decode, deserialize, encode, encode_empty, serialize, version_of, ContentType};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+const ACTOR: &str = "MB2ZQB6ROOMAYBO4ZCTFYWN7YIVBWA3MTKZYAQKJMTIHE2ELLRW2E3ZW";
+
+fn golden_path(name: &str, extension: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.{}", name, extension))
+}
+
+fn compare_golden(path: PathBuf, encoded: &[u8]) -> Vec<u8> {
+    if std::env::var("GANTRY_BLESS").is_ok() {
+        std::fs::write(&path, encoded).unwrap();
+    }
+    let golden = std::fs::read(&path)
+        .unwrap_or_else(|e| panic!("Unable to read {}: {}", path.display(), e));
+    assert_eq!(encoded, golden.as_slice(), "The encoding in {} has changed", path.display());
+    golden
+}
+
+fn check_golden<T>(name: &str, sample: &T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let golden = compare_golden(golden_path(name, "msgpack"), &serialize(sample).unwrap());
+    assert_eq!(&deserialize::<T>(&golden).unwrap(), sample);
+}
+
+fn check_golden_proto<T>(name: &str, sample: &T)
+where
+    T: Serialize + DeserializeOwned + ProtoMessage + PartialEq + Debug,
+{
+    let encoded = encode(ContentType::Protobuf, sample).unwrap();
+    let golden = compare_golden(golden_path(name, "pb"), &encoded);
+    let (decoded, content_type) = decode::<T>(&golden).unwrap();
+    assert_eq!(&decoded, sample);
+    assert_eq!(content_type, ContentType::Protobuf);
+}
+
+fn token() -> Token {
+    Token {
+        raw_token: "eyJ0eXAiOiJqd3QiLCJhbGciOiJFZDI1NTE5In0.e30.c2ln".to_string(),
+        decoded_token_json: "{}".to_string(),
+        validation_result: Some(TokenValidation {
+            expired: false,
+            expires_human: "never".to_string(),
+            not_before_human: "immediately".to_string(),
+            cannot_use_yet: false,
+            signature_valid: true,
+        }),
+        received_at: 1_584_000_000,
+    }
+}
+
+fn event() -> CatalogEvent {
+    CatalogEvent {
+        kind: EventKind::TokenAdded,
+        subject: ACTOR.to_string(),
+        issuer: Some("AAOZ7TBI3BDZMS3YYJYH4WZWLAEODVGQXW4XKWCSVGYX3XXYDKZG7T7T".to_string()),
+        revision: Some(3),
+        operator: None,
+        total_bytes: None,
+    }
+}
+
+#[test]
+fn messages_match_golden_files() {
+    check_golden("token", &token());
+    check_golden(
+        "catalog_query",
+        &CatalogQuery {
+            query_type: QueryType::Actor,
+            issuer: None,
+            operator: None,
+            subject: Some(ACTOR.to_string()),
+        },
+    );
+    check_golden(
+        "download_request",
+        &DownloadRequest {
+            actor: ACTOR.to_string(),
+            operator: None,
+            compression: vec![],
+            window: 0,
+            offset: 0,
+            length: 0,
+            transfer: String::new(),
+        },
+    );
+    check_golden(
+        "upload_request",
+        &UploadRequest {
+            actor: ACTOR.to_string(),
+            total_bytes: 300_000,
+            chunk_size: 262_144,
+            total_chunks: 1,
+            compression: vec![],
+        },
+    );
+    check_golden(
+        "transfer_ack",
+        &TransferAck {
+            success: true,
+            actor: ACTOR.to_string(),
+            total_bytes: 300_000,
+            chunk_size: 262_144,
+            total_chunks: 2,
+            compression: None,
+        },
+    );
+    check_golden(
+        "file_chunk",
+        &FileChunk {
+            sequence_no: 1,
+            actor: ACTOR.to_string(),
+            total_bytes: 4,
+            chunk_size: 262_144,
+            total_chunks: 1,
+            chunk_bytes: vec![0, 97, 115, 109],
+            compression: None,
+            offset: 0,
+        },
+    );
+    check_golden("catalog_event", &event());
+    check_golden(
+        "server_info",
+        &ServerInfo {
+            protocol_version: 1,
+            server_version: "0.0.3".to_string(),
+            features: vec!["events".to_string(), "audit".to_string()],
+            content_types: vec!["application/msgpack".to_string()],
+        },
+    );
+}
+
+#[test]
+fn messages_carry_the_protocol_version() {
+    let buf = serialize(token()).unwrap();
+    assert_eq!(version_of(&buf), PROTOCOL_VERSION);
+    assert_eq!(version_of(&rmp_serde::to_vec(&token()).unwrap()), 0);
+}
+
+// The shape of a token before the server began stamping when it was received
+#[derive(Serialize)]
+struct TokenV0 {
+    raw_token: String,
+    decoded_token_json: String,
+    validation_result: Option<TokenValidation>,
+}
+
+#[test]
+fn bare_positional_messages_are_accepted() {
+    let old = TokenV0 {
+        raw_token: "abc".to_string(),
+        decoded_token_json: "{}".to_string(),
+        validation_result: None,
+    };
+    let token = deserialize::<Token>(&rmp_serde::to_vec(&old).unwrap()).unwrap();
+    assert_eq!(token.raw_token, "abc");
+    assert_eq!(token.received_at, 0);
+}
+
+// An upload request as a future peer might send it
+#[derive(Serialize)]
+struct UploadRequestV2 {
+    actor: String,
+    total_bytes: u64,
+    chunk_size: u64,
+    total_chunks: u64,
+    resume_from: u64,
+}
+
+#[test]
+fn unknown_fields_are_ignored() {
+    let newer = UploadRequestV2 {
+        actor: ACTOR.to_string(),
+        total_bytes: 10,
+        chunk_size: 4,
+        total_chunks: 3,
+        resume_from: 8,
+    };
+    let req = deserialize::<UploadRequest>(&serialize(&newer).unwrap()).unwrap();
+    assert_eq!(
+        req,
+        UploadRequest {
+            actor: ACTOR.to_string(),
+            total_bytes: 10,
+            chunk_size: 4,
+            total_chunks: 3,
+            compression: vec![],
+        }
+    );
+}
+
+#[derive(Serialize)]
+struct SparseAck {
+    sequence_no: u64,
+}
+
+#[test]
+fn missing_fields_take_their_defaults() {
+    let ack = deserialize::<ChunkAck>(&serialize(&SparseAck { sequence_no: 7 }).unwrap()).unwrap();
+    assert_eq!(
+        ack,
+        ChunkAck {
+            success: false,
+            sequence_no: 7,
+            bytes_sent: 0,
+        }
+    );
+}
+
+fn verification() -> ChainVerification {
+    ChainVerification {
+        subject: ACTOR.to_string(),
+        operator: None,
+        trusted: false,
+        links: vec![ChainLink {
+            subject: ACTOR.to_string(),
+            kind: ChainLinkKind::Actor,
+            issuer: Some("AAOZ7TBI3BDZMS3YYJYH4WZWLAEODVGQXW4XKWCSVGYX3XXYDKZG7T7T".to_string()),
+            revision: Some(0),
+            signature_valid: true,
+            expired: true,
+            cannot_use_yet: false,
+            revoked: false,
+            problems: vec!["The token has expired".to_string()],
+        }],
+    }
+}
+
+#[test]
+fn protobuf_messages_match_golden_files() {
+    check_golden_proto("token", &token());
+    check_golden_proto(
+        "download_request",
+        &DownloadRequest {
+            actor: ACTOR.to_string(),
+            operator: None,
+            compression: vec![],
+            window: 0,
+            offset: 0,
+            length: 0,
+            transfer: String::new(),
+        },
+    );
+    check_golden_proto(
+        "file_chunk",
+        &FileChunk {
+            sequence_no: 1,
+            actor: ACTOR.to_string(),
+            total_bytes: 4,
+            chunk_size: 262_144,
+            total_chunks: 1,
+            chunk_bytes: vec![0, 97, 115, 109],
+            compression: None,
+            offset: 0,
+        },
+    );
+    check_golden_proto("chain_verification", &verification());
+    check_golden_proto("catalog_event", &event());
+}
+
+#[test]
+fn content_type_is_detected() {
+    let sample = verification();
+    for content_type in &[ContentType::MsgPack, ContentType::Protobuf] {
+        let buf = encode(*content_type, &sample).unwrap();
+        assert_eq!(ContentType::of(&buf), *content_type);
+        assert_eq!(version_of(&buf), PROTOCOL_VERSION);
+        assert_eq!(decode::<ChainVerification>(&buf).unwrap(), (sample.clone(), *content_type));
+    }
+    assert_eq!(ContentType::of(&encode_empty(ContentType::Protobuf).unwrap()), ContentType::Protobuf);
+    assert_eq!(ContentType::of(&encode_empty(ContentType::MsgPack).unwrap()), ContentType::MsgPack);
+}
+
+#[test]
+fn compression_is_negotiated_in_preference_order() {
+    let offered = vec!["lz4".to_string(), "zstd".to_string()];
+    assert_eq!(
+        negotiate_compression(&offered, &COMPRESSION_CODECS),
+        Some("zstd".to_string())
+    );
+    assert_eq!(negotiate_compression(&[], &COMPRESSION_CODECS), None);
+    assert_eq!(negotiate_compression(&offered, &[]), None);
+}
+
+fn ranged_download() -> DownloadRequest {
+    DownloadRequest {
+        actor: ACTOR.to_string(),
+        offset: 524_288,
+        length: 1_000,
+        transfer: "c2f1a0".to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn download_ranges_round_trip() {
+    for content_type in &[ContentType::MsgPack, ContentType::Protobuf] {
+        let buf = encode(*content_type, &ranged_download()).unwrap();
+        let (req, _) = decode::<DownloadRequest>(&buf).unwrap();
+        assert_eq!(req, ranged_download());
+    }
+}
+
+#[test]
+fn module_info_keeps_unrecorded_fields_absent() {
+    let legacy = ModuleInfo {
+        actor: ACTOR.to_string(),
+        exists: true,
+        total_bytes: 4,
+        ..Default::default()
+    };
+    let recorded = ModuleInfo {
+        digest: Some("9f86d081884c7d65".to_string()),
+        revision: Some(0),
+        uploaded_at: Some(1_760_000_000),
+        uploader: Some("AAOZ7TBI3BDZMS3YYJYH4WZWLAEODVGQXW4XKWCSVGYX3XXYDKZG7T7T".to_string()),
+        ..legacy.clone()
+    };
+    for info in &[legacy, recorded] {
+        for content_type in &[ContentType::MsgPack, ContentType::Protobuf] {
+            let buf = encode(*content_type, info).unwrap();
+            assert_eq!(decode::<ModuleInfo>(&buf).unwrap().0, *info);
+        }
+    }
+}
+
+#[test]
+fn token_changes_round_trip() {
+    let change = TokenChange {
+        subject: ACTOR.to_string(),
+        proof: "eyJ0eXAiOiJqd3QiLCJhbGciOiJFZDI1NTE5In0.e30.c2ln".to_string(),
+        ..Default::default()
+    };
+    let stamped = TokenChange {
+        revision: Some(2),
+        signer: Some("AAOZ7TBI3BDZMS3YYJYH4WZWLAEODVGQXW4XKWCSVGYX3XXYDKZG7T7T".to_string()),
+        received_at: 1_760_000_000,
+        ..change.clone()
+    };
+    for change in &[change, stamped] {
+        for content_type in &[ContentType::MsgPack, ContentType::Protobuf] {
+            let buf = encode(*content_type, change).unwrap();
+            assert_eq!(decode::<TokenChange>(&buf).unwrap().0, *change);
+        }
+    }
+    assert_ne!(
+        change_proof_name(SUBJECT_CATALOG_DELETE_TOKEN, None),
+        change_proof_name(SUBJECT_CATALOG_DELETE_TOKEN, Some(0))
+    );
+}
+
+#[test]
+fn export_requests_round_trip() {
+    let all = CatalogExportRequest {
+        query_type: QueryType::Actor,
+        subject: None,
+    };
+    let one = CatalogExportRequest {
+        query_type: QueryType::Actor,
+        subject: Some(ACTOR.to_string()),
+    };
+    for req in &[all, one] {
+        for content_type in &[ContentType::MsgPack, ContentType::Protobuf] {
+            let buf = encode(*content_type, req).unwrap();
+            assert_eq!(decode::<CatalogExportRequest>(&buf).unwrap().0, *req);
+        }
+    }
+}
+
+#[test]
+fn audit_messages_round_trip() {
+    let query = AuditQuery {
+        subject: Some(ACTOR.to_string()),
+        since: Some(0),
+        until: None,
+        limit: Some(10),
+    };
+    let log = AuditLog {
+        records: vec![
+            AuditRecord {
+                sequence: 1,
+                timestamp: 1_760_000_000,
+                operation: AuditOperation::PutToken,
+                subject: ACTOR.to_string(),
+                revision: Some(0),
+                caller: None,
+                success: true,
+                error: None,
+            },
+            AuditRecord {
+                sequence: 2,
+                timestamp: 1_760_000_060,
+                operation: AuditOperation::ReinstateToken,
+                subject: ACTOR.to_string(),
+                revision: None,
+                caller: Some("AAOZ7TBI3BDZMS3YYJYH4WZWLAEODVGQXW4XKWCSVGYX3XXYDKZG7T7T".to_string()),
+                success: false,
+                error: Some("not revoked".to_string()),
+            },
+        ],
+    };
+    for content_type in &[ContentType::MsgPack, ContentType::Protobuf] {
+        let buf = encode(*content_type, &query).unwrap();
+        assert_eq!(decode::<AuditQuery>(&buf).unwrap(), (query.clone(), *content_type));
+        let buf = encode(*content_type, &log).unwrap();
+        assert_eq!(decode::<AuditLog>(&buf).unwrap(), (log.clone(), *content_type));
+    }
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compressed_chunks_round_trip() {
+    use gantry_protocol::stream::{compress, decompress, COMPRESSION_ZSTD};
+
+    let bytes: Vec<u8> = (0..4096u32).map(|i| (i % 7) as u8).collect();
+    let compressed = compress(COMPRESSION_ZSTD, &bytes).unwrap();
+    assert!(compressed.len() < bytes.len());
+    assert_eq!(decompress(COMPRESSION_ZSTD, &compressed, 4096).unwrap(), bytes);
+    assert!(decompress(COMPRESSION_ZSTD, &compressed, 1024).is_err());
+    assert!(compress("lz4", &bytes).is_err());
+}
@@ -0,0 +1 @@
+x8MB2ZQB6ROOMAYBO4ZCTFYWN7YIVBWA3MTKZYAQKJMTIHE2ELLRW2E3ZW8AAOZ7TBI3BDZMS3YYJYH4WZWLAEODVGQXW4XKWCSVGYX3XXYDKZG7T7T"
\ No newline at end of file
@@ -0,0 +1,2 @@
+:
+8MB2ZQB6ROOMAYBO4ZCTFYWN7YIVBWA3MTKZYAQKJMTIHE2ELLRW2E3ZW
\ No newline at end of file
@@ -0,0 +1,2 @@
+T
+0eyJ0eXAiOiJqd3QiLCJhbGciOiJFZDI1NTE5In0.e30.c2ln{}neverimmediately( ا
\ No newline at end of file
@@ -0,0 +1,232 @@
+use codec::blobstore::*;
+use codec::capabilities::{CapabilityProvider, Dispatcher, NullDispatcher};
+use codec::core::{CapabilityConfiguration, OP_CONFIGURE, OP_REMOVE_ACTOR};
+use codec::{deserialize, serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+const CAPABILITY_ID: &str = "wascc:blobstore";
+
+/// An in-process blob store that keeps containers as directories beneath the `ROOT`
+/// supplied in each actor's configuration. Downloads are pushed back to the requesting
+/// actor chunk by chunk, mirroring the behavior of the S3 provider
+pub struct FsBlobstoreProvider {
+    dispatcher: Arc<RwLock<Box<dyn Dispatcher>>>,
+    roots: RwLock<HashMap<String, PathBuf>>,
+}
+
+impl Default for FsBlobstoreProvider {
+    fn default() -> Self {
+        FsBlobstoreProvider {
+            dispatcher: Arc::new(RwLock::new(Box::new(NullDispatcher::new()))),
+            roots: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl FsBlobstoreProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn configure(&self, config: CapabilityConfiguration) -> Result<Vec<u8>, Box<dyn Error>> {
+        let root = config
+            .values
+            .get("ROOT")
+            .ok_or("Filesystem blob store configuration is missing the 'ROOT' value")?;
+        fs::create_dir_all(root)?;
+        self.roots
+            .write()
+            .unwrap()
+            .insert(config.module, PathBuf::from(root));
+        Ok(vec![])
+    }
+
+    fn root(&self, actor: &str) -> Result<PathBuf, Box<dyn Error>> {
+        self.roots
+            .read()
+            .unwrap()
+            .get(actor)
+            .cloned()
+            .ok_or_else(|| format!("Actor {} has not configured the blob store", actor).into())
+    }
+
+    fn blob_path(&self, actor: &str, container: &str, id: &str) -> Result<PathBuf, Box<dyn Error>> {
+        sanitize(&sanitize(&self.root(actor)?, container)?, id)
+    }
+
+    fn create_container(
+        &self,
+        actor: &str,
+        container: Container,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        fs::create_dir_all(sanitize(&self.root(actor)?, &container.id)?)?;
+        serialize(container)
+    }
+
+    fn remove_container(
+        &self,
+        actor: &str,
+        container: Container,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        fs::remove_dir_all(sanitize(&self.root(actor)?, &container.id)?)?;
+        Ok(vec![])
+    }
+
+    fn remove_object(&self, actor: &str, blob: Blob) -> Result<Vec<u8>, Box<dyn Error>> {
+        fs::remove_file(self.blob_path(actor, &blob.container, &blob.id)?)?;
+        Ok(vec![])
+    }
+
+    fn list_objects(&self, actor: &str, container: Container) -> Result<Vec<u8>, Box<dyn Error>> {
+        let dir = sanitize(&self.root(actor)?, &container.id)?;
+        let mut blobs = Vec::new();
+        if dir.exists() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    blobs.push(Blob {
+                        id: entry.file_name().to_string_lossy().to_string(),
+                        container: container.id.to_string(),
+                        byte_size: entry.metadata()?.len(),
+                    });
+                }
+            }
+        }
+        serialize(BlobList { blobs })
+    }
+
+    fn get_object_info(&self, actor: &str, blob: Blob) -> Result<Vec<u8>, Box<dyn Error>> {
+        let path = self.blob_path(actor, &blob.container, &blob.id)?;
+        let info = if path.is_file() {
+            Blob {
+                byte_size: fs::metadata(&path)?.len(),
+                ..blob
+            }
+        } else {
+            Blob {
+                id: "none".to_string(),
+                container: "none".to_string(),
+                byte_size: 0,
+            }
+        };
+        serialize(info)
+    }
+
+    fn start_upload(&self, actor: &str, chunk: FileChunk) -> Result<Vec<u8>, Box<dyn Error>> {
+        let path = self.blob_path(actor, &chunk.container, &chunk.id)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::create(path)?;
+        Ok(vec![])
+    }
+
+    /// Chunks are written at their offset rather than appended so that a chunk which
+    /// is re-sent, or arrives out of order, cannot corrupt the stored blob. Chunk sequence
+    /// numbers are 1-based, as they are for downloads
+    fn upload_chunk(&self, actor: &str, chunk: FileChunk) -> Result<Vec<u8>, Box<dyn Error>> {
+        if chunk.sequence_no == 0 {
+            return Err(format!("Chunk 0 of {} is out of range; chunks start at 1", chunk.id).into());
+        }
+        let path = self.blob_path(actor, &chunk.container, &chunk.id)?;
+        let mut file = OpenOptions::new().create(true).write(true).open(path)?;
+        file.seek(SeekFrom::Start((chunk.sequence_no - 1) * chunk.chunk_size))?;
+        file.write_all(&chunk.chunk_bytes)?;
+        Ok(vec![])
+    }
+
+    fn start_download(&self, actor: &str, req: StreamRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        let path = self.blob_path(actor, &req.container, &req.id)?;
+        let mut file = File::open(&path)?;
+        let total_bytes = file.metadata()?.len();
+        let chunk_size = req.chunk_size.max(1);
+        let dispatcher = self.dispatcher.clone();
+        let actor = actor.to_string();
+
+        std::thread::spawn(move || {
+            let mut sequence_no = 0;
+            loop {
+                let mut buf = Vec::with_capacity(chunk_size as usize);
+                match file.by_ref().take(chunk_size).read_to_end(&mut buf) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!("Failed to read blob {}: {}", path.display(), e);
+                        break;
+                    }
+                }
+                // Chunk sequence numbers are 1-based, as they are for the S3 provider
+                sequence_no += 1;
+                let chunk = FileChunk {
+                    sequence_no,
+                    container: req.container.to_string(),
+                    id: req.id.to_string(),
+                    total_bytes,
+                    chunk_size,
+                    chunk_bytes: buf,
+                };
+                let res = serialize(chunk).and_then(|buf| {
+                    dispatcher
+                        .read()
+                        .unwrap()
+                        .dispatch(&actor, OP_RECEIVE_CHUNK, &buf)
+                });
+                if let Err(e) = res {
+                    error!("Failed to dispatch chunk to {}: {}", actor, e);
+                    break;
+                }
+            }
+        });
+        Ok(vec![])
+    }
+}
+
+/// Joins a container or blob name onto a path, refusing names that would escape it
+fn sanitize(base: &Path, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    if name.is_empty() || name.contains('/') || name.contains('\\') || name.starts_with('.') {
+        Err(format!("Invalid blob store name: {}", name).into())
+    } else {
+        Ok(base.join(name))
+    }
+}
+
+impl CapabilityProvider for FsBlobstoreProvider {
+    fn capability_id(&self) -> &'static str {
+        CAPABILITY_ID
+    }
+
+    fn configure_dispatch(&self, dispatcher: Box<dyn Dispatcher>) -> Result<(), Box<dyn Error>> {
+        *self.dispatcher.write().unwrap() = dispatcher;
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "Gantry Filesystem Blob Store"
+    }
+
+    fn handle_call(&self, actor: &str, op: &str, msg: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        trace!("Received blob store operation {} from {}", op, actor);
+        match op {
+            OP_CONFIGURE => self.configure(deserialize(msg)?),
+            OP_REMOVE_ACTOR => {
+                let config: CapabilityConfiguration = deserialize(msg)?;
+                self.roots.write().unwrap().remove(&config.module);
+                Ok(vec![])
+            }
+            OP_CREATE_CONTAINER => self.create_container(actor, deserialize(msg)?),
+            OP_REMOVE_CONTAINER => self.remove_container(actor, deserialize(msg)?),
+            OP_REMOVE_OBJECT => self.remove_object(actor, deserialize(msg)?),
+            OP_LIST_OBJECTS => self.list_objects(actor, deserialize(msg)?),
+            OP_GET_OBJECT_INFO => self.get_object_info(actor, deserialize(msg)?),
+            OP_START_UPLOAD => self.start_upload(actor, deserialize(msg)?),
+            OP_UPLOAD_CHUNK => self.upload_chunk(actor, deserialize(msg)?),
+            OP_START_DOWNLOAD => self.start_download(actor, deserialize(msg)?),
+            _ => Err(format!("Unsupported blob store operation: {}", op).into()),
+        }
+    }
+}
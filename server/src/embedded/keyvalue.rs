@@ -0,0 +1,242 @@
+use codec::capabilities::{CapabilityProvider, Dispatcher};
+use codec::core::{OP_CONFIGURE, OP_REMOVE_ACTOR};
+use codec::keyvalue::*;
+use codec::{deserialize, serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::RwLock;
+
+const CAPABILITY_ID: &str = "wascc:keyvalue";
+
+enum Value {
+    Atom(String),
+    List(Vec<String>),
+    Set(HashSet<String>),
+}
+
+/// An in-process, non-persistent stand-in for the Redis key-value provider. All
+/// actors share a single store, and key expiration is not supported
+#[derive(Default)]
+pub struct MemoryKeyValueProvider {
+    store: RwLock<HashMap<String, Value>>,
+}
+
+impl MemoryKeyValueProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get(&self, req: GetRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        let store = self.store.read().unwrap();
+        let resp = match store.get(&req.key) {
+            Some(Value::Atom(s)) => GetResponse {
+                value: s.to_string(),
+                exists: true,
+            },
+            Some(_) => return Err(format!("Key {} does not hold a scalar value", req.key).into()),
+            None => GetResponse {
+                value: String::new(),
+                exists: false,
+            },
+        };
+        serialize(resp)
+    }
+
+    fn set(&self, req: SetRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.store
+            .write()
+            .unwrap()
+            .insert(req.key, Value::Atom(req.value.to_string()));
+        serialize(SetResponse { value: req.value })
+    }
+
+    fn add(&self, req: AddRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut store = self.store.write().unwrap();
+        let current = match store.get(&req.key) {
+            Some(Value::Atom(s)) => s.parse::<i32>()?,
+            Some(_) => return Err(format!("Key {} does not hold a scalar value", req.key).into()),
+            None => 0,
+        };
+        let value = current + req.value;
+        store.insert(req.key, Value::Atom(value.to_string()));
+        serialize(AddResponse { value })
+    }
+
+    fn del(&self, req: DelRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.store.write().unwrap().remove(&req.key);
+        serialize(DelResponse { key: req.key })
+    }
+
+    fn list_clear(&self, req: ListClearRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.del(DelRequest { key: req.key })
+    }
+
+    fn list_range(&self, req: ListRangeRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        let store = self.store.read().unwrap();
+        let values = match store.get(&req.key) {
+            Some(Value::List(l)) => {
+                let len = l.len() as i32;
+                let start = normalize_index(req.start, len).max(0);
+                let stop = normalize_index(req.stop, len).min(len - 1);
+                if start > stop {
+                    vec![]
+                } else {
+                    l[start as usize..=stop as usize].to_vec()
+                }
+            }
+            Some(_) => return Err(format!("Key {} does not hold a list", req.key).into()),
+            None => vec![],
+        };
+        serialize(ListRangeResponse { values })
+    }
+
+    fn list_push(&self, req: ListPushRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut store = self.store.write().unwrap();
+        let entry = store
+            .entry(req.key.to_string())
+            .or_insert_with(|| Value::List(vec![]));
+        match entry {
+            Value::List(l) => {
+                l.push(req.value);
+                serialize(ListResponse {
+                    new_count: l.len() as i32,
+                })
+            }
+            _ => Err(format!("Key {} does not hold a list", req.key).into()),
+        }
+    }
+
+    fn list_del(&self, req: ListDelItemRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut store = self.store.write().unwrap();
+        match store.get_mut(&req.key) {
+            Some(Value::List(l)) => {
+                l.retain(|v| *v != req.value);
+                serialize(ListResponse {
+                    new_count: l.len() as i32,
+                })
+            }
+            Some(_) => Err(format!("Key {} does not hold a list", req.key).into()),
+            None => serialize(ListResponse { new_count: 0 }),
+        }
+    }
+
+    fn set_add(&self, req: SetAddRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut store = self.store.write().unwrap();
+        let entry = store
+            .entry(req.key.to_string())
+            .or_insert_with(|| Value::Set(HashSet::new()));
+        match entry {
+            Value::Set(s) => {
+                let added = s.insert(req.value);
+                serialize(SetOperationResponse {
+                    new_count: if added { 1 } else { 0 },
+                })
+            }
+            _ => Err(format!("Key {} does not hold a set", req.key).into()),
+        }
+    }
+
+    fn set_remove(&self, req: SetRemoveRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut store = self.store.write().unwrap();
+        match store.get_mut(&req.key) {
+            Some(Value::Set(s)) => {
+                let removed = s.remove(&req.value);
+                serialize(SetOperationResponse {
+                    new_count: if removed { 1 } else { 0 },
+                })
+            }
+            Some(_) => Err(format!("Key {} does not hold a set", req.key).into()),
+            None => serialize(SetOperationResponse { new_count: 0 }),
+        }
+    }
+
+    fn set_union(&self, req: SetUnionRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        let store = self.store.read().unwrap();
+        let mut union = HashSet::new();
+        for key in req.keys.iter() {
+            if let Some(Value::Set(s)) = store.get(key) {
+                union.extend(s.iter().cloned());
+            }
+        }
+        serialize(SetQueryResponse {
+            values: union.into_iter().collect(),
+        })
+    }
+
+    fn set_intersect(&self, req: SetIntersectionRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        let store = self.store.read().unwrap();
+        let mut sets = req.keys.iter().map(|k| match store.get(k) {
+            Some(Value::Set(s)) => s.clone(),
+            _ => HashSet::new(),
+        });
+        let first = sets.next().unwrap_or_default();
+        let values = sets
+            .fold(first, |acc, s| acc.intersection(&s).cloned().collect())
+            .into_iter()
+            .collect();
+        serialize(SetQueryResponse { values })
+    }
+
+    fn set_query(&self, req: SetQueryRequest) -> Result<Vec<u8>, Box<dyn Error>> {
+        let store = self.store.read().unwrap();
+        let values = match store.get(&req.key) {
+            Some(Value::Set(s)) => s.iter().cloned().collect(),
+            Some(_) => return Err(format!("Key {} does not hold a set", req.key).into()),
+            None => vec![],
+        };
+        serialize(SetQueryResponse { values })
+    }
+
+    fn exists(&self, req: KeyExistsQuery) -> Result<Vec<u8>, Box<dyn Error>> {
+        let exists = self.store.read().unwrap().contains_key(&req.key);
+        serialize(GetResponse {
+            value: String::new(),
+            exists,
+        })
+    }
+}
+
+/// Converts a Redis-style (possibly negative) list index into an absolute one
+fn normalize_index(idx: i32, len: i32) -> i32 {
+    if idx < 0 {
+        len + idx
+    } else {
+        idx
+    }
+}
+
+impl CapabilityProvider for MemoryKeyValueProvider {
+    fn capability_id(&self) -> &'static str {
+        CAPABILITY_ID
+    }
+
+    fn configure_dispatch(&self, _dispatcher: Box<dyn Dispatcher>) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "Gantry In-Memory Key-Value Store"
+    }
+
+    fn handle_call(&self, actor: &str, op: &str, msg: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        trace!("Received key-value operation {} from {}", op, actor);
+        match op {
+            OP_CONFIGURE | OP_REMOVE_ACTOR => Ok(vec![]),
+            OP_GET => self.get(deserialize(msg)?),
+            OP_SET => self.set(deserialize(msg)?),
+            OP_ADD => self.add(deserialize(msg)?),
+            OP_DEL => self.del(deserialize(msg)?),
+            OP_CLEAR => self.list_clear(deserialize(msg)?),
+            OP_RANGE => self.list_range(deserialize(msg)?),
+            OP_PUSH => self.list_push(deserialize(msg)?),
+            OP_LIST_DEL => self.list_del(deserialize(msg)?),
+            OP_SET_ADD => self.set_add(deserialize(msg)?),
+            OP_SET_REMOVE => self.set_remove(deserialize(msg)?),
+            OP_SET_UNION => self.set_union(deserialize(msg)?),
+            OP_SET_INTERSECT => self.set_intersect(deserialize(msg)?),
+            OP_SET_QUERY => self.set_query(deserialize(msg)?),
+            OP_KEY_EXISTS => self.exists(deserialize(msg)?),
+            _ => Err(format!("Unsupported key-value operation: {}", op).into()),
+        }
+    }
+}
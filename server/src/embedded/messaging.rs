@@ -0,0 +1,313 @@
+use codec::capabilities::{CapabilityProvider, Dispatcher, NullDispatcher};
+use codec::core::{CapabilityConfiguration, OP_CONFIGURE, OP_REMOVE_ACTOR};
+use codec::messaging::*;
+use codec::{deserialize, serialize};
+use crossbeam_channel::{bounded, unbounded, Sender};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+
+const CAPABILITY_ID: &str = "wascc:messaging";
+
+/// Delivers the messages routed to one subscriber on a thread of its own, in the order in
+/// which they were routed. The thread finishes once the worker is dropped, and messages
+/// still queued then are discarded
+struct Worker {
+    queue: Sender<BrokerMessage>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Worker {
+    fn spawn<F>(deliver: F) -> Worker
+    where
+        F: Fn(BrokerMessage) + Send + 'static,
+    {
+        let (queue, r) = unbounded::<BrokerMessage>();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let stop = cancelled.clone();
+        std::thread::spawn(move || {
+            for msg in r {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                deliver(msg);
+            }
+        });
+        Worker { queue, cancelled }
+    }
+
+    fn send(&self, msg: BrokerMessage) {
+        let _ = self.queue.send(msg);
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+/// An in-process stand-in for the NATS messaging provider. Actors subscribe through the
+/// usual `SUBSCRIPTION` configuration value, and code running in the same process as the
+/// host (such as tests or an embedding application) talks to them through the
+/// [`LoopbackBroker`] handle. Nothing is reachable from outside the process
+#[derive(Default)]
+pub struct LoopbackMessagingProvider {
+    broker: LoopbackBroker,
+}
+
+impl LoopbackMessagingProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Obtains a handle through which in-process code can publish, subscribe and
+    /// make requests against the actors bound to this provider
+    pub fn broker(&self) -> LoopbackBroker {
+        self.broker.clone()
+    }
+}
+
+/// A cloneable handle to the loopback message broker
+#[derive(Clone)]
+pub struct LoopbackBroker {
+    inner: Arc<BrokerInner>,
+}
+
+struct BrokerInner {
+    dispatcher: RwLock<Box<dyn Dispatcher>>,
+    actor_subscriptions: RwLock<HashMap<String, (Vec<String>, Worker)>>,
+    subscriptions: RwLock<HashMap<u64, (String, Worker)>>,
+    next_id: AtomicU64,
+}
+
+impl Default for LoopbackBroker {
+    fn default() -> Self {
+        LoopbackBroker {
+            inner: Arc::new(BrokerInner {
+                dispatcher: RwLock::new(Box::new(NullDispatcher::new())),
+                actor_subscriptions: RwLock::new(HashMap::new()),
+                subscriptions: RwLock::new(HashMap::new()),
+                next_id: AtomicU64::new(1),
+            }),
+        }
+    }
+}
+
+impl LoopbackBroker {
+    /// Registers a handler for all messages published on subjects matching the given
+    /// (possibly wildcarded) subject, returning an identifier used to unsubscribe. The
+    /// handler is invoked for one message at a time, in the order they were published
+    pub fn subscribe<F>(&self, subject: &str, handler: F) -> u64
+    where
+        F: Fn(&BrokerMessage) + Send + Sync + 'static,
+    {
+        let id = self.inner.next_id.fetch_add(1, Ordering::SeqCst);
+        let worker = Worker::spawn(move |msg| handler(&msg));
+        self.inner
+            .subscriptions
+            .write()
+            .unwrap()
+            .insert(id, (subject.to_string(), worker));
+        id
+    }
+
+    pub fn unsubscribe(&self, id: u64) {
+        self.inner.subscriptions.write().unwrap().remove(&id);
+    }
+
+    /// Publishes a message to every matching actor and in-process subscriber
+    pub fn publish(
+        &self,
+        subject: &str,
+        reply_to: Option<&str>,
+        body: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        self.route(BrokerMessage {
+            subject: subject.to_string(),
+            reply_to: reply_to.unwrap_or("").to_string(),
+            body: body.to_vec(),
+        })
+    }
+
+    /// Publishes a message with a unique reply subject and waits for the first reply
+    pub fn request(
+        &self,
+        subject: &str,
+        body: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let inbox = format!(
+            "_INBOX.{}",
+            self.inner.next_id.fetch_add(1, Ordering::SeqCst)
+        );
+        let (s, r) = bounded(1);
+        let id = self.subscribe(&inbox, move |msg| {
+            let _ = s.try_send(msg.body.clone());
+        });
+        let res = self.publish(subject, Some(&inbox), body).and_then(|_| {
+            r.recv_timeout(timeout)
+                .map_err(|_| format!("Request on {} timed out", subject).into())
+        });
+        self.unsubscribe(id);
+        res
+    }
+
+    /// Queues a message for every matching in-process subscriber and actor. Each
+    /// subscriber receives its messages on its own thread, so an actor is never re-entered
+    /// while it is still handling a call, even when an in-process subscriber publishes in
+    /// reply to a message the actor has just published
+    fn route(&self, msg: BrokerMessage) -> Result<(), Box<dyn Error>> {
+        for (sub, worker) in self.inner.subscriptions.read().unwrap().values() {
+            if subject_matches(sub, &msg.subject) {
+                worker.send(msg.clone());
+            }
+        }
+        for (subs, worker) in self.inner.actor_subscriptions.read().unwrap().values() {
+            if subs.iter().any(|s| subject_matches(s, &msg.subject)) {
+                worker.send(msg.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts delivering the messages routed to an actor. The worker holds the broker
+    /// weakly, since the broker holds the worker
+    fn actor_worker(&self, actor: &str) -> Worker {
+        let broker: Weak<BrokerInner> = Arc::downgrade(&self.inner);
+        let actor = actor.to_string();
+        Worker::spawn(move |msg| {
+            if let Some(inner) = broker.upgrade() {
+                if let Err(e) = deliver(&inner, &actor, msg) {
+                    error!("Failed to deliver message to {}: {}", actor, e);
+                }
+            }
+        })
+    }
+}
+
+fn deliver(inner: &BrokerInner, actor: &str, msg: BrokerMessage) -> Result<(), Box<dyn Error>> {
+    let buf = serialize(DeliverMessage { message: msg })?;
+    inner
+        .dispatcher
+        .read()
+        .unwrap()
+        .dispatch(actor, OP_DELIVER_MESSAGE, &buf)?;
+    Ok(())
+}
+
+/// Matches a NATS-style subject against a subscription, where `*` matches exactly one
+/// token and a trailing `>` matches one or more remaining tokens
+fn subject_matches(subscription: &str, subject: &str) -> bool {
+    let mut sub_tokens = subscription.split('.');
+    let mut subj_tokens = subject.split('.');
+    loop {
+        match (sub_tokens.next(), subj_tokens.next()) {
+            (Some(">"), Some(_)) => return true,
+            (Some("*"), Some(_)) => {}
+            (Some(a), Some(b)) if a == b => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Lets a `gantryclient::Client` in the same process, such as the one behind the HTTP
+/// gateway, speak the Gantry protocol over the broker
+impl gantryclient::Transport for LoopbackBroker {
+    fn request(
+        &self,
+        subject: &str,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        LoopbackBroker::request(self, subject, payload, timeout)
+    }
+
+    fn publish(&self, subject: &str, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        LoopbackBroker::publish(self, subject, None, payload)
+    }
+
+    fn subscribe(
+        &self,
+        subject: &str,
+        handler: gantryclient::MessageHandler,
+    ) -> Result<u64, Box<dyn Error>> {
+        Ok(LoopbackBroker::subscribe(self, subject, move |msg| {
+            handler(&msg.body)
+        }))
+    }
+
+    fn unsubscribe(&self, id: u64) -> Result<(), Box<dyn Error>> {
+        LoopbackBroker::unsubscribe(self, id);
+        Ok(())
+    }
+}
+
+impl CapabilityProvider for LoopbackMessagingProvider {
+    fn capability_id(&self) -> &'static str {
+        CAPABILITY_ID
+    }
+
+    fn configure_dispatch(&self, dispatcher: Box<dyn Dispatcher>) -> Result<(), Box<dyn Error>> {
+        *self.broker.inner.dispatcher.write().unwrap() = dispatcher;
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "Gantry Loopback Messaging"
+    }
+
+    fn handle_call(&self, actor: &str, op: &str, msg: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        trace!("Received messaging operation {} from {}", op, actor);
+        match op {
+            OP_CONFIGURE => {
+                let config: CapabilityConfiguration = deserialize(msg)?;
+                let subs = config
+                    .values
+                    .get("SUBSCRIPTION")
+                    .map(|s| {
+                        s.split(',')
+                            .map(|s| s.trim().to_string())
+                            .filter(|s| !s.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let worker = self.broker.actor_worker(&config.module);
+                self.broker
+                    .inner
+                    .actor_subscriptions
+                    .write()
+                    .unwrap()
+                    .insert(config.module, (subs, worker));
+                Ok(vec![])
+            }
+            OP_REMOVE_ACTOR => {
+                let config: CapabilityConfiguration = deserialize(msg)?;
+                self.broker
+                    .inner
+                    .actor_subscriptions
+                    .write()
+                    .unwrap()
+                    .remove(&config.module);
+                Ok(vec![])
+            }
+            OP_PUBLISH_MESSAGE => {
+                let pubmsg: PublishMessage = deserialize(msg)?;
+                self.broker.route(pubmsg.message)?;
+                Ok(vec![])
+            }
+            OP_PERFORM_REQUEST => {
+                let req: RequestMessage = deserialize(msg)?;
+                self.broker.request(
+                    &req.subject,
+                    &req.body,
+                    Duration::from_millis(req.timeout_ms as u64),
+                )
+            }
+            _ => Err(format!("Unsupported messaging operation: {}", op).into()),
+        }
+    }
+}
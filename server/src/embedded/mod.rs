@@ -0,0 +1,12 @@
+//! In-process stand-ins for the capability providers Gantry normally loads from
+//! native libraries. These let a single process run the registry end to end without
+//! Redis, MinIO or an external NATS server, which is primarily useful for local
+//! development and integration testing.
+
+mod blobstore;
+mod keyvalue;
+mod messaging;
+
+pub use blobstore::FsBlobstoreProvider;
+pub use keyvalue::MemoryKeyValueProvider;
+pub use messaging::{LoopbackBroker, LoopbackMessagingProvider};
@@ -0,0 +1,458 @@
+//! An HTTP gateway to the registry, for tools that cannot speak the NATS protocol. Every
+//! request is translated into the corresponding Gantry protocol request through a
+//! [`gantryclient::Client`], with request and response bodies being the JSON form of the
+//! `gantry_protocol` types. Modules are transferred as raw bytes, and downloads are
+//! streamed to the caller with chunked transfer encoding as chunks arrive from the registry.
+//!
+//! | Method | Path | |
+//! |---|---|---|
+//! | `GET` | `/v1/catalog/{actors,accounts,operators}` | Queries the catalog, optionally filtered by `issuer`, `operator` and `subject` |
+//! | `GET` | `/v1/tokens/{subject}` | The latest stored revision of a token |
+//! | `PUT` | `/v1/tokens` | Puts a token (`{"raw_token": "..."}`) |
+//! | `DELETE` | `/v1/tokens` | Revokes a subject, given a proven `TokenChange` (see `gantryclient::change_proof`) |
+//! | `GET` | `/v1/modules/{actor}` | Downloads an actor module |
+//! | `PUT` | `/v1/modules/{actor}` | Uploads an actor module |
+//!
+//! A subset of the OCI distribution API is also served beneath `/v2/` (see [`crate::oci`]).
+//!
+//! Requests are authorized with the registry's own token model: the bearer token must be
+//! a JWT signed with the key of an account or operator whose trust chain the catalog
+//! verifies, and it must expire within an hour. See `gantryclient::bearer_token`. For the
+//! benefit of OCI tooling, the token may also be supplied as the password of HTTP basic
+//! authentication, with any user name. Requests that change a subject (revoking a token
+//! or uploading a module) are refused with `403` unless the key that signed the bearer
+//! token is the subject's issuer, the operator at the root of its trust chain, or one of
+//! that operator's signers.
+
+use crate::oci::{self, Staging};
+use gantryclient::{CatalogQuery, Client, FileChunk, QueryType, Token, TokenChange};
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use wascap::jwt::{self, Account, Claims};
+
+const WORKERS: usize = 4;
+const MAX_TOKEN_TTL: u64 = 60 * 60;
+const MAX_MODULE_BYTES: u64 = 64 * 1024 * 1024;
+
+pub(crate) struct GatewayError {
+    status: u16,
+    /// The OCI distribution error code, for errors raised beneath `/v2/`
+    code: Option<&'static str>,
+    message: String,
+}
+
+impl GatewayError {
+    pub(crate) fn new(status: u16, message: &str) -> GatewayError {
+        GatewayError {
+            status,
+            code: None,
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn oci(status: u16, code: &'static str, message: &str) -> GatewayError {
+        GatewayError {
+            status,
+            code: Some(code),
+            message: message.to_string(),
+        }
+    }
+
+    /// A failed request to the registry itself
+    pub(crate) fn upstream(e: Box<dyn ::std::error::Error>) -> GatewayError {
+        GatewayError::new(502, &e.to_string())
+    }
+
+    /// The same error, reported with an OCI distribution error code
+    pub(crate) fn with_code(self, code: &'static str) -> GatewayError {
+        GatewayError {
+            code: Some(code),
+            ..self
+        }
+    }
+}
+
+pub(crate) type GatewayResult<T> = Result<T, GatewayError>;
+pub(crate) type HttpResponse = Response<Box<dyn Read + Send>>;
+
+/// Serves the gateway on the given address (e.g. `0.0.0.0:8080`) from a small pool of
+/// worker threads, returning their handles
+pub fn serve(
+    addr: &str,
+    client: Client,
+) -> Result<Vec<JoinHandle<()>>, Box<dyn ::std::error::Error>> {
+    let server = Arc::new(Server::http(addr).map_err(|e| e.to_string())?);
+    let staging = Arc::new(Staging::default());
+    info!("Serving the Gantry HTTP gateway on {}", addr);
+    let handles = (0..WORKERS)
+        .map(|_| {
+            let server = server.clone();
+            let client = client.clone();
+            let staging = staging.clone();
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(&client, &staging, request);
+                }
+            })
+        })
+        .collect();
+    Ok(handles)
+}
+
+fn handle(client: &Client, staging: &Staging, mut request: Request) {
+    let response = match authorize(client, &request) {
+        Ok(caller) => {
+            debug!("{} {} by {}", request.method(), request.url(), caller);
+            route(client, staging, &mut request, &caller)
+        }
+        Err(e) => Err(e),
+    };
+    let response = response.unwrap_or_else(|e| {
+        let body = match e.code {
+            Some(code) => serde_json::json!({ "errors": [{ "code": code, "message": e.message }] }),
+            None => serde_json::json!({ "error": e.message }),
+        };
+        let response = json_response(body.to_string().into_bytes()).with_status_code(e.status);
+        if e.status == 401 {
+            response.with_header(header("WWW-Authenticate", "Basic realm=\"gantry\""))
+        } else {
+            response
+        }
+    });
+    if let Err(e) = request.respond(response) {
+        error!("Failed to send HTTP response: {}", e);
+    }
+}
+
+fn route(
+    client: &Client,
+    staging: &Staging,
+    request: &mut Request,
+    caller: &str,
+) -> GatewayResult<HttpResponse> {
+    let url = request.url().to_string();
+    let (path, query) = match url.find('?') {
+        Some(i) => (&url[..i], &url[i + 1..]),
+        None => (url.as_str(), ""),
+    };
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let method = request.method().clone();
+    if segments[0] == "v2" {
+        return oci::route(client, staging, request, caller, &method, &segments[1..], query);
+    }
+    match (method, segments.as_slice()) {
+        (Method::Get, ["v1", "catalog", kind]) => query_catalog(client, kind, query),
+        (Method::Get, ["v1", "tokens", subject]) => get_token(client, subject),
+        (Method::Put, ["v1", "tokens"]) => {
+            let token = read_token(request)?;
+            to_json(&client.put_token(&token).map_err(GatewayError::upstream)?)
+        }
+        (Method::Delete, ["v1", "tokens"]) => {
+            let change = read_change(request)?;
+            authorize_subject(client, caller, &change.subject)?;
+            to_json(&client.revoke(&change).map_err(GatewayError::upstream)?)
+        }
+        (Method::Get, ["v1", "modules", actor]) => download(client, actor),
+        (Method::Put, ["v1", "modules", actor]) => {
+            let actor = actor.to_string();
+            authorize_subject(client, caller, &actor)?;
+            let bytes = read_body(request, MAX_MODULE_BYTES)?;
+            to_json(
+                &client
+                    .upload_actor(&actor, &bytes)
+                    .map_err(GatewayError::upstream)?,
+            )
+        }
+        _ => Err(GatewayError::new(404, "Not found")),
+    }
+}
+
+/// Checks the bearer token, returning the key that signed it
+fn authorize(client: &Client, request: &Request) -> GatewayResult<String> {
+    let unauthorized = |msg: &str| GatewayError::new(401, msg);
+    let header = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .ok_or_else(|| unauthorized("A bearer token is required"))?;
+    let value = header.value.as_str().trim();
+    let token = if let Some(token) = value.strip_prefix("Bearer ") {
+        token.trim().to_string()
+    } else if let Some(basic) = value.strip_prefix("Basic ") {
+        let decoded = base64::decode(basic.trim())
+            .ok()
+            .and_then(|b| String::from_utf8(b).ok())
+            .ok_or_else(|| unauthorized("Invalid basic credentials"))?;
+        decoded
+            .splitn(2, ':')
+            .nth(1)
+            .ok_or_else(|| unauthorized("Invalid basic credentials"))?
+            .to_string()
+    } else {
+        return Err(unauthorized("A bearer token is required"));
+    };
+    let token = token.as_str();
+
+    let validation = jwt::validate_token::<Account>(token)
+        .map_err(|e| unauthorized(&format!("Invalid bearer token: {}", e)))?;
+    if !validation.signature_valid || validation.expired || validation.cannot_use_yet {
+        return Err(unauthorized("The bearer token is not valid at this time"));
+    }
+    let claims = Claims::<Account>::decode(token)
+        .map_err(|e| unauthorized(&format!("Invalid bearer token: {}", e)))?;
+    match claims.expires {
+        Some(exp) if exp <= now() + MAX_TOKEN_TTL => {}
+        _ => return Err(unauthorized("Bearer tokens must expire within an hour")),
+    }
+
+    let report = client
+        .verify_chain(&claims.issuer)
+        .map_err(GatewayError::upstream)?;
+    if !report.trusted {
+        return Err(unauthorized(&format!(
+            "{} is not trusted by the registry",
+            claims.issuer
+        )));
+    }
+    Ok(claims.issuer)
+}
+
+/// Checks that the caller has authority over the latest revision of a subject in the
+/// catalog (see [`authorize_issuer`])
+pub(crate) fn authorize_subject(client: &Client, caller: &str, subject: &str) -> GatewayResult<()> {
+    let result = client
+        .query_catalog(&CatalogQuery {
+            query_type: query_type_of(subject)?,
+            issuer: None,
+            operator: None,
+            subject: Some(subject.to_string()),
+        })
+        .map_err(GatewayError::upstream)?
+        .results
+        .into_iter()
+        .next()
+        .ok_or_else(|| GatewayError::new(404, &format!("{} is not in the catalog", subject)))?;
+    authorize_issuer(client, caller, subject, &result.issuer, result.operator.as_deref())
+}
+
+/// Checks that the caller has authority over a subject with the given issuer: that the
+/// caller is the issuer itself, the trusted operator at the root of the subject's trust
+/// chain, or one of that operator's signers. The bearer token only shows that the caller
+/// is trusted somewhere in the registry, not that it may change this subject
+pub(crate) fn authorize_issuer(
+    client: &Client,
+    caller: &str,
+    subject: &str,
+    issuer: &str,
+    operator: Option<&str>,
+) -> GatewayResult<()> {
+    if caller == issuer || operator == Some(caller) {
+        return Ok(());
+    }
+    if let Some(operator) = operator {
+        let roots = client.trust_roots().map_err(GatewayError::upstream)?;
+        let signer = roots
+            .operators
+            .iter()
+            .any(|r| r.operator == operator && r.signers.iter().any(|s| s == caller));
+        if signer {
+            return Ok(());
+        }
+    }
+    Err(GatewayError::new(
+        403,
+        &format!("{} has no authority over {}", caller, subject),
+    ))
+}
+
+/// The operator at the root of the trust chain of an account in the catalog
+pub(crate) fn operator_of_account(client: &Client, account: &str) -> GatewayResult<Option<String>> {
+    Ok(client
+        .query_catalog(&CatalogQuery {
+            query_type: QueryType::Account,
+            issuer: None,
+            operator: None,
+            subject: Some(account.to_string()),
+        })
+        .map_err(GatewayError::upstream)?
+        .results
+        .into_iter()
+        .next()
+        .and_then(|r| r.operator))
+}
+
+fn query_type_of(subject: &str) -> GatewayResult<QueryType> {
+    match subject.chars().next() {
+        Some('M') => Ok(QueryType::Actor),
+        Some('A') => Ok(QueryType::Account),
+        Some('O') => Ok(QueryType::Operator),
+        _ => Err(GatewayError::new(400, "Not a token subject")),
+    }
+}
+
+fn query_catalog(client: &Client, kind: &str, query: &str) -> GatewayResult<HttpResponse> {
+    let query_type = match kind {
+        "actors" => QueryType::Actor,
+        "accounts" => QueryType::Account,
+        "operators" => QueryType::Operator,
+        _ => return Err(GatewayError::new(404, "Not found")),
+    };
+    let results = client
+        .query_catalog(&CatalogQuery {
+            query_type,
+            issuer: query_param(query, "issuer"),
+            operator: query_param(query, "operator"),
+            subject: query_param(query, "subject"),
+        })
+        .map_err(GatewayError::upstream)?;
+    to_json(&results)
+}
+
+fn get_token(client: &Client, subject: &str) -> GatewayResult<HttpResponse> {
+    let export = client
+        .export_subject(query_type_of(subject)?, subject)
+        .map_err(GatewayError::upstream)?;
+    let latest = export
+        .tokens
+        .into_iter()
+        .filter(|t| t.subject == subject)
+        .max_by_key(|t| t.revision)
+        .ok_or_else(|| GatewayError::new(404, &format!("{} is not in the catalog", subject)))?;
+    to_json(&latest)
+}
+
+fn download(client: &Client, actor: &str) -> GatewayResult<HttpResponse> {
+    let (s, r) = crossbeam_channel::unbounded();
+    let ack = client
+        .download_actor(actor, move |chunk| {
+            s.send(chunk).map_err(|e| e.to_string().into())
+        })
+        .map_err(GatewayError::upstream)?;
+    if !ack.success {
+        return Err(GatewayError::new(
+            404,
+            &format!("Download of actor {} was refused", actor),
+        ));
+    }
+    let reader = ChunkReader {
+        chunks: r,
+        deadline: Instant::now() + client.timeouts().download,
+        current: Vec::new(),
+        offset: 0,
+        done: false,
+    };
+    // With no length supplied, the body is sent with chunked transfer encoding
+    Ok(Response::new(
+        StatusCode(200),
+        vec![header("Content-Type", "application/wasm")],
+        Box::new(reader) as Box<dyn Read + Send>,
+        None,
+        None,
+    ))
+}
+
+/// Presents the chunks of a module download as a byte stream, ending after the final chunk
+struct ChunkReader {
+    chunks: crossbeam_channel::Receiver<FileChunk>,
+    deadline: Instant,
+    current: Vec<u8>,
+    offset: usize,
+    done: bool,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.current.len() {
+            if self.done {
+                return Ok(0);
+            }
+            let remaining = self.deadline.saturating_duration_since(Instant::now());
+            let chunk = self.chunks.recv_timeout(remaining).map_err(|_| {
+                io::Error::new(io::ErrorKind::TimedOut, "Timed out awaiting module chunks")
+            })?;
+            self.done = chunk.sequence_no >= chunk.total_chunks;
+            self.current = chunk.chunk_bytes;
+            self.offset = 0;
+        }
+        let n = buf.len().min(self.current.len() - self.offset);
+        buf[..n].copy_from_slice(&self.current[self.offset..self.offset + n]);
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+/// Finds a parameter in a query string. Only `:` is percent-decoded, as parameters are
+/// either public keys, numbers or digests
+pub(crate) fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|p| {
+            let mut kv = p.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) if k == name && !v.is_empty() => {
+                    Some(v.replace("%3A", ":").replace("%3a", ":"))
+                }
+                _ => None,
+            }
+        })
+        .next()
+}
+
+fn read_token(request: &mut Request) -> GatewayResult<Token> {
+    let body = read_body(request, 64 * 1024)?;
+    serde_json::from_slice::<Token>(&body)
+        .map_err(|e| GatewayError::new(400, &format!("Invalid token: {}", e)))
+}
+
+fn read_change(request: &mut Request) -> GatewayResult<TokenChange> {
+    let body = read_body(request, 64 * 1024)?;
+    serde_json::from_slice::<TokenChange>(&body)
+        .map_err(|e| GatewayError::new(400, &format!("Invalid token change: {}", e)))
+}
+
+/// Reads a request body, whether sent with a length or with chunked transfer encoding
+pub(crate) fn read_body(request: &mut Request, limit: u64) -> GatewayResult<Vec<u8>> {
+    if request.body_length().map_or(false, |len| len as u64 > limit) {
+        return Err(GatewayError::new(413, "Request body is too large"));
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(limit + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| GatewayError::new(400, &e.to_string()))?;
+    if body.len() as u64 > limit {
+        return Err(GatewayError::new(413, "Request body is too large"));
+    }
+    Ok(body)
+}
+
+pub(crate) fn to_json<T: serde::Serialize>(value: &T) -> GatewayResult<HttpResponse> {
+    let body = serde_json::to_vec(value).map_err(|e| GatewayError::new(500, &e.to_string()))?;
+    Ok(json_response(body))
+}
+
+pub(crate) fn json_response(body: Vec<u8>) -> HttpResponse {
+    let len = body.len();
+    Response::new(
+        StatusCode(200),
+        vec![header("Content-Type", "application/json")],
+        Box::new(io::Cursor::new(body)) as Box<dyn Read + Send>,
+        Some(len),
+        None,
+    )
+}
+
+pub(crate) fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,267 @@
+#[macro_use]
+extern crate log;
+
+extern crate wascc_codec as codec;
+
+pub mod embedded;
+pub mod gateway;
+mod middleware;
+mod oci;
+
+use embedded::{
+    FsBlobstoreProvider, LoopbackBroker, LoopbackMessagingProvider, MemoryKeyValueProvider,
+};
+use middleware::JWTDecoder;
+use std::{collections::HashMap, path::PathBuf};
+use wascap::jwt::{Claims, Operator};
+use wascc_host::{host, Actor, NativeCapability};
+
+/// The key-value store used by the catalog actor
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyValueBackend {
+    /// A Redis server, reached through a separately loaded provider
+    Redis(String),
+    /// The in-process, non-persistent store
+    Memory,
+}
+
+/// The blob store used by the streams actor
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlobstoreBackend {
+    /// An S3-compatible endpoint (such as MinIO), reached through a separately loaded provider
+    S3 {
+        endpoint: String,
+        region: String,
+        access_key: String,
+        secret_key: String,
+    },
+    /// The in-process store that keeps blobs beneath the given directory
+    Filesystem(PathBuf),
+}
+
+/// The message broker through which clients reach the Gantry actors
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessagingBackend {
+    /// A NATS server, reached through a separately loaded provider
+    Nats(String),
+    /// The in-process broker, reachable only through the [`LoopbackBroker`] returned by [`start`]
+    Loopback,
+}
+
+/// Selects the implementation that backs each capability used by the Gantry actors.
+/// Backends that are not in-process must have their provider supplied to [`start`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backends {
+    pub keyvalue: KeyValueBackend,
+    pub blobstore: BlobstoreBackend,
+    pub messaging: MessagingBackend,
+}
+
+impl Default for Backends {
+    fn default() -> Self {
+        Backends {
+            keyvalue: KeyValueBackend::Redis("redis://127.0.0.1:6379".to_string()),
+            blobstore: BlobstoreBackend::S3 {
+                endpoint: "http://localhost:9000".to_string(),
+                region: "us-east-1".to_string(),
+                access_key: "minioadmin".to_string(),
+                secret_key: "minioadmin".to_string(),
+            },
+            messaging: MessagingBackend::Nats("nats://localhost:4222".to_string()),
+        }
+    }
+}
+
+/// Loads the Gantry actors, middleware and capability providers into the waSCC host
+/// and configures them. When the loopback messaging backend is selected, the returned
+/// broker is the only way to communicate with the registry
+pub fn start(
+    catalog: Actor,
+    streams: Actor,
+    operators: &[Claims<Operator>],
+    backends: &Backends,
+    provider_paths: &[PathBuf],
+) -> Result<Option<LoopbackBroker>, Box<dyn ::std::error::Error>> {
+    for operator in operators {
+        info!("Trusting Gantry operator : {}", operator.subject);
+    }
+    check_pairing(&catalog, &streams)?;
+    let catalog_key = catalog.public_key();
+    let streams_key = streams.public_key();
+    host::add_actor(catalog)?;
+    host::add_actor(streams)?;
+    host::add_middleware(JWTDecoder::new(&streams_key));
+    for p in provider_paths {
+        host::add_native_capability(NativeCapability::from_file(p)?)?;
+    }
+
+    if let KeyValueBackend::Memory = backends.keyvalue {
+        host::add_native_capability(NativeCapability::from_instance(
+            MemoryKeyValueProvider::new(),
+        )?)?;
+    }
+    if let BlobstoreBackend::Filesystem(_) = backends.blobstore {
+        host::add_native_capability(NativeCapability::from_instance(FsBlobstoreProvider::new())?)?;
+    }
+    let broker = match backends.messaging {
+        MessagingBackend::Loopback => {
+            let provider = LoopbackMessagingProvider::new();
+            let broker = provider.broker();
+            host::add_native_capability(NativeCapability::from_instance(provider)?)?;
+            Some(broker)
+        }
+        MessagingBackend::Nats(_) => None,
+    };
+
+    host::configure(
+        &catalog_key,
+        "wascc:keyvalue",
+        keyvalue_config(&backends.keyvalue),
+    )?;
+
+    host::configure(
+        &catalog_key,
+        "wascc:messaging",
+        generate_config(
+            "gantry.catalog.tokens.*,gantry.catalog.trust.*,gantry.catalog.audit.query,gantry.meta.info",
+            &backends.messaging,
+        ),
+    )?;
+
+    host::configure(
+        &streams_key,
+        "wascc:messaging",
+        generate_config(
+            "gantry.stream.get,gantry.stream.put,gantry.stream.upload.*,gantry.stream.credit.*,gantry.stream.info",
+            &backends.messaging,
+        ),
+    )?;
+
+    host::configure(&catalog_key, &catalog_key, operator_config(operators))?;
+    host::configure(&streams_key, &streams_key, streams_config(&catalog_key))?;
+
+    host::configure(
+        &streams_key,
+        "wascc:blobstore",
+        blobstore_config(&backends.blobstore),
+    )?;
+
+    Ok(broker)
+}
+
+/// Checks that the catalog and streams modules can work together before either is loaded.
+/// The streams actor is told the catalog's public key, so the two need not be signed with
+/// any particular keys, but they must be distinct actors issued by the same account and
+/// signed with the capabilities the server binds to them
+fn check_pairing(catalog: &Actor, streams: &Actor) -> Result<(), Box<dyn ::std::error::Error>> {
+    let catalog_claims = catalog.claims();
+    let streams_claims = streams.claims();
+    if catalog_claims.subject == streams_claims.subject {
+        return Err(format!(
+            "The catalog and streams modules are the same actor ({})",
+            catalog_claims.subject
+        )
+        .into());
+    }
+    if catalog_claims.issuer != streams_claims.issuer {
+        return Err(format!(
+            "The catalog actor {} is issued by account {}, but the streams actor {} is issued by {}. \
+             Sign both modules with the same account",
+            catalog_claims.subject, catalog_claims.issuer, streams_claims.subject, streams_claims.issuer
+        )
+        .into());
+    }
+    for (name, claims, required) in &[
+        ("catalog", &catalog_claims, ["wascc:keyvalue", "wascc:messaging"]),
+        ("streams", &streams_claims, ["wascc:messaging", "wascc:blobstore"]),
+    ] {
+        let caps = claims
+            .metadata
+            .as_ref()
+            .and_then(|m| m.caps.clone())
+            .unwrap_or_default();
+        if let Some(missing) = required.iter().find(|c| !caps.iter().any(|cap| cap == *c)) {
+            return Err(format!(
+                "The {} actor {} is not signed with the {} capability",
+                name, claims.subject, missing
+            )
+            .into());
+        }
+    }
+    info!(
+        "Pairing catalog actor {} with streams actor {}",
+        catalog_claims.subject, streams_claims.subject
+    );
+    Ok(())
+}
+
+fn streams_config(catalog_key: &str) -> HashMap<String, String> {
+    let mut hm = HashMap::new();
+    hm.insert("CATALOG_ACTOR".to_string(), catalog_key.to_string());
+
+    hm
+}
+
+fn generate_config(sub: &str, backend: &MessagingBackend) -> HashMap<String, String> {
+    let mut hm = HashMap::new();
+    hm.insert("SUBSCRIPTION".to_string(), sub.to_string());
+    if let MessagingBackend::Nats(url) = backend {
+        hm.insert("URL".to_string(), url.to_string());
+    }
+
+    hm
+}
+
+fn keyvalue_config(backend: &KeyValueBackend) -> HashMap<String, String> {
+    let mut hm = HashMap::new();
+    if let KeyValueBackend::Redis(url) = backend {
+        hm.insert("URL".to_string(), url.to_string());
+    }
+
+    hm
+}
+
+fn operator_config(operators: &[Claims<Operator>]) -> HashMap<String, String> {
+    let mut hm = HashMap::new();
+    let subjects: Vec<String> = operators.iter().map(|o| o.subject.to_string()).collect();
+    hm.insert("operators".to_string(), subjects.join(","));
+    for operator in operators {
+        let valid_signers = operator
+            .metadata
+            .as_ref()
+            .and_then(|m| m.valid_signers.clone())
+            .unwrap_or_default();
+        hm.insert(
+            format!("{}.signers", operator.subject),
+            valid_signers.join(","),
+        );
+        hm.insert(
+            format!("{}.issued_at", operator.subject),
+            operator.issued_at.to_string(),
+        );
+    }
+
+    hm
+}
+
+fn blobstore_config(backend: &BlobstoreBackend) -> HashMap<String, String> {
+    let mut hm = HashMap::new();
+    match backend {
+        BlobstoreBackend::Filesystem(root) => {
+            hm.insert("ROOT".to_string(), root.to_string_lossy().to_string());
+        }
+        BlobstoreBackend::S3 {
+            endpoint,
+            region,
+            access_key,
+            secret_key,
+        } => {
+            hm.insert("ENDPOINT".to_string(), endpoint.to_string());
+            hm.insert("REGION".to_string(), region.to_string());
+            hm.insert("AWS_ACCESS_KEY".to_string(), access_key.to_string());
+            hm.insert("AWS_SECRET_ACCESS_KEY".to_string(), secret_key.to_string());
+        }
+    }
+
+    hm
+}
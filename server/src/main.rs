@@ -1,15 +1,9 @@
-#[macro_use]
-extern crate log;
-
-extern crate wascc_codec as codec;
-mod middleware;
-
-use middleware::JWTDecoder;
-use std::{collections::HashMap, path::PathBuf};
+use gantry_server::{BlobstoreBackend, Backends, KeyValueBackend, MessagingBackend};
+use std::{path::PathBuf, str::FromStr};
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
 use wascap::jwt::{Claims, Operator};
-use wascc_host::{host, Actor, NativeCapability};
+use wascc_host::Actor;
 
 #[derive(Debug, StructOpt, Clone)]
 #[structopt(
@@ -35,54 +29,134 @@ struct CliCommand {
     #[structopt(short = "p", long = "provider", parse(from_os_str))]
     provider_paths: Vec<PathBuf>,
 
-    /// The Gantry operator JWT. Used for provenance verification of all WebAssembly
-    /// modules stored in the registry.
-    #[structopt(short = "o", long = "operator")]
-    operator_jwt: String,
+    /// A trusted Gantry operator JWT. Used for provenance verification of all WebAssembly
+    /// modules stored in the registry. May be supplied multiple times to host tokens from
+    /// several operators in one registry.
+    #[structopt(short = "o", long = "operator", required = true, number_of_values = 1)]
+    operator_jwts: Vec<String>,
+
+    /// The key-value store backing the catalog: `redis` (requires a provider) or the
+    /// in-process `memory` store
+    #[structopt(long = "keyvalue", default_value = "redis")]
+    keyvalue: KeyValueKind,
+
+    /// The blob store backing module storage: `s3` (requires a provider) or the
+    /// in-process `fs` store
+    #[structopt(long = "blobstore", default_value = "s3")]
+    blobstore: BlobstoreKind,
+
+    /// Root directory for module storage when using the `fs` blob store
+    #[structopt(long = "blob-root", parse(from_os_str), default_value = "/tmp/gantry")]
+    blob_root: PathBuf,
+
+    /// The message broker through which clients reach the registry: `nats` (requires a
+    /// provider) or the in-process `memory` broker, which is only reachable over HTTP
+    #[structopt(long = "messaging", default_value = "nats")]
+    messaging: MessagingKind,
+
+    /// Also serve the registry over HTTP on this address, e.g. 0.0.0.0:8080
+    #[structopt(long = "http")]
+    http_addr: Option<String>,
 }
 
-fn handle_command(cmd: CliCommand) -> Result<(), Box<dyn ::std::error::Error>> {
-    let operator: Claims<Operator> = Claims::<Operator>::decode(&cmd.operator_jwt)?;
-    info!("Gantry operator is : {}", operator.subject);
-    host::add_actor(Actor::from_file(cmd.catalog_path)?)?;
-    host::add_actor(Actor::from_file(cmd.streamer_path)?)?;
-    host::add_middleware(JWTDecoder::new());
-    cmd.provider_paths.iter().for_each(|p| {
-        host::add_native_capability(NativeCapability::from_file(p).unwrap()).unwrap();
-    });
-
-    host::configure(
-        "MCIXJVXAXKDX7UFYDFW2737SHVIRNZILS3ULODGEQOVCTWQ7HSGOHUY7",
-        "wascc:keyvalue",
-        redis_config(),
-    )?;
+#[derive(Debug, Clone, PartialEq)]
+enum KeyValueKind {
+    Redis,
+    Memory,
+}
 
-    host::configure(
-        "MCIXJVXAXKDX7UFYDFW2737SHVIRNZILS3ULODGEQOVCTWQ7HSGOHUY7",
-        "wascc:messaging",
-        generate_config("gantry.catalog.tokens.*"),
-    )?;
+impl FromStr for KeyValueKind {
+    type Err = std::io::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "redis" => Ok(KeyValueKind::Redis),
+            "memory" => Ok(KeyValueKind::Memory),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::Other, "bad key-value store")),
+        }
+    }
+}
 
-    host::configure(
-        "MATR36QS6IWITSNUS2I7V72R2I3ALJCIS2Y4FJQJZ33KQN5MRXDNJMJ2",
-        "wascc:messaging",
-        generate_config("gantry.stream.get,gantry.stream.put,gantry.stream.upload.*"),
-    )?;
+#[derive(Debug, Clone, PartialEq)]
+enum BlobstoreKind {
+    S3,
+    Fs,
+}
 
-    host::configure(
-        "MCIXJVXAXKDX7UFYDFW2737SHVIRNZILS3ULODGEQOVCTWQ7HSGOHUY7",
-        "MCIXJVXAXKDX7UFYDFW2737SHVIRNZILS3ULODGEQOVCTWQ7HSGOHUY7",
-        operator_config(
-            &operator.subject,
-            operator.metadata.unwrap().valid_signers.as_ref().unwrap(),
-        ),
-    )?;
+impl FromStr for BlobstoreKind {
+    type Err = std::io::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "s3" => Ok(BlobstoreKind::S3),
+            "fs" => Ok(BlobstoreKind::Fs),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::Other, "bad blob store")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum MessagingKind {
+    Nats,
+    Memory,
+}
 
-    host::configure(
-        "MATR36QS6IWITSNUS2I7V72R2I3ALJCIS2Y4FJQJZ33KQN5MRXDNJMJ2",
-        "wascc:blobstore",
-        generate_fs_config(),
+impl FromStr for MessagingKind {
+    type Err = std::io::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nats" => Ok(MessagingKind::Nats),
+            "memory" => Ok(MessagingKind::Memory),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::Other, "bad message broker")),
+        }
+    }
+}
+
+fn to_backends(cmd: &CliCommand) -> Backends {
+    let mut backends = Backends::default();
+    if cmd.keyvalue == KeyValueKind::Memory {
+        backends.keyvalue = KeyValueBackend::Memory;
+    }
+    if cmd.blobstore == BlobstoreKind::Fs {
+        backends.blobstore = BlobstoreBackend::Filesystem(cmd.blob_root.clone());
+    }
+    if cmd.messaging == MessagingKind::Memory {
+        backends.messaging = MessagingBackend::Loopback;
+    }
+    backends
+}
+
+fn handle_command(cmd: CliCommand) -> Result<(), Box<dyn ::std::error::Error>> {
+    let operators = cmd
+        .operator_jwts
+        .iter()
+        .map(|jwt| Claims::<Operator>::decode(jwt))
+        .collect::<Result<Vec<_>, _>>()?;
+    let backends = to_backends(&cmd);
+    if backends.messaging == MessagingBackend::Loopback && cmd.http_addr.is_none() {
+        return Err(
+            "The in-process message broker is only reachable over HTTP, so --http is required".into(),
+        );
+    }
+    let broker = gantry_server::start(
+        Actor::from_file(&cmd.catalog_path)?,
+        Actor::from_file(&cmd.streamer_path)?,
+        &operators,
+        &backends,
+        &cmd.provider_paths,
     )?;
+    if let Some(addr) = &cmd.http_addr {
+        let client = match (&backends.messaging, broker) {
+            (MessagingBackend::Nats(url), _) => gantryclient::Client::builder()
+                .server_urls(vec![url.to_string()])
+                .build()?,
+            (MessagingBackend::Loopback, Some(broker)) => {
+                gantryclient::Client::with_transport(broker)
+            }
+            (MessagingBackend::Loopback, None) => {
+                return Err("The in-process message broker was not started".into())
+            }
+        };
+        gantry_server::gateway::serve(addr, client)?;
+    }
 
     std::thread::park();
     Ok(())
@@ -101,42 +175,3 @@ fn main() -> Result<(), Box<dyn ::std::error::Error>> {
     }
     Ok(())
 }
-
-fn generate_config(sub: &str) -> HashMap<String, String> {
-    let mut hm = HashMap::new();
-    hm.insert("SUBSCRIPTION".to_string(), sub.to_string());
-    hm.insert("URL".to_string(), "nats://localhost:4222".to_string());
-
-    hm
-}
-
-fn redis_config() -> HashMap<String, String> {
-    let mut hm = HashMap::new();
-    hm.insert("URL".to_string(), "redis://127.0.0.1:6379".to_string());
-
-    hm
-}
-
-fn operator_config(op: &str, valid_signers: &[String]) -> HashMap<String, String> {
-    let mut hm = HashMap::new();
-    hm.insert("operator".to_string(), op.to_string());
-    hm.insert("signers".to_string(), valid_signers.join(","));
-
-    hm
-}
-
-fn generate_fs_config() -> HashMap<String, String> {
-    let mut hm = HashMap::new();
-    //hm.insert("ROOT".to_string(), "/tmp".to_string());
-    hm.insert("ENDPOINT".to_string(), "http://localhost:9000".to_string());
-    hm.insert("REGION".to_string(), "us-east-1".to_string());
-    hm.insert("AWS_ACCESS_KEY".to_string(), "minioadmin".to_string());
-    hm.insert(
-        "AWS_SECRET_ACCESS_KEY".to_string(),
-        "minioadmin".to_string(),
-    );
-
-
-
-    hm
-}
@@ -6,13 +6,19 @@ use wascap::jwt::Operator;
 use wascc_host::host::{Invocation, InvocationResponse};
 use wascc_host::Middleware;
 use gantry_protocol as protocol;
-use protocol::{serialize, deserialize};
+use codec::{deserialize, serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub(crate) struct JWTDecoder {}
+pub(crate) struct JWTDecoder {
+    // Only the streams actor may report uploads to the catalog
+    streams_actor: String,
+}
 
 impl JWTDecoder {
-    pub fn new() -> Self {
-        JWTDecoder {}
+    pub fn new(streams_actor: &str) -> Self {
+        JWTDecoder {
+            streams_actor: streams_actor.to_string(),
+        }
     }
 }
 
@@ -31,7 +37,64 @@ impl Middleware for JWTDecoder {
                 )?;
                 return Ok(newinv);
             }
-            
+            if msg.subject == protocol::catalog::SUBJECT_CATALOG_DELETE_TOKEN
+                || msg.subject == protocol::catalog::SUBJECT_CATALOG_REINSTATE_TOKEN
+                || msg.subject == protocol::catalog::SUBJECT_CATALOG_UNREGISTER_TOKEN
+            {
+                let newinv = stamp_change_signer(
+                    msg.body.as_slice(),
+                    msg.reply_to,
+                    msg.subject,
+                    &inv,
+                )?;
+                return Ok(newinv);
+            }
+            if msg.subject == protocol::audit::SUBJECT_AUDIT_APPEND {
+                if inv.origin != self.streams_actor {
+                    return Err(format!(
+                        "Audit records cannot be appended by {}",
+                        inv.origin
+                    )
+                    .into());
+                }
+                let newinv = stamp_audit_record(
+                    msg.body.as_slice(),
+                    msg.reply_to,
+                    msg.subject,
+                    &inv,
+                )?;
+                return Ok(newinv);
+            }
+            if msg.subject == protocol::stream::SUBJECT_MODULE_RECORD {
+                if inv.origin != self.streams_actor {
+                    return Err(format!("Uploads cannot be recorded by {}", inv.origin).into());
+                }
+                let newinv = stamp_module_record(
+                    msg.body.as_slice(),
+                    msg.reply_to,
+                    msg.subject,
+                    &inv,
+                )?;
+                return Ok(newinv);
+            }
+            if msg.subject.starts_with(protocol::stream::SUBJECT_STREAM_UPLOAD_PREFIX) {
+                let newinv = decompress_upload_chunk(
+                    msg.body.as_slice(),
+                    msg.reply_to,
+                    msg.subject,
+                    &inv,
+                )?;
+                return Ok(newinv);
+            }
+            if msg.subject == protocol::catalog::SUBJECT_CATALOG_TRUST_VERIFY {
+                let newinv = stamp_verification_request(
+                    msg.body.as_slice(),
+                    msg.reply_to,
+                    msg.subject,
+                    &inv,
+                )?;
+                return Ok(newinv);
+            }
         }
         Ok(inv)
     }
@@ -42,6 +105,18 @@ impl Middleware for JWTDecoder {
         Ok(response)
     }
     fn capability_pre_invoke(&self, inv: Invocation) -> wascc_host::Result<Invocation> {
+        if inv.origin == self.streams_actor
+            && inv.operation.rsplit('!').next() == Some(messaging::OP_PUBLISH_MESSAGE)
+        {
+            let msg = deserialize::<messaging::PublishMessage>(inv.msg.as_slice())?.message;
+            if msg.subject.starts_with(protocol::stream::SUBJECT_STREAM_DOWNLOAD_PREFIX) {
+                let (chunk, ct) =
+                    protocol::decode::<protocol::stream::FileChunk>(msg.body.as_slice())?;
+                if chunk.compression.is_some() {
+                    return compress_download_chunk(chunk, ct, msg.reply_to, msg.subject, &inv);
+                }
+            }
+        }
         Ok(inv)
     }
     fn capability_post_invoke(
@@ -56,13 +131,220 @@ fn decode_deliver_message(msg: &[u8]) -> wascc_host::Result<messaging::DeliverMe
     deserialize::<messaging::DeliverMessage>(msg).map_err(|e| e.into())
 }
 
+/// Actors have no access to a clock, so the time against which token expiry is
+/// checked during trust chain verification is supplied by the host
+fn stamp_verification_request(
+    body: &[u8],
+    reply_to: String,
+    subject: String,
+    inv: &Invocation,
+) -> wascc_host::Result<Invocation> {
+    let (mut req, ct) = protocol::decode::<protocol::catalog::ChainVerificationRequest>(body)?;
+    req.as_of = now();
+    let delivermsg = messaging::DeliverMessage {
+        message: messaging::BrokerMessage {
+            body: protocol::encode(ct, &req)?,
+            reply_to,
+            subject,
+        },
+    };
+
+    Ok(Invocation {
+        origin: inv.origin.clone(),
+        operation: inv.operation.clone(),
+        msg: serialize(&delivermsg)?,
+    })
+}
+
+/// The compression codecs link native code, which the streams actor cannot, so uploaded
+/// chunks are decompressed before they reach it
+fn decompress_upload_chunk(
+    body: &[u8],
+    reply_to: String,
+    subject: String,
+    inv: &Invocation,
+) -> wascc_host::Result<Invocation> {
+    let (mut chunk, ct) = protocol::decode::<protocol::stream::FileChunk>(body)?;
+    // The declared chunk size bounds the decompressed chunk, so it must not exceed the
+    // chunks the server accepts
+    if chunk.chunk_size > gantryclient::CHUNK_SIZE {
+        return Err(format!(
+            "Chunk size {} exceeds the largest accepted chunk of {} bytes",
+            chunk.chunk_size,
+            gantryclient::CHUNK_SIZE
+        )
+        .into());
+    }
+    if let Some(codec) = chunk.compression.take() {
+        chunk.chunk_bytes =
+            protocol::stream::decompress(&codec, &chunk.chunk_bytes, chunk.chunk_size)?;
+    }
+    let delivermsg = messaging::DeliverMessage {
+        message: messaging::BrokerMessage {
+            body: protocol::encode(ct, &chunk)?,
+            reply_to,
+            subject,
+        },
+    };
+
+    Ok(Invocation {
+        origin: inv.origin.clone(),
+        operation: inv.operation.clone(),
+        msg: serialize(&delivermsg)?,
+    })
+}
+
+/// The streams actor publishes downloaded chunks uncompressed, naming the codec negotiated
+/// for the download, and they are compressed on their way to the client
+fn compress_download_chunk(
+    mut chunk: protocol::stream::FileChunk,
+    ct: protocol::ContentType,
+    reply_to: String,
+    subject: String,
+    inv: &Invocation,
+) -> wascc_host::Result<Invocation> {
+    if let Some(ref codec) = chunk.compression {
+        chunk.chunk_bytes = protocol::stream::compress(codec, &chunk.chunk_bytes)?;
+    }
+    let pubmsg = messaging::PublishMessage {
+        message: messaging::BrokerMessage {
+            body: protocol::encode(ct, &chunk)?,
+            reply_to,
+            subject,
+        },
+    };
+
+    Ok(Invocation {
+        origin: inv.origin.clone(),
+        operation: inv.operation.clone(),
+        msg: serialize(&pubmsg)?,
+    })
+}
+
+/// Audit records reported by the streams actor are timestamped as they reach the catalog
+fn stamp_audit_record(
+    body: &[u8],
+    reply_to: String,
+    subject: String,
+    inv: &Invocation,
+) -> wascc_host::Result<Invocation> {
+    let (mut record, ct) = protocol::decode::<protocol::audit::AuditRecord>(body)?;
+    record.timestamp = now();
+    let delivermsg = messaging::DeliverMessage {
+        message: messaging::BrokerMessage {
+            body: protocol::encode(ct, &record)?,
+            reply_to,
+            subject,
+        },
+    };
+
+    Ok(Invocation {
+        origin: inv.origin.clone(),
+        operation: inv.operation.clone(),
+        msg: serialize(&delivermsg)?,
+    })
+}
+
+/// Completed uploads reported by the streams actor are timestamped as they reach the catalog
+fn stamp_module_record(
+    body: &[u8],
+    reply_to: String,
+    subject: String,
+    inv: &Invocation,
+) -> wascc_host::Result<Invocation> {
+    let mut info = protocol::deserialize::<protocol::stream::ModuleInfo>(body)?;
+    info.uploaded_at = Some(now());
+    let delivermsg = messaging::DeliverMessage {
+        message: messaging::BrokerMessage {
+            body: protocol::serialize(&info)?,
+            reply_to,
+            subject,
+        },
+    };
+
+    Ok(Invocation {
+        origin: inv.origin.clone(),
+        operation: inv.operation.clone(),
+        msg: serialize(&delivermsg)?,
+    })
+}
+
+/// Checks the proof of a change to a subject, stamping the key that signed it. Whether
+/// that key has authority over the subject is for the catalog to decide, so a change with
+/// an invalid proof is passed on unsigned, to be refused and audited by the catalog
+fn stamp_change_signer(
+    body: &[u8],
+    reply_to: String,
+    subject: String,
+    inv: &Invocation,
+) -> wascc_host::Result<Invocation> {
+    let (mut change, ct) = protocol::decode::<protocol::catalog::TokenChange>(body)?;
+    change.signer = match proof_signer(&change, &subject) {
+        Ok(signer) => Some(signer),
+        Err(e) => {
+            warn!("Rejecting the proof of a change to {}: {}", change.subject, e);
+            None
+        }
+    };
+    change.received_at = now();
+    let delivermsg = messaging::DeliverMessage {
+        message: messaging::BrokerMessage {
+            body: protocol::encode(ct, &change)?,
+            reply_to,
+            subject,
+        },
+    };
+
+    Ok(Invocation {
+        origin: inv.origin.clone(),
+        operation: inv.operation.clone(),
+        msg: serialize(&delivermsg)?,
+    })
+}
+
+fn proof_signer(
+    change: &protocol::catalog::TokenChange,
+    request_subject: &str,
+) -> Result<String, Box<dyn ::std::error::Error>> {
+    let validation = jwt::validate_token::<Account>(&change.proof)?;
+    if !validation.signature_valid || validation.expired || validation.cannot_use_yet {
+        return Err("the proof is not valid at this time".into());
+    }
+    let claims = jwt::Claims::<Account>::decode(&change.proof)?;
+    match claims.expires {
+        Some(exp) if exp <= now() + protocol::catalog::CHANGE_PROOF_TTL => {}
+        _ => {
+            return Err(format!(
+                "the proof must expire within {} seconds",
+                protocol::catalog::CHANGE_PROOF_TTL
+            )
+            .into())
+        }
+    }
+    let expected = protocol::catalog::change_proof_name(request_subject, change.revision);
+    let name = claims.metadata.as_ref().and_then(|m| m.name.clone());
+    if claims.subject != change.subject || name.as_ref() != Some(&expected) {
+        return Err(format!("the proof is not for '{}' of {}", expected, change.subject).into());
+    }
+    Ok(claims.issuer)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 fn augment_token_message(
     body: &[u8],
     reply_to: String,
     subject: String,
     inv: &Invocation,
 ) -> wascc_host::Result<Invocation> {
-    let token = deserialize::<protocol::catalog::Token>(body)?;
+    // The token is passed on in the content type in which it arrived, so that the
+    // catalog replies in that content type
+    let (token, ct) = protocol::decode::<protocol::catalog::Token>(body)?;
 
     // Operators and Accounts can safely decode actor tokens (lossy), so this is
     // an "okay" way to get at the claim subject, then re-decode once we figure
@@ -101,8 +383,9 @@ fn augment_token_message(
             cannot_use_yet: vres.cannot_use_yet,
             signature_valid: vres.signature_valid,
         }),
+        received_at: now(),
     };
-    let buf = serialize(&new_token)?;    
+    let buf = protocol::encode(ct, &new_token)?;    
 
     let delivermsg = messaging::DeliverMessage {
         message: messaging::BrokerMessage {
@@ -123,14 +406,16 @@ fn augment_token_message(
 
 #[cfg(test)]
 mod test {
-    use super::JWTDecoder;
+    use super::{now, JWTDecoder};
     use codec::messaging;
     use nkeys::KeyPair;    
     use wascap::jwt;
     use wascc_host::host::Invocation;
     use wascc_host::Middleware;
     use super::protocol;
-    use super::protocol::{serialize, deserialize};
+    use codec::{deserialize, serialize};
+
+    const STREAMS_ACTOR: &str = "MATR36QS6IWITSNUS2I7V72R2I3ALJCIS2Y4FJQJZ33KQN5MRXDNJMJ2";
 
     #[test]
     fn middleware_augments_valid_token() {
@@ -138,13 +423,13 @@ mod test {
         // insertion into the catalog, the middleware will crack open the raw token, perform validation, and
         // store the validation results and a raw JSON string version of the decoded token.
         let (claims, issuer) = gen_valid_token();
-        let message = wrap_token(&claims, &issuer);
+        let message = wrap_token(&claims, &issuer, protocol::ContentType::MsgPack);
         let inv = make_invocation(message);
 
-        let decoder = JWTDecoder {};
+        let decoder = JWTDecoder::new(STREAMS_ACTOR);
 
         let res = decoder.actor_pre_invoke(inv).unwrap();
-        let new_token = extract_token(&res);
+        let (new_token, _) = extract_token(&res);
 
         assert!(new_token.validation_result.is_some());
         assert!(new_token.validation_result.unwrap().signature_valid);
@@ -153,6 +438,230 @@ mod test {
             serde_json::from_str(&new_token.decoded_token_json).unwrap();
         let actor_metadata = claims.metadata.unwrap();
         assert_eq!(actor_metadata.name.unwrap(), "test actor");
+        assert!(new_token.received_at > 0);
+    }
+
+    #[test]
+    fn middleware_preserves_token_content_type() {
+        let (claims, issuer) = gen_valid_token();
+        let message = wrap_token(&claims, &issuer, protocol::ContentType::Protobuf);
+
+        let res = JWTDecoder::new(STREAMS_ACTOR).actor_pre_invoke(make_invocation(message)).unwrap();
+        let (new_token, ct) = extract_token(&res);
+
+        assert_eq!(ct, protocol::ContentType::Protobuf);
+        assert!(new_token.validation_result.unwrap().signature_valid);
+    }
+
+    #[test]
+    fn middleware_stamps_verification_time() {
+        let req = protocol::catalog::ChainVerificationRequest {
+            subject: "Mxxxx".to_string(),
+            revision: None,
+            operator: None,
+            as_of: 0,
+        };
+        let message = messaging::DeliverMessage {
+            message: messaging::BrokerMessage {
+                reply_to: "reply".to_string(),
+                subject: protocol::catalog::SUBJECT_CATALOG_TRUST_VERIFY.to_string(),
+                body: protocol::serialize(&req).unwrap(),
+            },
+        };
+        let inv = make_invocation(message);
+
+        let decoder = JWTDecoder::new(STREAMS_ACTOR);
+
+        let res = decoder.actor_pre_invoke(inv).unwrap();
+        let delivermsg = deserialize::<messaging::DeliverMessage>(res.msg.as_ref()).unwrap();
+        let stamped = protocol::deserialize::<protocol::catalog::ChainVerificationRequest>(
+            delivermsg.message.body.as_ref(),
+        )
+        .unwrap();
+
+        assert_eq!(stamped.subject, "Mxxxx");
+        assert!(stamped.as_of > 0);
+    }
+
+    #[test]
+    fn middleware_only_accepts_audit_records_from_streams() {
+        let record = protocol::audit::AuditRecord {
+            sequence: 0,
+            timestamp: 0,
+            operation: protocol::audit::AuditOperation::UploadModule,
+            subject: "Mxxxx".to_string(),
+            revision: None,
+            caller: None,
+            success: true,
+            error: None,
+        };
+        let message = || messaging::DeliverMessage {
+            message: messaging::BrokerMessage {
+                reply_to: "".to_string(),
+                subject: protocol::audit::SUBJECT_AUDIT_APPEND.to_string(),
+                body: protocol::serialize(&record).unwrap(),
+            },
+        };
+        let decoder = JWTDecoder::new(STREAMS_ACTOR);
+
+        assert!(decoder.actor_pre_invoke(make_invocation(message())).is_err());
+
+        let mut inv = make_invocation(message());
+        inv.origin = STREAMS_ACTOR.to_string();
+        let res = decoder.actor_pre_invoke(inv).unwrap();
+        let delivermsg = deserialize::<messaging::DeliverMessage>(res.msg.as_ref()).unwrap();
+        let stamped =
+            protocol::deserialize::<protocol::audit::AuditRecord>(delivermsg.message.body.as_ref())
+                .unwrap();
+        assert_eq!(stamped.subject, "Mxxxx");
+        assert!(stamped.timestamp > 0);
+    }
+
+    #[test]
+    fn middleware_stamps_module_records_from_streams() {
+        let info = protocol::stream::ModuleInfo {
+            actor: "Mxxxx".to_string(),
+            exists: true,
+            total_bytes: 4,
+            ..Default::default()
+        };
+        let message = || messaging::DeliverMessage {
+            message: messaging::BrokerMessage {
+                reply_to: "".to_string(),
+                subject: protocol::stream::SUBJECT_MODULE_RECORD.to_string(),
+                body: protocol::serialize(&info).unwrap(),
+            },
+        };
+        let decoder = JWTDecoder::new(STREAMS_ACTOR);
+
+        assert!(decoder.actor_pre_invoke(make_invocation(message())).is_err());
+
+        let mut inv = make_invocation(message());
+        inv.origin = STREAMS_ACTOR.to_string();
+        let res = decoder.actor_pre_invoke(inv).unwrap();
+        let delivermsg = deserialize::<messaging::DeliverMessage>(res.msg.as_ref()).unwrap();
+        let stamped =
+            protocol::deserialize::<protocol::stream::ModuleInfo>(delivermsg.message.body.as_ref())
+                .unwrap();
+        assert_eq!(stamped.actor, "Mxxxx");
+        assert!(stamped.uploaded_at.is_some());
+    }
+
+    #[test]
+    fn middleware_compresses_chunks_for_streams() {
+        let bytes: Vec<u8> = (0..4096).map(|i| (i % 7) as u8).collect();
+        let chunk = |chunk_bytes: Vec<u8>| protocol::stream::FileChunk {
+            sequence_no: 1,
+            actor: "Mxxxx".to_string(),
+            total_bytes: 4096,
+            chunk_size: 262_144,
+            total_chunks: 1,
+            chunk_bytes,
+            compression: Some(protocol::stream::COMPRESSION_ZSTD.to_string()),
+            offset: 0,
+        };
+        let decoder = JWTDecoder::new(STREAMS_ACTOR);
+
+        let compressed = protocol::stream::compress("zstd", &bytes).unwrap();
+        let upload = make_invocation(messaging::DeliverMessage {
+            message: messaging::BrokerMessage {
+                reply_to: "reply".to_string(),
+                subject: format!("{}Mxxxx", protocol::stream::SUBJECT_STREAM_UPLOAD_PREFIX),
+                body: protocol::encode(protocol::ContentType::MsgPack, &chunk(compressed)).unwrap(),
+            },
+        });
+        let res = decoder.actor_pre_invoke(upload).unwrap();
+        let delivermsg = deserialize::<messaging::DeliverMessage>(res.msg.as_ref()).unwrap();
+        let (stored, _) =
+            protocol::decode::<protocol::stream::FileChunk>(delivermsg.message.body.as_ref())
+                .unwrap();
+        assert_eq!(stored.compression, None);
+        assert_eq!(stored.chunk_bytes, bytes);
+
+        let oversized = protocol::stream::FileChunk {
+            chunk_size: gantryclient::CHUNK_SIZE + 1,
+            ..chunk(protocol::stream::compress("zstd", &bytes).unwrap())
+        };
+        let upload = make_invocation(messaging::DeliverMessage {
+            message: messaging::BrokerMessage {
+                reply_to: "reply".to_string(),
+                subject: format!("{}Mxxxx", protocol::stream::SUBJECT_STREAM_UPLOAD_PREFIX),
+                body: protocol::encode(protocol::ContentType::MsgPack, &oversized).unwrap(),
+            },
+        });
+        assert!(decoder.actor_pre_invoke(upload).is_err());
+
+        let publish = messaging::PublishMessage {
+            message: messaging::BrokerMessage {
+                reply_to: "".to_string(),
+                subject: format!("{}c2f1a0", protocol::stream::SUBJECT_STREAM_DOWNLOAD_PREFIX),
+                body: protocol::encode(protocol::ContentType::MsgPack, &chunk(bytes.clone())).unwrap(),
+            },
+        };
+        let download = Invocation {
+            operation: messaging::OP_PUBLISH_MESSAGE.to_string(),
+            origin: STREAMS_ACTOR.to_string(),
+            msg: serialize(&publish).unwrap(),
+        };
+        let res = decoder.capability_pre_invoke(download).unwrap();
+        let pubmsg = deserialize::<messaging::PublishMessage>(res.msg.as_ref()).unwrap();
+        let (sent, _) =
+            protocol::decode::<protocol::stream::FileChunk>(pubmsg.message.body.as_ref()).unwrap();
+        assert_eq!(
+            protocol::stream::decompress("zstd", &sent.chunk_bytes, sent.chunk_size).unwrap(),
+            bytes
+        );
+    }
+
+    #[test]
+    fn middleware_stamps_the_signer_of_change_proofs() {
+        let account = KeyPair::new_account();
+        let subject = KeyPair::new_module().public_key();
+        let proof = |name: &str, ttl: u64| {
+            let mut claims = jwt::Claims::<jwt::Account>::new(
+                name.to_string(),
+                account.public_key(),
+                subject.to_string(),
+                vec![],
+            );
+            claims.expires = Some(now() + ttl);
+            claims.encode(&account).unwrap()
+        };
+        let stamp = |proof: String| {
+            let change = protocol::catalog::TokenChange {
+                subject: subject.to_string(),
+                proof,
+                signer: Some("forged".to_string()),
+                ..Default::default()
+            };
+            let message = messaging::DeliverMessage {
+                message: messaging::BrokerMessage {
+                    reply_to: "reply".to_string(),
+                    subject: protocol::catalog::SUBJECT_CATALOG_DELETE_TOKEN.to_string(),
+                    body: protocol::serialize(&change).unwrap(),
+                },
+            };
+            let res = JWTDecoder::new(STREAMS_ACTOR)
+                .actor_pre_invoke(make_invocation(message))
+                .unwrap();
+            let delivermsg = deserialize::<messaging::DeliverMessage>(res.msg.as_ref()).unwrap();
+            protocol::decode::<protocol::catalog::TokenChange>(delivermsg.message.body.as_ref())
+                .unwrap()
+                .0
+        };
+        let delete =
+            protocol::catalog::change_proof_name(protocol::catalog::SUBJECT_CATALOG_DELETE_TOKEN, None);
+
+        let stamped = stamp(proof(&delete, 60));
+        assert_eq!(stamped.signer, Some(account.public_key()));
+        assert!(stamped.received_at > 0);
+        // A proof for another change, or one that lives too long, proves nothing
+        let reinstate = protocol::catalog::change_proof_name(
+            protocol::catalog::SUBJECT_CATALOG_REINSTATE_TOKEN,
+            None,
+        );
+        assert_eq!(stamp(proof(&reinstate, 60)).signer, None);
+        assert_eq!(stamp(proof(&delete, 24 * 60 * 60)).signer, None);
     }
 
     // The chain is pretty deep...
@@ -167,14 +676,19 @@ mod test {
         }
     }
 
-    fn wrap_token(claims: &jwt::Claims<jwt::Actor>, issuer: &KeyPair) -> messaging::DeliverMessage {
+    fn wrap_token(
+        claims: &jwt::Claims<jwt::Actor>,
+        issuer: &KeyPair,
+        ct: protocol::ContentType,
+    ) -> messaging::DeliverMessage {
         let encoded = claims.encode(issuer).unwrap();
         let token = protocol::catalog::Token {
             raw_token: encoded,
             decoded_token_json: "".to_string(),
             validation_result: None,
+            received_at: 0,
         };
-        let buf = serialize(&token).unwrap();
+        let buf = protocol::encode(ct, &token).unwrap();
         
         messaging::DeliverMessage {
             message: messaging::BrokerMessage {
@@ -203,8 +717,8 @@ mod test {
         )
     }
 
-    fn extract_token(inv: &Invocation) -> protocol::catalog::Token {
+    fn extract_token(inv: &Invocation) -> (protocol::catalog::Token, protocol::ContentType) {
         let delivermsg = deserialize::<messaging::DeliverMessage>(inv.msg.as_ref()).unwrap();
-        deserialize::<protocol::catalog::Token>(delivermsg.message.body.as_ref()).unwrap()
+        protocol::decode::<protocol::catalog::Token>(delivermsg.message.body.as_ref()).unwrap()
     }
 }
@@ -0,0 +1,454 @@
+//! A subset of the OCI distribution API, so that standard OCI tooling can push and pull
+//! signed actors. Each actor is a repository named after its public key in lowercase
+//! (OCI repository names cannot contain capitals), and the registry holds a single
+//! artifact per actor: the module of its latest revision. That artifact is described by
+//! a manifest with one layer, the module itself, and a config blob holding the catalog's
+//! summary of the actor. Any tag resolves to it, as do `latest`, the actor's version and
+//! the manifest's digest. The registry generates that manifest itself, so a pushed manifest
+//! is answered with the digest of the manifest that will be served for it.
+//!
+//! Blobs pushed to a repository are held in memory, and only visible within that
+//! repository, until a manifest referring to them is pushed. The module layer is then published exactly as `gantry publish` would: the JWT
+//! embedded in the module is put into the catalog (and so is validated) before the
+//! module is uploaded, and nothing is stored if the token is rejected. The gateway holds
+//! no seed with which to prove a rollback, so if the upload then fails the new revision
+//! is left registered and the push fails with an error saying so; the publisher can
+//! remove it with `gantry unregister`.
+//!
+//! Pushes are only accepted from a caller with authority over the actor: its issuer, the
+//! operator at the root of its trust chain, or one of that operator's signers.
+
+use crate::gateway::{
+    authorize_issuer, authorize_subject, header, json_response, operator_of_account,
+    query_param, read_body, GatewayError, GatewayResult, HttpResponse,
+};
+use gantryclient::{cache::digest_of, ActorSummary, Client};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tiny_http::{Method, Request, Response, StatusCode};
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const CONFIG_MEDIA_TYPE: &str = "application/vnd.wascc.actor.config.v1+json";
+const WASM_MEDIA_TYPE: &str = "application/vnd.wasm.content.layer.v1+wasm";
+const MAX_MANIFEST_BYTES: u64 = 64 * 1024;
+const MAX_STAGED_BYTES: usize = 256 * 1024 * 1024;
+
+/// Blobs that have been pushed but not yet referred to by a manifest, and uploads in progress
+#[derive(Default)]
+pub(crate) struct Staging {
+    inner: Mutex<StagingInner>,
+    next_session: AtomicU64,
+}
+
+/// Uploads and blobs are keyed by the repository they were pushed to, and then by session
+/// or digest
+#[derive(Default)]
+struct StagingInner {
+    sessions: HashMap<(String, String), Vec<u8>>,
+    blobs: HashMap<(String, String), Vec<u8>>,
+}
+
+impl StagingInner {
+    fn size(&self) -> usize {
+        self.sessions.values().chain(self.blobs.values()).map(|b| b.len()).sum()
+    }
+}
+
+impl Staging {
+    fn start_session(&self, name: &str) -> String {
+        let id = format!(
+            "{:x}-{:x}",
+            std::process::id(),
+            self.next_session.fetch_add(1, Ordering::SeqCst)
+        );
+        self.inner
+            .lock()
+            .unwrap()
+            .sessions
+            .insert(staged(name, &id), Vec::new());
+        id
+    }
+
+    /// Appends to an upload, returning the number of bytes received so far
+    fn append(&self, name: &str, session: &str, bytes: &[u8]) -> GatewayResult<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.size() + bytes.len() > MAX_STAGED_BYTES {
+            return Err(GatewayError::oci(413, "DENIED", "Too many blobs are awaiting a manifest"));
+        }
+        let buf = inner
+            .sessions
+            .get_mut(&staged(name, session))
+            .ok_or_else(|| GatewayError::oci(404, "BLOB_UPLOAD_UNKNOWN", "Unknown upload"))?;
+        buf.extend_from_slice(bytes);
+        Ok(buf.len())
+    }
+
+    /// Completes an upload, failing if its contents do not match the expected digest
+    fn finish(&self, name: &str, session: &str, digest: &str) -> GatewayResult<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let bytes = inner
+            .sessions
+            .remove(&staged(name, session))
+            .ok_or_else(|| GatewayError::oci(404, "BLOB_UPLOAD_UNKNOWN", "Unknown upload"))?;
+        if oci_digest(&bytes) != digest {
+            return Err(GatewayError::oci(400, "DIGEST_INVALID", "The upload does not match its digest"));
+        }
+        inner.blobs.insert(staged(name, digest), bytes);
+        Ok(())
+    }
+
+    fn blob(&self, name: &str, digest: &str) -> Option<Vec<u8>> {
+        self.inner.lock().unwrap().blobs.get(&staged(name, digest)).cloned()
+    }
+
+    fn discard(&self, name: &str, digests: &[&str]) {
+        let mut inner = self.inner.lock().unwrap();
+        for digest in digests {
+            inner.blobs.remove(&staged(name, digest));
+        }
+    }
+}
+
+fn staged(name: &str, id: &str) -> (String, String) {
+    (name.to_string(), id.to_string())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    schema_version: u32,
+    #[serde(default)]
+    media_type: Option<String>,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+    // Ordered, so that the manifest generated for an artifact always has the same digest
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: String,
+    digest: String,
+    size: u64,
+}
+
+/// The artifact the registry holds for an actor
+struct Artifact {
+    summary: ActorSummary,
+    config: Vec<u8>,
+    module: Vec<u8>,
+    manifest: Vec<u8>,
+}
+
+pub(crate) fn route(
+    client: &Client,
+    staging: &Staging,
+    request: &mut Request,
+    caller: &str,
+    method: &Method,
+    segments: &[&str],
+    query: &str,
+) -> GatewayResult<HttpResponse> {
+    match (method, segments) {
+        (Method::Get, []) | (Method::Get, [""]) => Ok(json_response(b"{}".to_vec())),
+        (Method::Get, [name, "manifests", reference])
+        | (Method::Head, [name, "manifests", reference]) => {
+            let artifact = artifact(client, name)?;
+            if !resolves(&artifact, reference) {
+                return Err(GatewayError::oci(404, "MANIFEST_UNKNOWN", "Unknown manifest"));
+            }
+            Ok(blob_response(artifact.manifest, MANIFEST_MEDIA_TYPE))
+        }
+        (Method::Put, [name, "manifests", reference]) => {
+            put_manifest(client, staging, request, caller, name, reference)
+        }
+        (Method::Get, [name, "blobs", digest]) | (Method::Head, [name, "blobs", digest]) => {
+            if let Some(bytes) = staging.blob(name, digest) {
+                return Ok(blob_response(bytes, "application/octet-stream"));
+            }
+            let artifact = artifact(client, name)?;
+            if oci_digest(&artifact.module) == *digest {
+                Ok(blob_response(artifact.module, WASM_MEDIA_TYPE))
+            } else if oci_digest(&artifact.config) == *digest {
+                Ok(blob_response(artifact.config, CONFIG_MEDIA_TYPE))
+            } else {
+                Err(GatewayError::oci(404, "BLOB_UNKNOWN", "Unknown blob"))
+            }
+        }
+        (Method::Post, [name, "blobs", "uploads"]) => {
+            authorize_repository(client, caller, name)?;
+            let session = staging.start_session(name);
+            match query_param(query, "digest") {
+                // A monolithic upload, completed in a single request
+                Some(digest) => {
+                    let body = read_body(request, MAX_STAGED_BYTES as u64)?;
+                    staging.append(name, &session, &body)?;
+                    finish_upload(client, staging, caller, name, &session, &digest)
+                }
+                None => Ok(upload_status(name, &session, 0, 202)),
+            }
+        }
+        (Method::Patch, [name, "blobs", "uploads", session]) => {
+            authorize_repository(client, caller, name)?;
+            let body = read_body(request, MAX_STAGED_BYTES as u64)?;
+            let received = staging.append(name, session, &body)?;
+            Ok(upload_status(name, session, received, 202))
+        }
+        (Method::Put, [name, "blobs", "uploads", session]) => {
+            authorize_repository(client, caller, name)?;
+            let digest = query_param(query, "digest")
+                .ok_or_else(|| GatewayError::oci(400, "DIGEST_INVALID", "A digest is required"))?;
+            let body = read_body(request, MAX_STAGED_BYTES as u64)?;
+            staging.append(name, session, &body)?;
+            finish_upload(client, staging, caller, name, session, &digest)
+        }
+        _ => Err(GatewayError::oci(404, "UNSUPPORTED", "Not supported by this registry")),
+    }
+}
+
+fn put_manifest(
+    client: &Client,
+    staging: &Staging,
+    request: &mut Request,
+    caller: &str,
+    name: &str,
+    reference: &str,
+) -> GatewayResult<HttpResponse> {
+    let body = read_body(request, MAX_MANIFEST_BYTES)?;
+    let manifest: Manifest = serde_json::from_slice(&body)
+        .map_err(|e| GatewayError::oci(400, "MANIFEST_INVALID", &e.to_string()))?;
+    let layer = match manifest.layers.as_slice() {
+        [layer] => layer,
+        _ => {
+            return Err(GatewayError::oci(
+                400,
+                "MANIFEST_INVALID",
+                "An actor manifest must have exactly one layer, the module",
+            ))
+        }
+    };
+    let module = staging
+        .blob(name, &layer.digest)
+        .ok_or_else(|| GatewayError::oci(400, "MANIFEST_BLOB_UNKNOWN", "The module layer has not been pushed"))?;
+    let embedded = wascap::wasm::extract_claims(&module)
+        .map_err(|e| GatewayError::oci(400, "MANIFEST_INVALID", &e.to_string()))?
+        .ok_or_else(|| GatewayError::oci(400, "MANIFEST_INVALID", "The module is not signed"))?;
+    if embedded.claims.subject.to_lowercase() != name {
+        return Err(GatewayError::oci(
+            400,
+            "NAME_INVALID",
+            &format!("The module is signed for actor {}", embedded.claims.subject),
+        ));
+    }
+    authorize_repository(client, caller, name)?;
+    authorize_module(client, caller, &embedded.claims.subject, &embedded.claims.issuer)?;
+
+    let published = client
+        .publish_actor(&module, None)
+        .map_err(|e| GatewayError::oci(400, "DENIED", &e.to_string()))?;
+    info!(
+        "Published actor {} ({}) as {}:{}",
+        published.registration.subject, published.digest, name, reference
+    );
+    staging.discard(name, &[&layer.digest, &manifest.config.digest]);
+
+    // A pull is served the manifest the registry generates for the published module, so
+    // that is the manifest whose digest the push is answered with
+    let summary = client
+        .actor_summary(&embedded.claims.subject)
+        .map_err(GatewayError::upstream)?
+        .ok_or_else(|| GatewayError::new(500, "The published actor is not in the catalog"))?;
+    let digest = oci_digest(&assemble(summary, module)?.manifest);
+    Ok(json_response(Vec::new())
+        .with_status_code(201)
+        .with_header(header("Location", &format!("/v2/{}/manifests/{}", name, digest)))
+        .with_header(header("Docker-Content-Digest", &digest)))
+}
+
+fn finish_upload(
+    client: &Client,
+    staging: &Staging,
+    caller: &str,
+    name: &str,
+    session: &str,
+    digest: &str,
+) -> GatewayResult<HttpResponse> {
+    staging.finish(name, session, digest)?;
+    // A signed module may only be staged by a key with authority over its issuer
+    if let Some(module) = staging.blob(name, digest) {
+        if let Ok(Some(embedded)) = wascap::wasm::extract_claims(&module) {
+            let claims = embedded.claims;
+            let res = authorize_module(client, caller, &claims.subject, &claims.issuer);
+            if res.is_err() {
+                staging.discard(name, &[digest]);
+            }
+            res?;
+        }
+    }
+    Ok(json_response(Vec::new())
+        .with_status_code(201)
+        .with_header(header("Location", &format!("/v2/{}/blobs/{}", name, digest)))
+        .with_header(header("Docker-Content-Digest", digest)))
+}
+
+fn upload_status(name: &str, session: &str, received: usize, status: u16) -> HttpResponse {
+    json_response(Vec::new())
+        .with_status_code(status)
+        .with_header(header(
+            "Location",
+            &format!("/v2/{}/blobs/uploads/{}", name, session),
+        ))
+        .with_header(header("Docker-Upload-UUID", session))
+        .with_header(header("Range", &format!("0-{}", received.saturating_sub(1))))
+}
+
+/// Checks that the caller has authority over the actor named by a repository, if it is
+/// already in the catalog. The blobs of an actor that is not yet registered are checked
+/// once they are complete, and again when its manifest is pushed
+fn authorize_repository(client: &Client, caller: &str, name: &str) -> GatewayResult<()> {
+    let subject = name.to_uppercase();
+    let registered = client
+        .actor_summary(&subject)
+        .map_err(GatewayError::upstream)?
+        .is_some();
+    if registered {
+        authorize_subject(client, caller, &subject).map_err(|e| e.with_code("DENIED"))?;
+    }
+    Ok(())
+}
+
+/// Checks that the caller has authority over the account that signed a module
+fn authorize_module(client: &Client, caller: &str, subject: &str, issuer: &str) -> GatewayResult<()> {
+    let operator = operator_of_account(client, issuer)?;
+    authorize_issuer(client, caller, subject, issuer, operator.as_deref())
+        .map_err(|e| e.with_code("DENIED"))
+}
+
+/// Assembles the artifact for the actor named by a repository
+fn artifact(client: &Client, name: &str) -> GatewayResult<Artifact> {
+    let subject = name.to_uppercase();
+    let summary = client
+        .actor_summary(&subject)
+        .map_err(GatewayError::upstream)?
+        .ok_or_else(|| GatewayError::oci(404, "NAME_UNKNOWN", &format!("Unknown actor {}", subject)))?;
+    let module = client
+        .download_actor_bytes(&subject)
+        .map_err(GatewayError::upstream)?;
+    assemble(summary, module)
+}
+
+/// Generates the config blob and manifest describing an actor's module
+fn assemble(summary: ActorSummary, module: Vec<u8>) -> GatewayResult<Artifact> {
+    let config = serde_json::to_vec(&summary).map_err(|e| GatewayError::new(500, &e.to_string()))?;
+
+    let mut annotations = BTreeMap::new();
+    annotations.insert("org.opencontainers.image.title".to_string(), summary.name.to_string());
+    annotations.insert("org.opencontainers.image.version".to_string(), summary.version.to_string());
+    annotations.insert("dev.wascc.revision".to_string(), summary.revision.to_string());
+    let manifest = Manifest {
+        schema_version: 2,
+        media_type: Some(MANIFEST_MEDIA_TYPE.to_string()),
+        config: Descriptor {
+            media_type: CONFIG_MEDIA_TYPE.to_string(),
+            digest: oci_digest(&config),
+            size: config.len() as u64,
+        },
+        layers: vec![Descriptor {
+            media_type: WASM_MEDIA_TYPE.to_string(),
+            digest: oci_digest(&module),
+            size: module.len() as u64,
+        }],
+        annotations,
+    };
+    let manifest = serde_json::to_vec(&manifest).map_err(|e| GatewayError::new(500, &e.to_string()))?;
+    Ok(Artifact {
+        summary,
+        config,
+        module,
+        manifest,
+    })
+}
+
+fn resolves(artifact: &Artifact, reference: &str) -> bool {
+    if reference.starts_with("sha256:") {
+        oci_digest(&artifact.manifest) == reference
+    } else {
+        reference == "latest"
+            || reference == artifact.summary.version
+            || reference == artifact.summary.revision.to_string()
+    }
+}
+
+fn blob_response(bytes: Vec<u8>, media_type: &str) -> HttpResponse {
+    let digest = oci_digest(&bytes);
+    let len = bytes.len();
+    Response::new(
+        StatusCode(200),
+        vec![
+            header("Content-Type", media_type),
+            header("Docker-Content-Digest", &digest),
+        ],
+        Box::new(io::Cursor::new(bytes)) as Box<dyn Read + Send>,
+        Some(len),
+        None,
+    )
+}
+
+fn oci_digest(bytes: &[u8]) -> String {
+    format!("sha256:{}", digest_of(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{assemble, oci_digest, Staging};
+    use gantryclient::ActorSummary;
+
+    fn summary() -> ActorSummary {
+        ActorSummary {
+            public_key: "MB2ZQB6ROOMAYBO4ZCTFYWN7YIVBWA3MTKZYAQKJMTIHE2ELLRW2E3ZW".to_string(),
+            capabilities: vec![],
+            provider: false,
+            tags: vec![],
+            version: "1.0.0".to_string(),
+            revision: 3,
+            account: "AAOZ7TBI3BDZMS3YYJYH4WZWLAEODVGQXW4XKWCSVGYX3XXYDKZG7T7T".to_string(),
+            name: "oci actor".to_string(),
+            module_hash: "".to_string(),
+        }
+    }
+
+    #[test]
+    fn staged_blobs_belong_to_their_repository() {
+        let staging = Staging::default();
+        let bytes = b"module".to_vec();
+        let digest = oci_digest(&bytes);
+        let session = staging.start_session("mone");
+        assert!(staging.append("mtwo", &session, &bytes).is_err());
+        assert!(staging.append("mone", &session, &bytes).is_ok());
+        assert!(staging.finish("mone", &session, &digest).is_ok());
+
+        assert_eq!(staging.blob("mone", &digest), Some(bytes));
+        assert_eq!(staging.blob("mtwo", &digest), None);
+        staging.discard("mtwo", &[&digest]);
+        assert!(staging.blob("mone", &digest).is_some());
+    }
+
+    #[test]
+    fn generated_manifests_have_a_stable_digest() {
+        let digest = || {
+            assemble(summary(), b"module".to_vec())
+                .ok()
+                .map(|artifact| oci_digest(&artifact.manifest))
+        };
+        let first = digest();
+        assert!(first.is_some());
+        for _ in 0..8 {
+            assert_eq!(digest(), first);
+        }
+    }
+}
@@ -0,0 +1,522 @@
+//! End-to-end tests that boot the Gantry host with the catalog and streams actors and
+//! the in-process key-value, blob store and messaging providers, then drive the registry
+//! through `gantryclient`.
+//!
+//! These tests need the signed actor modules (see the `catalog` and `streams` Makefiles).
+//! They are located through the `GANTRY_CATALOG_WASM` and `GANTRY_STREAMS_WASM`
+//! environment variables, defaulting to the debug build output of each actor, and the
+//! tests are ignored by default. Run them with `cargo test -- --ignored`. The modules
+//! must both be signed with the same account.
+
+#[macro_use]
+extern crate lazy_static;
+
+use gantry_protocol as protocol;
+use gantry_server::embedded::LoopbackBroker;
+use gantry_server::{Backends, BlobstoreBackend, KeyValueBackend, MessagingBackend};
+use gantryclient::{
+    CatalogQuery, Client, ClientBuilder, ContentType, EventKind, Token, UploadRequest,
+};
+use nkeys::KeyPair;
+use protocol::catalog::QueryType;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use wascap::jwt::{self, Claims};
+use wascc_host::Actor;
+
+lazy_static! {
+    static ref HARNESS: Harness = Harness::boot();
+}
+
+struct Harness {
+    client: Client,
+    broker: LoopbackBroker,
+    operator: KeyPair,
+    account: KeyPair,
+}
+
+impl Harness {
+    fn boot() -> Harness {
+        let _ = env_logger::try_init();
+        let operator = KeyPair::new_operator();
+        let account = KeyPair::new_account();
+        let operator_claims = Claims::<jwt::Operator>::new(
+            "integration operator".to_string(),
+            operator.public_key(),
+            operator.public_key(),
+            vec![],
+        );
+
+        let root = std::env::temp_dir().join(format!("gantry-it-{}", std::process::id()));
+        let backends = Backends {
+            keyvalue: KeyValueBackend::Memory,
+            blobstore: BlobstoreBackend::Filesystem(root),
+            messaging: MessagingBackend::Loopback,
+        };
+        let broker = gantry_server::start(
+            Actor::from_file(actor_path("GANTRY_CATALOG_WASM", "catalog")).unwrap(),
+            Actor::from_file(actor_path("GANTRY_STREAMS_WASM", "streams")).unwrap(),
+            &[operator_claims],
+            &backends,
+            &[],
+        )
+        .unwrap()
+        .unwrap();
+        let client = Client::with_transport(broker.clone());
+
+        let account_claims = Claims::<jwt::Account>::new(
+            "integration account".to_string(),
+            operator.public_key(),
+            account.public_key(),
+            vec![],
+        );
+        client
+            .put_token(&raw_token(account_claims.encode(&operator).unwrap()))
+            .unwrap();
+
+        Harness {
+            client,
+            broker,
+            operator,
+            account,
+        }
+    }
+
+    fn actor_claims(&self, name: &str) -> Claims<jwt::Actor> {
+        Claims::<jwt::Actor>::new(
+            name.to_string(),
+            self.account.public_key(),
+            KeyPair::new_module().public_key(),
+            Some(vec!["wascc:messaging".to_string()]),
+            None,
+            false,
+            Some(1),
+            Some("1.0.0".to_string()),
+        )
+    }
+
+    fn register(&self, claims: &Claims<jwt::Actor>) -> Result<(), Box<dyn ::std::error::Error>> {
+        self.client
+            .put_token(&raw_token(claims.encode(&self.account).unwrap()))
+            .map(|_| ())
+    }
+
+    fn upload(&self, actor: &str, bytes: &[u8]) -> Result<(), Box<dyn ::std::error::Error>> {
+        let chunk_size = gantryclient::CHUNK_SIZE;
+        let total_bytes = bytes.len() as u64;
+        self.client.start_upload(&UploadRequest {
+            actor: actor.to_string(),
+            chunk_size,
+            total_bytes,
+            total_chunks: total_bytes / chunk_size,
+            compression: vec![],
+        })?;
+        for (i, chunk) in bytes.chunks(chunk_size as usize).enumerate() {
+            self.client.upload_chunk(
+                i as u64,
+                actor,
+                chunk_size,
+                total_bytes,
+                total_bytes / chunk_size,
+                chunk.to_vec(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn download(&self, actor: &str) -> Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+        let (s, r) = crossbeam_channel::bounded(1);
+        let bytes = Arc::new(Mutex::new(Vec::new()));
+        let b = bytes.clone();
+        self.client.download_actor(actor, move |chunk| {
+            b.lock().unwrap().extend_from_slice(&chunk.chunk_bytes);
+            if chunk.sequence_no == chunk.total_chunks {
+                let _ = s.send(());
+            }
+            Ok(())
+        })?;
+        r.recv_timeout(Duration::from_secs(5))?;
+        let bytes = bytes.lock().unwrap();
+        Ok(bytes.clone())
+    }
+
+    fn actor_subjects(&self) -> Vec<String> {
+        self.client
+            .query_catalog(&CatalogQuery {
+                query_type: QueryType::Actor,
+                issuer: None,
+                operator: None,
+                subject: None,
+            })
+            .unwrap()
+            .results
+            .into_iter()
+            .map(|r| r.subject)
+            .collect()
+    }
+}
+
+fn actor_path(var: &str, actor: &str) -> String {
+    std::env::var(var).unwrap_or_else(|_| {
+        format!(
+            "{}/../{}/target/wasm32-unknown-unknown/debug/{}_signed.wasm",
+            env!("CARGO_MANIFEST_DIR"),
+            actor,
+            actor
+        )
+    })
+}
+
+fn raw_token(raw: String) -> Token {
+    Token {
+        raw_token: raw,
+        decoded_token_json: "".to_string(),
+        validation_result: None,
+        received_at: 0,
+    }
+}
+
+fn module_bytes(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+#[ignore]
+fn token_and_module_lifecycle() {
+    let h = &*HARNESS;
+    let claims = h.actor_claims("lifecycle actor");
+    let subject = claims.subject.to_string();
+
+    h.register(&claims).unwrap();
+    assert!(h.actor_subjects().contains(&subject));
+
+    // Spans several chunks, with a partial final chunk
+    let bytes = module_bytes(gantryclient::CHUNK_SIZE as usize * 2 + 1234);
+    h.upload(&subject, &bytes).unwrap();
+    assert_eq!(h.download(&subject).unwrap(), bytes);
+
+    let report = h.client.verify_chain(&subject).unwrap();
+    assert!(report.trusted);
+    assert_eq!(report.links.len(), 3);
+
+    // The actor's token is public, but only its issuer's key can prove a revocation
+    let rogue = KeyPair::new_account().seed().unwrap();
+    assert!(h.client.remove_token(&subject, &rogue).is_err());
+    assert!(h.actor_subjects().contains(&subject));
+
+    h.client
+        .remove_token(&subject, &h.account.seed().unwrap())
+        .unwrap();
+    assert!(!h.actor_subjects().contains(&subject));
+    assert!(h.download(&subject).is_err());
+    assert!(!h.client.verify_chain(&subject).unwrap().trusted);
+    assert!(h.register(&claims).is_err());
+
+    assert!(h.client.reinstate_token(&subject, &rogue).is_err());
+    h.client
+        .reinstate_token(&subject, &h.account.seed().unwrap())
+        .unwrap();
+    assert!(h.actor_subjects().contains(&subject));
+    assert_eq!(h.download(&subject).unwrap(), bytes);
+}
+
+#[test]
+#[ignore]
+fn concurrent_downloads_are_kept_apart() {
+    let h = &*HARNESS;
+    let claims = h.actor_claims("concurrently downloaded actor");
+    let subject = claims.subject.to_string();
+    h.register(&claims).unwrap();
+    let bytes = module_bytes(gantryclient::CHUNK_SIZE as usize * 3 + 17);
+    h.upload(&subject, &bytes).unwrap();
+
+    let downloads: Vec<_> = (0..4)
+        .map(|_| {
+            let subject = subject.to_string();
+            std::thread::spawn(move || HARNESS.client.download_actor_bytes(&subject).unwrap())
+        })
+        .collect();
+    for download in downloads {
+        assert_eq!(download.join().unwrap(), bytes);
+    }
+}
+
+#[test]
+#[ignore]
+fn out_of_order_upload_is_digested_as_stored() {
+    let h = &*HARNESS;
+    let claims = h.actor_claims("out of order actor");
+    let subject = claims.subject.to_string();
+    h.register(&claims).unwrap();
+    let revision = h.client.actor_summary(&subject).unwrap().unwrap().revision;
+    let bytes = module_bytes(gantryclient::CHUNK_SIZE as usize * 2 + 99);
+
+    let chunk_size = gantryclient::CHUNK_SIZE;
+    let total_bytes = bytes.len() as u64;
+    h.client
+        .start_upload(&UploadRequest {
+            actor: subject.to_string(),
+            chunk_size,
+            total_bytes,
+            total_chunks: total_bytes / chunk_size,
+            compression: vec![],
+        })
+        .unwrap();
+    for (i, chunk) in bytes.chunks(chunk_size as usize).enumerate().rev() {
+        h.client
+            .upload_chunk(
+                i as u64,
+                &subject,
+                chunk_size,
+                total_bytes,
+                total_bytes / chunk_size,
+                chunk.to_vec(),
+            )
+            .unwrap();
+    }
+
+    // The digest is recorded once the stored module has been read back
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    let info = loop {
+        let info = h.client.module_info(&subject, None).unwrap();
+        if info.digest.is_some() || std::time::Instant::now() > deadline {
+            break info;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(info.digest, Some(gantryclient::cache::digest_of(&bytes)));
+    assert_eq!(info.revision, Some(revision));
+    assert_eq!(h.download(&subject).unwrap(), bytes);
+}
+
+#[test]
+#[ignore]
+fn chunks_are_only_stored_for_authorized_uploads() {
+    let h = &*HARNESS;
+    let claims = h.actor_claims("unauthorized chunk actor");
+    let subject = claims.subject.to_string();
+    h.register(&claims).unwrap();
+    let bytes = module_bytes(99);
+
+    // A chunk sent without an upload request is refused rather than stored
+    assert!(h
+        .client
+        .upload_chunk(0, &subject, 99, 99, 1, bytes.clone())
+        .is_err());
+    assert!(!h.client.module_info(&subject, None).unwrap().exists);
+
+    // So is an upload that declares chunks the server cannot count
+    for chunk_size in &[0, gantryclient::CHUNK_SIZE + 1] {
+        assert!(h
+            .client
+            .start_upload(&UploadRequest {
+                actor: subject.to_string(),
+                chunk_size: *chunk_size,
+                total_bytes: 99,
+                total_chunks: 1,
+                compression: vec![],
+            })
+            .is_err());
+    }
+    assert!(!h.client.module_info(&subject, None).unwrap().exists);
+
+    h.upload(&subject, &bytes).unwrap();
+    assert_eq!(h.download(&subject).unwrap(), bytes);
+}
+
+#[test]
+#[ignore]
+fn unregistering_a_revision_must_be_proven() {
+    let h = &*HARNESS;
+    let claims = h.actor_claims("unregistered actor");
+    let subject = claims.subject.to_string();
+    h.register(&claims).unwrap();
+    let revision = h.client.actor_summary(&subject).unwrap().unwrap().revision;
+
+    let rogue = KeyPair::new_account().seed().unwrap();
+    assert!(h.client.unregister_token(&subject, revision, &rogue).is_err());
+    assert!(h.actor_subjects().contains(&subject));
+
+    h.client
+        .unregister_token(&subject, revision, &h.account.seed().unwrap())
+        .unwrap();
+    assert!(!h.actor_subjects().contains(&subject));
+}
+
+#[test]
+#[ignore]
+fn expired_token_is_rejected() {
+    let h = &*HARNESS;
+    let mut claims = h.actor_claims("expired actor");
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    claims.expires = Some(now - 3600);
+
+    assert!(h.register(&claims).is_err());
+    assert!(!h.actor_subjects().contains(&claims.subject));
+}
+
+#[test]
+#[ignore]
+fn actor_from_unregistered_account_is_rejected() {
+    let h = &*HARNESS;
+    let rogue = KeyPair::new_account();
+    let claims = Claims::<jwt::Actor>::new(
+        "rogue actor".to_string(),
+        rogue.public_key(),
+        KeyPair::new_module().public_key(),
+        None,
+        None,
+        false,
+        Some(1),
+        None,
+    );
+
+    assert!(h
+        .client
+        .put_token(&raw_token(claims.encode(&rogue).unwrap()))
+        .is_err());
+}
+
+#[test]
+#[ignore]
+fn cross_signed_operator_token_cannot_replace_trust_roots() {
+    let h = &*HARNESS;
+    let roots = h.client.trust_roots().unwrap();
+    let rogue = KeyPair::new_operator();
+    let forged = Claims::<jwt::Operator>::new(
+        "forged operator".to_string(),
+        rogue.public_key(),
+        h.operator.public_key(),
+        vec![rogue.public_key()],
+    );
+
+    assert!(h
+        .client
+        .put_token(&raw_token(forged.encode(&rogue).unwrap()))
+        .is_err());
+    assert_eq!(h.client.trust_roots().unwrap(), roots);
+
+    let account = Claims::<jwt::Account>::new(
+        "rogue account".to_string(),
+        rogue.public_key(),
+        KeyPair::new_account().public_key(),
+        vec![],
+    );
+    assert!(h
+        .client
+        .put_token(&raw_token(account.encode(&rogue).unwrap()))
+        .is_err());
+}
+
+#[test]
+#[ignore]
+fn unknown_actor_cannot_be_streamed() {
+    let h = &*HARNESS;
+    let unknown = KeyPair::new_module().public_key();
+
+    assert!(h.upload(&unknown, &module_bytes(1024)).is_err());
+    assert!(h.download(&unknown).is_err());
+}
+
+#[test]
+#[ignore]
+fn corrupted_chunk_is_rejected() {
+    let h = &*HARNESS;
+    let claims = h.actor_claims("corruption actor");
+    let subject = claims.subject.to_string();
+    h.register(&claims).unwrap();
+    let bytes = module_bytes(4096);
+    h.upload(&subject, &bytes).unwrap();
+
+    let garbage = vec![0xc1; 64];
+    let res = h.broker.request(
+        &format!(
+            "{}{}",
+            protocol::stream::SUBJECT_STREAM_UPLOAD_PREFIX,
+            subject
+        ),
+        &garbage,
+        Duration::from_millis(500),
+    );
+
+    assert!(res.is_err());
+    assert_eq!(h.download(&subject).unwrap(), bytes);
+}
+
+#[test]
+#[ignore]
+fn change_events_are_published() {
+    let h = &*HARNESS;
+    let claims = h.actor_claims("watched actor");
+    let subject = claims.subject.to_string();
+    let (s, r) = crossbeam_channel::unbounded();
+    let watched = subject.to_string();
+    h.client
+        .watch(move |event| {
+            if event.subject == watched {
+                let _ = s.send(event);
+            }
+        })
+        .unwrap();
+
+    h.register(&claims).unwrap();
+    h.upload(&subject, &module_bytes(gantryclient::CHUNK_SIZE as usize + 1))
+        .unwrap();
+    h.client
+        .remove_token(&subject, &h.account.seed().unwrap())
+        .unwrap();
+    h.client
+        .reinstate_token(&subject, &h.account.seed().unwrap())
+        .unwrap();
+
+    let kinds: Vec<EventKind> = (0..4)
+        .map(|_| r.recv_timeout(Duration::from_secs(5)).unwrap().kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            EventKind::TokenAdded,
+            EventKind::ModuleUploaded,
+            EventKind::TokenRevoked,
+            EventKind::TokenReinstated
+        ]
+    );
+}
+
+#[test]
+#[ignore]
+fn protobuf_requests_are_answered_in_protobuf() {
+    let h = &*HARNESS;
+    let client = ClientBuilder::new()
+        .transport(h.broker.clone())
+        .content_type(ContentType::Protobuf)
+        .build()
+        .unwrap();
+    let claims = h.actor_claims("protobuf actor");
+    client
+        .put_token(&raw_token(claims.encode(&h.account).unwrap()))
+        .unwrap();
+
+    let query = CatalogQuery {
+        query_type: QueryType::Actor,
+        issuer: None,
+        operator: None,
+        subject: Some(claims.subject.to_string()),
+    };
+    let reply = h
+        .broker
+        .request(
+            protocol::catalog::SUBJECT_CATALOG_QUERY,
+            &protocol::encode(ContentType::Protobuf, &query).unwrap(),
+            Duration::from_secs(2),
+        )
+        .unwrap();
+    assert_eq!(ContentType::of(&reply), ContentType::Protobuf);
+    assert_eq!(client.query_catalog(&query).unwrap().results.len(), 1);
+
+    let info = client.server_info().unwrap();
+    assert!(info.content_types.contains(&ContentType::Protobuf.mime().to_string()));
+}
@@ -15,40 +15,259 @@
 extern crate wascc_actor as actor;
 use gantry_protocol as protocol;
 use actor::prelude::*;
+use protocol::audit::{AuditOperation, AuditRecord};
+use protocol::catalog::{SubjectStatus, SubjectStatusRequest, SUBJECT_CATALOG_STATUS};
+use protocol::events::{CatalogEvent, EventKind};
 use protocol::stream::{
-    DownloadRequest, TransferAck, UploadRequest, SUBJECT_STREAM_DOWNLOAD_PREFIX,
-    SUBJECT_STREAM_UPLOAD_PREFIX,
+    negotiate_compression, ChunkAck, DownloadRequest, ModuleInfo,
+    ModuleInfoRequest, TransferAck, UploadRequest, COMPRESSION_CODECS,
+    SUBJECT_MODULE_LOOKUP, SUBJECT_MODULE_RECORD, SUBJECT_STREAM_CREDIT_PREFIX,
+    SUBJECT_STREAM_DOWNLOAD_PREFIX, SUBJECT_STREAM_UPLOAD_PREFIX,
 };
+use protocol::ContentType;
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 
 const CHUNK_SIZE: u64 = 256 * 1024; // 256KB chunks
 
+/// A download in progress. Chunks arrive from the blob store without any record of the
+/// download that asked for them, but every download of an actor reads the same blob, so
+/// each chunk is handed to the oldest download of its actor that still lacks it
+struct Download {
+    actor: String,
+    content_type: ContentType,
+    compression: Option<String>,
+    window: u64,
+    // The range of the module's bytes requested, as [start, end)
+    start: u64,
+    end: u64,
+    // The sequence number of the final chunk overlapping the range
+    last_chunk: u64,
+    // The number of chunks in the stored module, and those received from the blob store so
+    // far, whether or not they overlap the range
+    blob_chunks: u64,
+    received: BTreeSet<u64>,
+    // Chunks published and not yet acknowledged by the client
+    outstanding: u64,
+    // Encoded chunks held back until the client grants credit for them
+    pending: VecDeque<Vec<u8>>,
+    // When the download started and when it last made progress, in ACTIVITY ticks
+    started: u64,
+    active: u64,
+}
+
+impl Download {
+    /// Trims a chunk to the requested range, returning false if it lies outside of it
+    fn trim(&self, chunk: &mut protocol::stream::FileChunk) -> bool {
+        let chunk_start = chunk.sequence_no.saturating_sub(1) * chunk.chunk_size;
+        let chunk_end = chunk_start + chunk.chunk_bytes.len() as u64;
+        if chunk_end <= self.start || chunk_start >= self.end {
+            return false;
+        }
+        let from = self.start.saturating_sub(chunk_start) as usize;
+        let to = (self.end.min(chunk_end) - chunk_start) as usize;
+        chunk.chunk_bytes = chunk.chunk_bytes[from..to].to_vec();
+        chunk.offset = chunk_start + from as u64;
+        chunk.total_chunks = self.last_chunk;
+        true
+    }
+
+    /// Whether every chunk has been read from the blob store and delivered to the client
+    fn finished(&self) -> bool {
+        self.received.len() as u64 >= self.blob_chunks
+            && self.pending.is_empty()
+            && self.outstanding == 0
+    }
+}
+
+// The most downloads held at once. Starting another abandons the one that has gone the
+// longest without progress, which is how the state of clients that go away is reclaimed
+const MAX_DOWNLOADS: usize = 32;
+
+// Downloads in progress, keyed by transfer
+static DOWNLOADS: Mutex<BTreeMap<String, Download>> = Mutex::new(BTreeMap::new());
+
+// Counts download activity, standing in for a clock to order downloads by when they
+// started and when they last made progress
+static ACTIVITY: AtomicU64 = AtomicU64::new(0);
+
+/// An upload in progress
+struct Upload {
+    // The revision of the actor's token when the upload was authorized
+    revision: Option<u64>,
+    total_chunks: u64,
+    // The protocol sequence numbers of the chunks stored so far
+    stored: BTreeSet<u64>,
+}
+
+/// A completed upload whose stored blob is being read back to compute its digest, which
+/// is recorded with the catalog once every chunk has been hashed
+struct PendingDigest {
+    revision: Option<u64>,
+    total_bytes: u64,
+    blob_chunks: u64,
+    // The next chunk to hash, and those that arrived ahead of it
+    next: u64,
+    ahead: BTreeMap<u64, Vec<u8>>,
+    hasher: Sha256,
+}
+
+// Uploads in progress, keyed by actor
+static UPLOADS: Mutex<BTreeMap<String, Upload>> = Mutex::new(BTreeMap::new());
+
+// Digests being computed, keyed by actor
+static DIGESTS: Mutex<BTreeMap<String, PendingDigest>> = Mutex::new(BTreeMap::new());
+
+// The public key of the catalog actor, supplied by the host through OP_CONFIGURE
+static CATALOG_ACTOR: Mutex<Option<String>> = Mutex::new(None);
+
 actor_handlers!{ messaging::OP_DELIVER_MESSAGE => handle_message,
                  blobstore::OP_RECEIVE_CHUNK => handle_blob_chunk,
+                 core::OP_CONFIGURE => handle_config,
                  core::OP_HEALTH_REQUEST => health }
 
 pub fn health(_ctx: &CapabilitiesContext, _req: core::HealthRequest) -> ReceiveResult {
     Ok(vec![])
 }
 
+fn handle_config(
+    ctx: &CapabilitiesContext,
+    config: core::CapabilityConfiguration,
+) -> ReceiveResult {
+    let catalog = config
+        .values
+        .get("CATALOG_ACTOR")
+        .ok_or("Streams actor configuration is missing the 'CATALOG_ACTOR' value")?;
+    ctx.log(&format!("Streams actor will consult catalog actor {}", catalog));
+    *CATALOG_ACTOR.lock().unwrap() = Some(catalog.to_string());
+    Ok(vec![])
+}
+
 fn handle_blob_chunk(
     ctx: &CapabilitiesContext,
     chunk: blobstore::FileChunk,
 ) -> ReceiveResult {    
     ctx.log("Received chunk from blob store");
-    let newchunk = convert_chunk(&chunk);
-    let buf = serialize(newchunk)?;        
+    let mut newchunk = convert_chunk(&chunk);
+    if digest_chunk(ctx, &newchunk) {
+        return Ok(vec![]);
+    }
+    let mut downloads = DOWNLOADS.lock().unwrap();
+    let transfer = match downloads
+        .iter()
+        .filter(|(_, d)| d.actor == newchunk.actor && !d.received.contains(&newchunk.sequence_no))
+        .min_by_key(|(_, d)| d.started)
+    {
+        Some((transfer, _)) => transfer.to_string(),
+        None => {
+            ctx.log(&format!(
+                "Dropping chunk {} of {}, which no download is waiting for",
+                newchunk.sequence_no, newchunk.actor
+            ));
+            return Ok(vec![]);
+        }
+    };
+    let d = downloads.get_mut(&transfer).unwrap();
+    d.received.insert(newchunk.sequence_no);
+    d.active = ACTIVITY.fetch_add(1, Ordering::SeqCst);
+    if !d.trim(&mut newchunk) {
+        if d.finished() {
+            downloads.remove(&transfer);
+        }
+        return Ok(vec![]);
+    }
+    // The chunk is published uncompressed, naming the negotiated codec, and the server
+    // compresses it on its way to the client
+    newchunk.compression = d.compression.clone();
+    let buf = protocol::encode(d.content_type, &newchunk)?;        
+    if d.window > 0 && d.outstanding >= d.window {
+        d.pending.push_back(buf);
+        return Ok(vec![]);
+    }
+    if d.window > 0 {
+        d.outstanding += 1;
+    } else if d.finished() {
+        downloads.remove(&transfer);
+    }
+    drop(downloads);
+    publish_chunk(ctx, &transfer, &buf)
+}
+
+/// Hashes a chunk read back from the blob store, if the digest of its actor's module is
+/// being computed and still lacks it, recording the upload once the digest is complete.
+/// Returns whether the chunk was taken
+fn digest_chunk(
+    ctx: &CapabilitiesContext,
+    chunk: &protocol::stream::FileChunk,
+) -> bool {
+    let mut digests = DIGESTS.lock().unwrap();
+    let d = match digests.get_mut(&chunk.actor) {
+        Some(d) if chunk.sequence_no >= d.next && !d.ahead.contains_key(&chunk.sequence_no) => d,
+        _ => return false,
+    };
+    d.ahead.insert(chunk.sequence_no, chunk.chunk_bytes.clone());
+    while let Some(bytes) = d.ahead.remove(&d.next) {
+        sha2::Digest::input(&mut d.hasher, &bytes);
+        d.next += 1;
+    }
+    if d.next > d.blob_chunks {
+        let d = digests.remove(&chunk.actor).unwrap();
+        drop(digests);
+        let digest = hex(&sha2::Digest::result(d.hasher));
+        if let Err(e) = record_upload(ctx, &chunk.actor, d.revision, d.total_bytes, Some(digest)) {
+            ctx.log(&format!("Failed to record module upload: {}", e));
+        }
+    }
+    true
+}
+
+fn publish_chunk(ctx: &CapabilitiesContext, transfer: &str, buf: &[u8]) -> ReceiveResult {
     ctx.msg().publish(
-        &format!(
-            "{}{}",
-            SUBJECT_STREAM_DOWNLOAD_PREFIX,
-            chunk.id[0..chunk.id.len() - 5].to_string()
-        ),
+        &format!("{}{}", SUBJECT_STREAM_DOWNLOAD_PREFIX, transfer),
         None,
-        &buf,
+        buf,
     )?;
     Ok(vec![])
 }
 
+/// Releases the next held back chunk of a download, if the acknowledgement leaves room for
+/// it. A failed acknowledgement abandons the download
+fn handle_credit(ctx: &CapabilitiesContext, transfer: &str, ack: ChunkAck) -> ReceiveResult {
+    let mut downloads = DOWNLOADS.lock().unwrap();
+    if !ack.success {
+        ctx.log(&format!(
+            "Client failed to handle chunk {} of transfer {}, abandoning it",
+            ack.sequence_no, transfer
+        ));
+        downloads.remove(transfer);
+        return Ok(vec![]);
+    }
+    let d = match downloads.get_mut(transfer) {
+        Some(d) => d,
+        None => return Ok(vec![]),
+    };
+    d.active = ACTIVITY.fetch_add(1, Ordering::SeqCst);
+    d.outstanding = d.outstanding.saturating_sub(1);
+    let next = if d.outstanding < d.window {
+        d.pending.pop_front().map(|buf| {
+            d.outstanding += 1;
+            buf
+        })
+    } else {
+        None
+    };
+    if d.finished() {
+        downloads.remove(transfer);
+    }
+    drop(downloads);
+    match next {
+        Some(buf) => publish_chunk(ctx, transfer, &buf),
+        None => Ok(vec![]),
+    }
+}
+
 fn convert_chunk(chunk: &blobstore::FileChunk) -> protocol::stream::FileChunk {
     protocol::stream::FileChunk {
         sequence_no: chunk.sequence_no,
@@ -57,6 +276,8 @@ fn convert_chunk(chunk: &blobstore::FileChunk) -> protocol::stream::FileChunk {
         total_bytes: chunk.total_bytes,
         total_chunks: total_chunks(chunk.total_bytes, chunk.chunk_size),
         chunk_bytes: chunk.chunk_bytes.clone(),
+        compression: None,
+        offset: 0,
     }
 }
 
@@ -67,15 +288,31 @@ fn handle_message(
     let subject = msg.message.subject.clone();
 
     if subject == protocol::stream::SUBJECT_STREAM_DOWNLOAD {
-        let req = deserialize::<DownloadRequest>(msg.message.body.as_ref())?;
-        handle_download(ctx, req, &msg.message.reply_to)
+        let (req, ct) = protocol::decode::<DownloadRequest>(msg.message.body.as_ref())?;
+        handle_download(ctx, req, ct, &msg.message.reply_to)
     } else if subject == protocol::stream::SUBJECT_STREAM_UPLOAD {
-        let req = deserialize::<UploadRequest>(msg.message.body.as_ref())?;
-        handle_upload(ctx, req, &msg.message.reply_to)
+        let (req, ct) = protocol::decode::<UploadRequest>(msg.message.body.as_ref())?;
+        let actor = req.actor.to_string();
+        let res = handle_upload(ctx, req, ct, &msg.message.reply_to);
+        if let Err(ref e) = res {
+            audit_upload(ctx, &actor, Some(e.to_string()));
+        }
+        res
     } else if subject.starts_with(SUBJECT_STREAM_UPLOAD_PREFIX) {
-        let chunk =
-            deserialize::<protocol::stream::FileChunk>(msg.message.body.as_ref())?;
-        handle_upload_chunk(ctx, chunk, &msg.message.reply_to)
+        let (chunk, ct) =
+            protocol::decode::<protocol::stream::FileChunk>(msg.message.body.as_ref())?;
+        let actor = chunk.actor.to_string();
+        let res = handle_upload_chunk(ctx, chunk, ct, &msg.message.reply_to);
+        if let Err(ref e) = res {
+            audit_upload(ctx, &actor, Some(e.to_string()));
+        }
+        res
+    } else if subject == protocol::stream::SUBJECT_STREAM_INFO {
+        let (req, ct) = protocol::decode::<ModuleInfoRequest>(msg.message.body.as_ref())?;
+        handle_info(ctx, req, ct, &msg.message.reply_to)
+    } else if subject.starts_with(SUBJECT_STREAM_CREDIT_PREFIX) {
+        let (ack, _) = protocol::decode::<ChunkAck>(msg.message.body.as_ref())?;
+        handle_credit(ctx, &subject[SUBJECT_STREAM_CREDIT_PREFIX.len()..], ack)
     } else {
         Err("Unknown stream request".into())
     }
@@ -84,9 +321,28 @@ fn handle_message(
 fn handle_upload_chunk(
     ctx: &CapabilitiesContext,
     chunk: protocol::stream::FileChunk,
+    ct: ContentType,
     reply_to: &str, 
 ) -> ReceiveResult {
     ctx.log("Received file chunk");
+    // The server decompresses chunks before they reach the actor, so the blob store only
+    // ever holds the uncompressed module
+    if let Some(codec) = chunk.compression {
+        return Err(format!("Chunk arrived still compressed with {}", codec).into());
+    }
+    check_chunk_size(chunk.chunk_size)?;
+    // Only chunks of an upload that was authorized by an upload request are stored
+    let expected = match UPLOADS.lock().unwrap().get(&chunk.actor) {
+        Some(upload) => upload.total_chunks,
+        None => return Err(format!("No upload of {} has been authorized", chunk.actor).into()),
+    };
+    if chunk.sequence_no >= expected {
+        return Err(format!(
+            "Chunk {} is beyond the {} chunks of the upload of {}",
+            chunk.sequence_no, expected, chunk.actor
+        )
+        .into());
+    }
     let xfer = blobstore::Transfer {
         total_size: chunk.chunk_bytes.len() as u64,
         blob_id: format!("{}.wasm", chunk.actor),
@@ -94,25 +350,224 @@ fn handle_upload_chunk(
         chunk_size: chunk.chunk_size,
         total_chunks: chunk.total_chunks,
     };
+    // Protocol upload chunks are numbered from 0, while the blob store numbers chunks from
+    // 1 in both directions
     ctx.objectstore()
-        .upload_chunk(&xfer, chunk.sequence_no, chunk.chunk_bytes.as_ref())?;
+        .upload_chunk(&xfer, chunk.sequence_no + 1, chunk.chunk_bytes.as_ref())?;
     let ack = protocol::stream::ChunkAck {
         bytes_sent: chunk.chunk_bytes.len() as u64,
         sequence_no: chunk.sequence_no,
         success: true
     };
-    let buf = serialize(&ack)?;    
+    let buf = protocol::encode(ct, &ack)?;    
     ctx.msg().publish(reply_to, None, &buf)?;
+    // Chunks may arrive in any order, so the upload is complete once all of them have been
+    // stored
+    let mut uploads = UPLOADS.lock().unwrap();
+    let completed = match uploads.get_mut(&chunk.actor) {
+        Some(upload) => {
+            upload.stored.insert(chunk.sequence_no);
+            if upload.stored.len() as u64 >= upload.total_chunks {
+                uploads.remove(&chunk.actor).map(|upload| upload.revision)
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+    drop(uploads);
+    if let Some(revision) = completed {
+        audit_upload(ctx, &chunk.actor, None);
+        // The upload itself has succeeded, so failures to record and announce it are
+        // only logged
+        if let Err(e) = start_digest(ctx, &chunk.actor, revision, chunk.total_bytes) {
+            ctx.log(&format!("Failed to record module upload: {}", e));
+        }
+        if let Err(e) = publish_uploaded(ctx, &chunk, revision, ct) {
+            ctx.log(&format!("Failed to publish module upload event: {}", e));
+        }
+    }
     Ok(vec![])
 }
 
-fn handle_upload(ctx: &CapabilitiesContext, req: UploadRequest, reply_to: &str) -> ReceiveResult {
-    let filename = format!("{}.wasm", req.actor);
+/// Reads a completed upload back from the blob store to compute the digest of the module
+/// as stored, which is recorded by [`digest_chunk`] once every chunk has been hashed
+fn start_digest(
+    ctx: &CapabilitiesContext,
+    actor: &str,
+    revision: Option<u64>,
+    total_bytes: u64,
+) -> ::std::result::Result<(), Box<dyn ::std::error::Error>> {
+    let blob_chunks = total_chunks(total_bytes, CHUNK_SIZE);
+    if blob_chunks == 0 {
+        let digest = hex(&sha2::Digest::result(Sha256::default()));
+        return record_upload(ctx, actor, revision, total_bytes, Some(digest));
+    }
+    DIGESTS.lock().unwrap().insert(
+        actor.to_string(),
+        PendingDigest {
+            revision,
+            total_bytes,
+            blob_chunks,
+            next: 1,
+            ahead: BTreeMap::new(),
+            hasher: Sha256::default(),
+        },
+    );
+    let blob = blobstore::Blob {
+        id: format!("{}.wasm", actor),
+        container: "gantry".to_string(),
+        byte_size: total_bytes,
+    };
+    if let Err(e) = ctx.objectstore().start_download(&blob, CHUNK_SIZE) {
+        DIGESTS.lock().unwrap().remove(actor);
+        ctx.log(&format!("Failed to read back module {}: {}", actor, e));
+        return record_upload(ctx, actor, revision, total_bytes, None);
+    }
+    Ok(())
+}
 
-    let actors = catalog_get_actors(ctx)?;
-    if !actors.contains(&req.actor) {
-        return Err("Module is not registered in catalog".into());
+/// Records a completed upload with the catalog, against the revision of the actor's token
+/// for which it was authorized. Without a revision, the catalog attributes it to the
+/// latest one
+fn record_upload(
+    ctx: &CapabilitiesContext,
+    actor: &str,
+    revision: Option<u64>,
+    total_bytes: u64,
+    digest: Option<String>,
+) -> ::std::result::Result<(), Box<dyn ::std::error::Error>> {
+    let info = ModuleInfo {
+        actor: actor.to_string(),
+        exists: true,
+        total_bytes,
+        digest,
+        revision,
+        ..Default::default()
+    };
+    call_catalog(ctx, SUBJECT_MODULE_RECORD, protocol::serialize(&info)?)?;
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Describes the module stored for an actor, combining the blob store's record of its size
+/// with the catalog's record of its upload
+fn handle_info(
+    ctx: &CapabilitiesContext,
+    req: ModuleInfoRequest,
+    ct: ContentType,
+    reply_to: &str,
+) -> ReceiveResult {
+    let blob_id = format!("{}.wasm", req.actor);
+    let mut info = match ctx.objectstore().get_blob_info("gantry", &blob_id)? {
+        Some(blob) => {
+            let lookup = ModuleInfoRequest {
+                actor: req.actor.to_string(),
+                revision: None,
+            };
+            let res = call_catalog(ctx, SUBJECT_MODULE_LOOKUP, protocol::serialize(&lookup)?)?;
+            ModuleInfo {
+                exists: true,
+                total_bytes: blob.byte_size,
+                ..protocol::deserialize::<ModuleInfo>(res.as_ref())?
+            }
+        }
+        None => ModuleInfo {
+            actor: req.actor.to_string(),
+            ..Default::default()
+        },
+    };
+    if let Some(revision) = req.revision {
+        info.exists = info.exists && info.revision == Some(revision);
+    }
+    let buf = protocol::encode(ct, &info)?;
+    ctx.msg().publish(reply_to, None, &buf)?;
+    Ok(vec![])
+}
+
+/// Announces that the final chunk of a module has been stored, encoded in the content type
+/// of the chunk
+fn publish_uploaded(
+    ctx: &CapabilitiesContext,
+    chunk: &protocol::stream::FileChunk,
+    revision: Option<u64>,
+    ct: ContentType,
+) -> ::std::result::Result<(), Box<dyn ::std::error::Error>> {
+    let event = CatalogEvent {
+        kind: EventKind::ModuleUploaded,
+        subject: chunk.actor.to_string(),
+        issuer: None,
+        revision,
+        operator: None,
+        total_bytes: Some(chunk.total_bytes),
+    };
+    let buf = protocol::encode(ct, &event)?;
+    ctx.msg().publish(event.kind.subject(), None, &buf)?;
+    Ok(())
+}
+
+/// Reports the outcome of an upload to the catalog's audit log. Uploads are recorded when
+/// they are refused, when any chunk fails, and when the final chunk has been stored
+fn audit_upload(ctx: &CapabilitiesContext, actor: &str, error: Option<String>) {
+    let record = AuditRecord {
+        sequence: 0,
+        timestamp: 0,
+        operation: AuditOperation::UploadModule,
+        subject: actor.to_string(),
+        revision: None,
+        caller: None,
+        success: error.is_none(),
+        error,
+    };
+    if let Err(e) = send_audit_record(ctx, &record) {
+        ctx.log(&format!("Failed to write audit record {:?}: {}", record, e));
     }
+}
+
+fn send_audit_record(
+    ctx: &CapabilitiesContext,
+    record: &AuditRecord,
+) -> ::std::result::Result<(), Box<dyn ::std::error::Error>> {
+    call_catalog(ctx, protocol::audit::SUBJECT_AUDIT_APPEND, protocol::serialize(record)?)?;
+    Ok(())
+}
+
+/// Delivers a message directly to the catalog actor, returning its reply
+fn call_catalog(
+    ctx: &CapabilitiesContext,
+    subject: &str,
+    body: Vec<u8>,
+) -> ::std::result::Result<Vec<u8>, Box<dyn ::std::error::Error>> {
+    let msg = messaging::DeliverMessage {
+        message: messaging::BrokerMessage {
+            reply_to: "".to_string(),
+            subject: subject.to_string(),
+            body,
+        },
+    };
+    let catalog = CATALOG_ACTOR
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or("The catalog actor has not been configured")?;
+    Ok(ctx
+        .raw()
+        .call(&catalog, messaging::OP_DELIVER_MESSAGE, &serialize(&msg)?)?)
+}
+
+fn handle_upload(
+    ctx: &CapabilitiesContext,
+    req: UploadRequest,
+    ct: ContentType,
+    reply_to: &str,
+) -> ReceiveResult {
+    let filename = format!("{}.wasm", req.actor);
+
+    let status = authorize(ctx, &req.actor, None)?;
+    check_chunk_size(req.chunk_size)?;
     let blob = blobstore::Blob {
         id: filename.to_string(),
         container: "gantry".to_string(),
@@ -120,50 +575,122 @@ fn handle_upload(ctx: &CapabilitiesContext, req: UploadRequest, reply_to: &str)
     };
     let ack = TransferAck {
         success: true,
+        compression: negotiate_compression(&req.compression, &COMPRESSION_CODECS),
         actor: req.actor,
         total_bytes: blob.byte_size,
         chunk_size: CHUNK_SIZE,
-        total_chunks: blob.byte_size / CHUNK_SIZE,
+        total_chunks: total_chunks(blob.byte_size, CHUNK_SIZE),
     };    
 
-    let buf = serialize(&ack)?;    
+    UPLOADS.lock().unwrap().insert(
+        ack.actor.to_string(),
+        Upload {
+            revision: status.latest_revision,
+            total_chunks: total_chunks(req.total_bytes, req.chunk_size),
+            stored: BTreeSet::new(),
+        },
+    );
+    let buf = protocol::encode(ct, &ack)?;    
     ctx.msg().publish(reply_to, None, &buf)?;
     ctx.objectstore()
         .start_upload(&blob, req.chunk_size, req.total_bytes)?;
     Ok(vec![])
 }
 
-fn total_chunks(bytes: u64, chunk_size: u64) -> u64 {
-    let mut c = bytes / chunk_size;
-    if bytes % CHUNK_SIZE != 0 {
-        c = c+ 1;
+/// Rejects the chunk size of an upload unless it is between 1 byte and the size of the
+/// chunks the blob store is read back in
+fn check_chunk_size(chunk_size: u64) -> ::std::result::Result<(), Box<dyn ::std::error::Error>> {
+    if chunk_size == 0 || chunk_size > CHUNK_SIZE {
+        return Err(format!(
+            "Chunk size {} is not between 1 and {} bytes",
+            chunk_size, CHUNK_SIZE
+        )
+        .into());
     }
-    c
+    Ok(())
+}
+
+/// The number of chunks of the given size needed to hold the given number of bytes. The
+/// chunk size must not be zero
+fn total_chunks(bytes: u64, chunk_size: u64) -> u64 {
+    bytes.saturating_add(chunk_size - 1) / chunk_size
 }
 
 fn handle_download(
     ctx: &CapabilitiesContext,
     req: DownloadRequest,
+    ct: ContentType,
     reply_to: &str,
 ) -> ReceiveResult {
     let blob_id = format!("{}.wasm", req.actor);
-    let actors = catalog_get_actors(ctx)?;
-    if !actors.contains(&req.actor) {
-        return Err("Module is not registered in catalog".into());
-    }
+    let transfer = transfer_of(&req)?;
+    authorize(ctx, &req.actor, req.operator.as_deref())?;
     let blobinfo = ctx.objectstore().get_blob_info("gantry", &blob_id)?;
     ctx.log(&format!("Retrieve blob info: {:?}", blobinfo));
     if let Some(blobinfo) = blobinfo {
+        let size = blobinfo.byte_size;
+        if req.offset > size {
+            return Err(format!(
+                "Range starts at byte {} beyond the end of the {} byte module",
+                req.offset, size
+            )
+            .into());
+        }
+        let end = match req.length {
+            0 => size,
+            length => size.min(req.offset.saturating_add(length)),
+        };
+        let last_chunk = if req.offset == end {
+            0
+        } else {
+            total_chunks(end, CHUNK_SIZE)
+        };
         let ack = TransferAck {
             success: true,
             actor: req.actor.to_string(),
-            total_bytes: blobinfo.byte_size,
+            total_bytes: size,
             chunk_size: CHUNK_SIZE,
-            total_chunks: total_chunks(blobinfo.byte_size, CHUNK_SIZE),
+            total_chunks: last_chunk,
+            compression: negotiate_compression(&req.compression, &COMPRESSION_CODECS),
         };
 
-        let buf = serialize(ack)?;        
+        let buf = protocol::encode(ct, &ack)?;        
         ctx.msg().publish(reply_to, None, &buf)?;
+        // An empty range has no chunks to send
+        if last_chunk == 0 {
+            return Ok(vec![]);
+        }
+        let now = ACTIVITY.fetch_add(1, Ordering::SeqCst);
+        let mut downloads = DOWNLOADS.lock().unwrap();
+        if downloads.len() >= MAX_DOWNLOADS && !downloads.contains_key(&transfer) {
+            let idle = downloads
+                .iter()
+                .min_by_key(|(_, d)| d.active)
+                .map(|(t, _)| t.to_string());
+            if let Some(idle) = idle {
+                ctx.log(&format!("Abandoning idle download {}", idle));
+                downloads.remove(&idle);
+            }
+        }
+        downloads.insert(
+            transfer,
+            Download {
+                actor: req.actor.to_string(),
+                content_type: ct,
+                compression: ack.compression.clone(),
+                window: req.window,
+                start: req.offset,
+                end,
+                last_chunk,
+                blob_chunks: total_chunks(size, CHUNK_SIZE),
+                received: BTreeSet::new(),
+                outstanding: 0,
+                pending: VecDeque::new(),
+                started: now,
+                active: now,
+            },
+        );
+        drop(downloads);
         ctx.objectstore().start_download(&blobinfo, CHUNK_SIZE)?;
         Ok(vec![])
     } else {
@@ -171,36 +698,49 @@ fn handle_download(
     }
 }
 
-const CATALOG_ACTOR: &str = "MCIXJVXAXKDX7UFYDFW2737SHVIRNZILS3ULODGEQOVCTWQ7HSGOHUY7";
-
-fn catalog_get_actors(
-    ctx: &CapabilitiesContext,
-) -> ::std::result::Result<Vec<String>, Box<dyn ::std::error::Error>> {
-    let results = ctx.raw().call(
-        CATALOG_ACTOR,
-        messaging::OP_DELIVER_MESSAGE,
-        &gen_actor_query(),
-    )?;
-    let query_res = deserialize::<protocol::catalog::CatalogQueryResults>(results.as_ref())?;
-    Ok(query_res
-        .results
-        .iter()
-        .map(|r| r.subject.clone())
-        .collect())
+/// The subject token naming a download, which defaults to its actor's public key
+fn transfer_of(req: &DownloadRequest) -> ::std::result::Result<String, Box<dyn ::std::error::Error>> {
+    let transfer = if req.transfer.is_empty() {
+        &req.actor
+    } else {
+        &req.transfer
+    };
+    if transfer
+        .chars()
+        .any(|c| c == '.' || c == '*' || c == '>' || c.is_whitespace())
+    {
+        return Err(format!("'{}' cannot name a transfer", transfer).into());
+    }
+    Ok(transfer.to_string())
 }
 
-fn gen_actor_query() -> Vec<u8> {    
-    let q = protocol::catalog::CatalogQuery {
-        issuer: None,
-        query_type: protocol::catalog::QueryType::Actor,
-    };
-    let buf = serialize(&q).unwrap();    
-    let msg = messaging::DeliverMessage {
-        message: messaging::BrokerMessage {
-            reply_to: "".to_string(),
-            subject: protocol::catalog::SUBJECT_CATALOG_QUERY.to_string(),
-            body: buf,
-        },
+/// Refuses to transfer a module unless the catalog reports that its actor is registered
+/// and has not been revoked and, if an operator is given, that it belongs to that
+/// operator's trust chain. Returns the status on which the transfer was authorized
+fn authorize(
+    ctx: &CapabilitiesContext,
+    actor: &str,
+    operator: Option<&str>,
+) -> ::std::result::Result<SubjectStatus, Box<dyn ::std::error::Error>> {
+    if !actor.starts_with('M') {
+        return Err(format!("{} is not an actor", actor).into());
+    }
+    let req = SubjectStatusRequest {
+        subject: actor.to_string(),
+        revision: None,
     };
-    serialize(&msg).unwrap()    
+    let res = call_catalog(ctx, SUBJECT_CATALOG_STATUS, protocol::serialize(&req)?)?;
+    let status = protocol::deserialize::<SubjectStatus>(res.as_ref())?;
+    if status.revoked {
+        return Err(format!("Module {} has been revoked", actor).into());
+    }
+    if !status.registered {
+        return Err("Module is not registered in catalog".into());
+    }
+    match operator {
+        Some(op) if status.operator.as_deref() != Some(op) => {
+            Err(format!("Module is not registered in catalog under operator {}", op).into())
+        }
+        _ => Ok(status),
+    }
 }